@@ -75,7 +75,7 @@ EOF
 TIMESTAMP=$(date +%s)
 
 # Generate signature
-MESSAGE="${TIMESTAMP}.${BODY}"
+MESSAGE="${TIMESTAMP}.POST./config.${BODY}"
 SIGNATURE=$(echo -n "${MESSAGE}" | openssl dgst -sha256 -hmac "${API_KEY}" | awk '{print $2}')
 
 echo "Endpoint: ${WEBHOOK_URL}/config"
@@ -86,6 +86,7 @@ RESPONSE=$(curl -s -w "\n%{http_code}" -X POST "${WEBHOOK_URL}/config" \
   -H "Content-Type: application/json" \
   -H "X-Timestamp: ${TIMESTAMP}" \
   -H "X-Signature: sha256=${SIGNATURE}" \
+  -H "X-Signature-Version: 2" \
   -d "${BODY}")
 
 # Extract HTTP status code
@@ -0,0 +1,2448 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, FixedOffset};
+use regex::Regex;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::filter::WebhookEvent;
+
+/// Maximum nesting depth of an expression, so hostile specs cannot exhaust the
+/// stack. Chained operators, selections and calls each count as a level.
+const MAX_DEPTH: usize = 64;
+
+/// A Common Expression Language (CEL) expression, parsed and type-checked
+/// against the webhook variables `body`, `headers`, `query`, `method` and
+/// `received_at`.
+///
+/// This is a subset of CEL: literals, lists, field selection and indexing,
+/// arithmetic, comparisons, `in`, `&&`, `||`, `!`, `?:`, `has()`, `size`,
+/// `int`/`double`/`string`/`dyn`/`timestamp`/`duration` conversions, the
+/// string methods `startsWith`, `endsWith`, `contains`, `matches`,
+/// `lowerAscii`, `upperAscii`, and the `all`, `exists`, `exists_one`,
+/// `filter` and `map` macros. Map literals are not supported.
+#[derive(Debug)]
+pub struct Program {
+    source: String,
+    expr: Expr,
+    result_type: Type,
+}
+
+/// Static type of an expression; `Dyn` is only known at runtime
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Dyn,
+    Null,
+    Bool,
+    Int,
+    Double,
+    String,
+    List(Box<Type>),
+    Map(Box<Type>),
+    Timestamp,
+    Duration,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Dyn => f.write_str("dyn"),
+            Type::Null => f.write_str("null_type"),
+            Type::Bool => f.write_str("bool"),
+            Type::Int => f.write_str("int"),
+            Type::Double => f.write_str("double"),
+            Type::String => f.write_str("string"),
+            Type::List(elem) => write!(f, "list({})", elem),
+            Type::Map(elem) => write!(f, "map(string, {})", elem),
+            Type::Timestamp => f.write_str("timestamp"),
+            Type::Duration => f.write_str("duration"),
+        }
+    }
+}
+
+impl Program {
+    /// Parses and type-checks an expression
+    pub fn compile(source: &str) -> Result<Program> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let mut expr = parser.expr()?;
+        if parser.peek() != &Token::End {
+            return Err(anyhow!(
+                "Syntax error at position {}: unexpected {}",
+                parser.offset(),
+                parser.peek()
+            ));
+        }
+
+        let mut scope = vec![
+            ("body".to_string(), Type::Dyn),
+            ("headers".to_string(), Type::Map(Box::new(Type::String))),
+            ("query".to_string(), Type::Map(Box::new(Type::String))),
+            ("method".to_string(), Type::String),
+            ("received_at".to_string(), Type::Timestamp),
+        ];
+        let result_type = check(&mut expr, &mut scope)?;
+
+        Ok(Program {
+            source: source.to_string(),
+            expr,
+            result_type,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn result_type(&self) -> &Type {
+        &self.result_type
+    }
+
+    /// Evaluates an expression that should produce a bool
+    pub fn evaluate_bool(&self, event: &WebhookEvent) -> Result<bool, String> {
+        match self.evaluate(event)? {
+            Val::Bool(b) => Ok(b),
+            other => Err(format!("expected bool, got {}", other.type_name())),
+        }
+    }
+
+    /// Evaluates an expression to the scalar it is matched against route
+    /// mappings as
+    pub fn evaluate_scalar(&self, event: &WebhookEvent) -> Result<Value, String> {
+        match self.evaluate(event)? {
+            Val::Null => Ok(Value::Null),
+            Val::Str(s) => Ok(Value::String(s.into_owned())),
+            Val::Int(n) => Ok(Value::from(n)),
+            Val::Bool(b) => Ok(Value::Bool(b)),
+            Val::Double(n) => serde_json::Number::from_f64(n)
+                .map(Value::Number)
+                .ok_or_else(|| format!("{} is not a finite number", n)),
+            other => Err(format!(
+                "expected string, number or bool, got {}",
+                other.type_name()
+            )),
+        }
+    }
+
+    fn evaluate<'a>(&'a self, event: &'a WebhookEvent<'a>) -> Result<Val<'a>, String> {
+        let mut scope = Scope {
+            event,
+            locals: Vec::new(),
+        };
+        eval(&self.expr, &mut scope)
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Lexer
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Int(i64),
+    Double(f64),
+    Str(String),
+    Ident(String),
+    Punct(&'static str),
+    End,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Int(n) => write!(f, "{}", n),
+            Token::Double(n) => write!(f, "{}", n),
+            Token::Str(s) => write!(f, "{:?}", s),
+            Token::Ident(s) => write!(f, "'{}'", s),
+            Token::Punct(p) => write!(f, "'{}'", p),
+            Token::End => f.write_str("end of expression"),
+        }
+    }
+}
+
+const PUNCTUATION: &[&str] = &[
+    "&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+", "-", "*", "/", "%", "?", ":", ".", ",",
+    "(", ")", "[", "]", "{", "}",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(usize, Token)>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+
+        if c.is_whitespace() {
+            i += 1;
+            continue;
+        }
+
+        let raw = matches!(c, 'r' | 'R') && matches!(chars.get(i + 1), Some('\'' | '"'));
+        if raw || c == '\'' || c == '"' {
+            if raw {
+                i += 1;
+            }
+            let (value, end) = lex_string(&chars, i, raw)?;
+            tokens.push((start, Token::Str(value)));
+            i = end;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let (token, end) = lex_number(&chars, i)?;
+            tokens.push((start, token));
+            i = end;
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Ident(chars[start..i].iter().collect())));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let punct = PUNCTUATION
+                .iter()
+                .find(|p| rest.starts_with(**p))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Syntax error at position {}: unexpected character {:?}",
+                        i,
+                        c
+                    )
+                })?;
+            tokens.push((start, Token::Punct(punct)));
+            i += punct.len();
+        }
+    }
+
+    tokens.push((chars.len(), Token::End));
+    Ok(tokens)
+}
+
+fn lex_string(chars: &[char], start: usize, raw: bool) -> Result<(String, usize)> {
+    let quote = chars[start];
+    let mut value = String::new();
+    let mut i = start + 1;
+
+    loop {
+        let c = *chars
+            .get(i)
+            .ok_or_else(|| anyhow!("Syntax error at position {}: unterminated string", start))?;
+        i += 1;
+        match c {
+            c if c == quote => return Ok((value, i)),
+            '\\' if !raw => {
+                let escaped = *chars.get(i).ok_or_else(|| {
+                    anyhow!("Syntax error at position {}: unterminated string", start)
+                })?;
+                i += 1;
+                match escaped {
+                    'n' => value.push('\n'),
+                    'r' => value.push('\r'),
+                    't' => value.push('\t'),
+                    '\\' | '\'' | '"' | '`' | '?' => value.push(escaped),
+                    'u' => {
+                        let hex: String = chars.get(i..i + 4).unwrap_or_default().iter().collect();
+                        let code = u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| {
+                                anyhow!("Syntax error at position {}: invalid \\u escape", i - 2)
+                            })?;
+                        value.push(code);
+                        i += 4;
+                    }
+                    other => {
+                        return Err(anyhow!(
+                            "Syntax error at position {}: invalid escape \\{}",
+                            i - 2,
+                            other
+                        ));
+                    }
+                }
+            }
+            c => value.push(c),
+        }
+    }
+}
+
+fn lex_number(chars: &[char], start: usize) -> Result<(Token, usize)> {
+    let mut i = start;
+    let invalid = |i: usize| anyhow!("Syntax error at position {}: invalid number", i);
+
+    if chars[i] == '0' && matches!(chars.get(i + 1), Some('x' | 'X')) {
+        i += 2;
+        let digits_start = i;
+        while i < chars.len() && chars[i].is_ascii_hexdigit() {
+            i += 1;
+        }
+        let digits: String = chars[digits_start..i].iter().collect();
+        let n = i64::from_str_radix(&digits, 16).map_err(|_| invalid(start))?;
+        if chars.get(i) == Some(&'u') {
+            i += 1;
+        }
+        return Ok((Token::Int(n), i));
+    }
+
+    let mut is_double = false;
+    while i < chars.len() && chars[i].is_ascii_digit() {
+        i += 1;
+    }
+    if chars.get(i) == Some(&'.') && chars.get(i + 1).is_some_and(char::is_ascii_digit) {
+        is_double = true;
+        i += 1;
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+    if matches!(chars.get(i), Some('e' | 'E')) {
+        is_double = true;
+        i += 1;
+        if matches!(chars.get(i), Some('+' | '-')) {
+            i += 1;
+        }
+        while i < chars.len() && chars[i].is_ascii_digit() {
+            i += 1;
+        }
+    }
+
+    let text: String = chars[start..i].iter().collect();
+    if is_double {
+        let n = text.parse().map_err(|_| invalid(start))?;
+        Ok((Token::Double(n), i))
+    } else {
+        let n = text.parse().map_err(|_| invalid(start))?;
+        if chars.get(i) == Some(&'u') {
+            i += 1;
+        }
+        Ok((Token::Int(n), i))
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Parser
+
+#[derive(Debug)]
+enum Expr {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Str(String),
+    Ident(String),
+    List(Vec<Expr>),
+    Select(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    Has(Box<Expr>, String),
+    Call {
+        target: Option<Box<Expr>>,
+        function: String,
+        args: Vec<Expr>,
+        /// Precompiled pattern for `matches` with a literal argument
+        regex: Option<Regex>,
+    },
+    Comprehension {
+        kind: Macro,
+        range: Box<Expr>,
+        var: String,
+        body: Box<Expr>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+impl BinaryOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "_+_",
+            BinaryOp::Sub => "_-_",
+            BinaryOp::Mul => "_*_",
+            BinaryOp::Div => "_/_",
+            BinaryOp::Rem => "_%_",
+            BinaryOp::Eq => "_==_",
+            BinaryOp::Ne => "_!=_",
+            BinaryOp::Lt => "_<_",
+            BinaryOp::Le => "_<=_",
+            BinaryOp::Gt => "_>_",
+            BinaryOp::Ge => "_>=_",
+            BinaryOp::In => "@in",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Macro {
+    All,
+    Exists,
+    ExistsOne,
+    Filter,
+    Map,
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        if token != Token::End {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn eat(&mut self, punct: &str) -> bool {
+        if matches!(self.peek(), Token::Punct(p) if *p == punct) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<()> {
+        if self.eat(punct) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("expected '{}'", punct)))
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!(
+            "Syntax error at position {}: {}, found {}",
+            self.offset(),
+            message,
+            self.peek()
+        )
+    }
+
+    /// Adds a level of nesting; callers restore `depth` once the level is built
+    fn enter(&mut self) -> Result<()> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(anyhow!("Expression is nested too deeply"));
+        }
+        Ok(())
+    }
+
+    fn expr(&mut self) -> Result<Expr> {
+        self.enter()?;
+        let cond = self.or()?;
+        let expr = if self.eat("?") {
+            let then = self.or()?;
+            self.expect(":")?;
+            let otherwise = self.expr()?;
+            Expr::Cond(Box::new(cond), Box::new(then), Box::new(otherwise))
+        } else {
+            cond
+        };
+        self.depth -= 1;
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.and()?;
+        while self.eat("||") {
+            self.enter()?;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.relation()?;
+        while self.eat("&&") {
+            self.enter()?;
+            left = Expr::And(Box::new(left), Box::new(self.relation()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn relation(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.addition()?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct("==") => BinaryOp::Eq,
+                Token::Punct("!=") => BinaryOp::Ne,
+                Token::Punct("<") => BinaryOp::Lt,
+                Token::Punct("<=") => BinaryOp::Le,
+                Token::Punct(">") => BinaryOp::Gt,
+                Token::Punct(">=") => BinaryOp::Ge,
+                Token::Ident(name) if name == "in" => BinaryOp::In,
+                _ => break,
+            };
+            self.next();
+            self.enter()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.addition()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn addition(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.multiplication()?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct("+") => BinaryOp::Add,
+                Token::Punct("-") => BinaryOp::Sub,
+                _ => break,
+            };
+            self.next();
+            self.enter()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.multiplication()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn multiplication(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut left = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct("*") => BinaryOp::Mul,
+                Token::Punct("/") => BinaryOp::Div,
+                Token::Punct("%") => BinaryOp::Rem,
+                _ => break,
+            };
+            self.next();
+            self.enter()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+        self.depth = depth;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let expr = if self.eat("!") {
+            self.enter()?;
+            Expr::Not(Box::new(self.unary()?))
+        } else if self.eat("-") {
+            self.enter()?;
+            match self.unary()? {
+                Expr::Int(n) => Expr::Int(-n),
+                Expr::Double(n) => Expr::Double(-n),
+                operand => Expr::Neg(Box::new(operand)),
+            }
+        } else {
+            self.member()?
+        };
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn member(&mut self) -> Result<Expr> {
+        let depth = self.depth;
+        let mut expr = self.primary()?;
+        loop {
+            if self.eat(".") {
+                self.enter()?;
+                let name = match self.next() {
+                    Token::Ident(name) => name,
+                    _ => return Err(self.error("expected a field or method name")),
+                };
+                expr = if self.eat("(") {
+                    let args = self.args(")")?;
+                    method_call(expr, name, args)?
+                } else {
+                    Expr::Select(Box::new(expr), name)
+                };
+            } else if self.eat("[") {
+                self.enter()?;
+                let index = self.expr()?;
+                self.expect("]")?;
+                expr = Expr::Index(Box::new(expr), Box::new(index));
+            } else {
+                self.depth = depth;
+                return Ok(expr);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Token::Int(n) => Ok(Expr::Int(n)),
+            Token::Double(n) => Ok(Expr::Double(n)),
+            Token::Str(s) => Ok(Expr::Str(s)),
+            Token::Ident(name) => match name.as_str() {
+                "true" => Ok(Expr::Bool(true)),
+                "false" => Ok(Expr::Bool(false)),
+                "null" => Ok(Expr::Null),
+                "in" => Err(anyhow!("Syntax error: reserved word 'in'")),
+                _ if self.eat("(") => {
+                    let mut args = self.args(")")?;
+                    if name == "has" {
+                        match (args.pop(), args.is_empty()) {
+                            (Some(Expr::Select(operand, field)), true) => {
+                                Ok(Expr::Has(operand, field))
+                            }
+                            _ => Err(anyhow!(
+                                "has() requires a single field selection, e.g. has(body.field)"
+                            )),
+                        }
+                    } else {
+                        Ok(Expr::Call {
+                            target: None,
+                            function: name,
+                            args,
+                            regex: None,
+                        })
+                    }
+                }
+                _ => Ok(Expr::Ident(name)),
+            },
+            Token::Punct("(") => {
+                let expr = self.expr()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Punct("[") => Ok(Expr::List(self.args("]")?)),
+            Token::Punct("{") => Err(anyhow!("Map literals are not supported")),
+            _ => {
+                self.pos = self.pos.saturating_sub(1);
+                Err(self.error("expected an expression"))
+            }
+        }
+    }
+
+    /// Comma-separated expressions up to the closing delimiter
+    fn args(&mut self, close: &str) -> Result<Vec<Expr>> {
+        let mut args = Vec::new();
+        while !self.eat(close) {
+            args.push(self.expr()?);
+            if !self.eat(",") {
+                self.expect(close)?;
+                break;
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn method_call(target: Expr, function: String, mut args: Vec<Expr>) -> Result<Expr> {
+    let kind = match function.as_str() {
+        "all" => Some(Macro::All),
+        "exists" => Some(Macro::Exists),
+        "exists_one" => Some(Macro::ExistsOne),
+        "filter" => Some(Macro::Filter),
+        "map" => Some(Macro::Map),
+        _ => None,
+    };
+
+    match kind {
+        Some(kind) => {
+            let body = args.pop();
+            match (args.pop(), body, args.is_empty()) {
+                (Some(Expr::Ident(var)), Some(body), true) => Ok(Expr::Comprehension {
+                    kind,
+                    range: Box::new(target),
+                    var,
+                    body: Box::new(body),
+                }),
+                _ => Err(anyhow!(
+                    "{}() requires a variable and an expression, e.g. {}(x, x > 0)",
+                    function,
+                    function
+                )),
+            }
+        }
+        None => Ok(Expr::Call {
+            target: Some(Box::new(target)),
+            function,
+            args,
+            regex: None,
+        }),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Type checker
+
+fn check(expr: &mut Expr, scope: &mut Vec<(String, Type)>) -> Result<Type> {
+    Ok(match expr {
+        Expr::Null => Type::Null,
+        Expr::Bool(_) => Type::Bool,
+        Expr::Int(_) => Type::Int,
+        Expr::Double(_) => Type::Double,
+        Expr::Str(_) => Type::String,
+        Expr::Ident(name) => scope
+            .iter()
+            .rev()
+            .find(|(declared, _)| declared == name)
+            .map(|(_, type_)| type_.clone())
+            .ok_or_else(|| anyhow!("undeclared reference to '{}'", name))?,
+        Expr::List(items) => {
+            let mut elem: Option<Type> = None;
+            for item in items {
+                let type_ = check(item, scope)?;
+                elem = Some(match elem {
+                    Some(elem) if elem != type_ => Type::Dyn,
+                    _ => type_,
+                });
+            }
+            Type::List(Box::new(elem.unwrap_or(Type::Dyn)))
+        }
+        Expr::Select(operand, field) => match check(operand, scope)? {
+            Type::Map(elem) => *elem,
+            Type::Dyn => Type::Dyn,
+            other => {
+                return Err(anyhow!(
+                    "type '{}' does not support field selection ('.{}')",
+                    other,
+                    field
+                ))
+            }
+        },
+        Expr::Has(operand, field) => match check(operand, scope)? {
+            Type::Map(_) | Type::Dyn => Type::Bool,
+            other => {
+                return Err(anyhow!(
+                    "type '{}' does not support field selection ('.{}')",
+                    other,
+                    field
+                ))
+            }
+        },
+        Expr::Index(operand, index) => {
+            let operand = check(operand, scope)?;
+            let index = check(index, scope)?;
+            match (operand, index) {
+                (Type::List(elem), Type::Int | Type::Dyn) => *elem,
+                (Type::Map(elem), Type::String | Type::Dyn) => *elem,
+                (Type::Dyn, _) => Type::Dyn,
+                (operand, index) => {
+                    return Err(anyhow!(
+                        "no matching overload for '_[_]' applied to ({}, {})",
+                        operand,
+                        index
+                    ))
+                }
+            }
+        }
+        Expr::Not(operand) => match check(operand, scope)? {
+            Type::Bool | Type::Dyn => Type::Bool,
+            other => {
+                return Err(anyhow!(
+                    "no matching overload for '!_' applied to ({})",
+                    other
+                ))
+            }
+        },
+        Expr::Neg(operand) => match check(operand, scope)? {
+            type_ @ (Type::Int | Type::Double | Type::Duration | Type::Dyn) => type_,
+            other => {
+                return Err(anyhow!(
+                    "no matching overload for '-_' applied to ({})",
+                    other
+                ))
+            }
+        },
+        Expr::And(left, right) | Expr::Or(left, right) => {
+            for operand in [left, right] {
+                let type_ = check(operand, scope)?;
+                if !matches!(type_, Type::Bool | Type::Dyn) {
+                    return Err(anyhow!(
+                        "logical operators require bool operands, got {}",
+                        type_
+                    ));
+                }
+            }
+            Type::Bool
+        }
+        Expr::Cond(cond, then, otherwise) => {
+            let cond = check(cond, scope)?;
+            if !matches!(cond, Type::Bool | Type::Dyn) {
+                return Err(anyhow!(
+                    "conditional requires a bool condition, got {}",
+                    cond
+                ));
+            }
+            let then = check(then, scope)?;
+            let otherwise = check(otherwise, scope)?;
+            if then == otherwise {
+                then
+            } else {
+                Type::Dyn
+            }
+        }
+        Expr::Binary(op, left, right) => {
+            let op = *op;
+            let left = check(left, scope)?;
+            let right = check(right, scope)?;
+            check_binary(op, &left, &right).ok_or_else(|| {
+                anyhow!(
+                    "no matching overload for '{}' applied to ({}, {})",
+                    op.symbol(),
+                    left,
+                    right
+                )
+            })?
+        }
+        Expr::Call {
+            target,
+            function,
+            args,
+            regex,
+        } => {
+            let target = match target {
+                Some(target) => Some(check(target, scope)?),
+                None => None,
+            };
+            let mut arg_types = Vec::with_capacity(args.len());
+            for arg in args.iter_mut() {
+                arg_types.push(check(arg, scope)?);
+            }
+            if let (Some(_), "matches", [Expr::Str(pattern)]) =
+                (&target, function.as_str(), args.as_slice())
+            {
+                *regex = Some(
+                    Regex::new(pattern)
+                        .map_err(|e| anyhow!("Invalid regex {:?}: {}", pattern, e))?,
+                );
+            }
+            if let (None, "timestamp" | "duration", [Expr::Str(literal)]) =
+                (&target, function.as_str(), args.as_slice())
+            {
+                convert(function, Val::Str(Cow::Borrowed(literal))).map_err(|e| anyhow!(e))?;
+            }
+            check_call(target.as_ref(), function, &arg_types)?
+        }
+        Expr::Comprehension {
+            kind,
+            range,
+            var,
+            body,
+        } => {
+            let range = check(range, scope)?;
+            let elem = match &range {
+                Type::List(elem) => (**elem).clone(),
+                Type::Map(_) => Type::String,
+                Type::Dyn => Type::Dyn,
+                other => return Err(anyhow!("type '{}' cannot be iterated", other)),
+            };
+            scope.push((var.clone(), elem.clone()));
+            let body = check(body, scope);
+            scope.pop();
+            let body = body?;
+
+            match kind {
+                Macro::Map => Type::List(Box::new(body)),
+                _ if !matches!(body, Type::Bool | Type::Dyn) => {
+                    return Err(anyhow!("macro predicate must be bool, got {}", body));
+                }
+                Macro::Filter => Type::List(Box::new(elem)),
+                Macro::All | Macro::Exists | Macro::ExistsOne => Type::Bool,
+            }
+        }
+    })
+}
+
+fn is_number(type_: &Type) -> bool {
+    matches!(type_, Type::Int | Type::Double)
+}
+
+fn check_binary(op: BinaryOp, left: &Type, right: &Type) -> Option<Type> {
+    use Type::*;
+
+    match op {
+        BinaryOp::Eq | BinaryOp::Ne => {
+            let comparable = matches!(left, Dyn | Null)
+                || matches!(right, Dyn | Null)
+                || (is_number(left) && is_number(right))
+                || std::mem::discriminant(left) == std::mem::discriminant(right);
+            comparable.then_some(Bool)
+        }
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            let orderable =
+                |t: &Type| matches!(t, Dyn | Int | Double | String | Bool | Timestamp | Duration);
+            let comparable = match (left, right) {
+                (Dyn, other) | (other, Dyn) => orderable(other),
+                (left, right) if is_number(left) && is_number(right) => true,
+                (left, right) => left == right && orderable(left),
+            };
+            comparable.then_some(Bool)
+        }
+        BinaryOp::In => match right {
+            List(_) | Map(_) | Dyn => Some(Bool),
+            _ => None,
+        },
+        BinaryOp::Add => match (left, right) {
+            (Dyn, Dyn) => Some(Dyn),
+            (Dyn, other) | (other, Dyn) => matches!(
+                other,
+                Int | Double | String | List(_) | Timestamp | Duration
+            )
+            .then_some(Dyn),
+            (Int, Int) => Some(Int),
+            (Double, Double) => Some(Double),
+            (String, String) => Some(String),
+            (List(a), List(b)) => Some(List(Box::new(if a == b { (**a).clone() } else { Dyn }))),
+            (Timestamp, Duration) | (Duration, Timestamp) => Some(Timestamp),
+            (Duration, Duration) => Some(Duration),
+            _ => None,
+        },
+        BinaryOp::Sub => match (left, right) {
+            (Dyn, Dyn) => Some(Dyn),
+            (Dyn, other) | (other, Dyn) => {
+                matches!(other, Int | Double | Timestamp | Duration).then_some(Dyn)
+            }
+            (Int, Int) => Some(Int),
+            (Double, Double) => Some(Double),
+            (Timestamp, Timestamp) => Some(Duration),
+            (Timestamp, Duration) => Some(Timestamp),
+            (Duration, Duration) => Some(Duration),
+            _ => None,
+        },
+        BinaryOp::Mul | BinaryOp::Div => match (left, right) {
+            (Dyn, Dyn) => Some(Dyn),
+            (Dyn, other) | (other, Dyn) => is_number(other).then_some(Dyn),
+            (Int, Int) => Some(Int),
+            (Double, Double) => Some(Double),
+            _ => None,
+        },
+        BinaryOp::Rem => match (left, right) {
+            (Int | Dyn, Int | Dyn) => Some(Int),
+            _ => None,
+        },
+    }
+}
+
+fn check_call(target: Option<&Type>, function: &str, args: &[Type]) -> Result<Type> {
+    use Type::*;
+
+    let result = match (target, function, args) {
+        (None, "size", [String | List(_) | Map(_) | Dyn]) => Some(Int),
+        (None, "int", [Int | Double | String | Timestamp | Dyn]) => Some(Int),
+        (None, "double", [Int | Double | String | Dyn]) => Some(Double),
+        (None, "string", [Int | Double | String | Bool | Timestamp | Duration | Dyn]) => {
+            Some(String)
+        }
+        (None, "dyn", [_]) => Some(Dyn),
+        (None, "timestamp", [String | Timestamp | Dyn]) => Some(Timestamp),
+        (None, "duration", [String | Duration | Dyn]) => Some(Duration),
+        (None, _, _)
+            if !matches!(
+                function,
+                "size" | "int" | "double" | "string" | "dyn" | "timestamp" | "duration"
+            ) =>
+        {
+            return Err(anyhow!("undeclared reference to '{}'", function));
+        }
+        (Some(String | List(_) | Map(_) | Dyn), "size", []) => Some(Int),
+        (
+            Some(String | Dyn),
+            "startsWith" | "endsWith" | "contains" | "matches",
+            [String | Dyn],
+        ) => Some(Bool),
+        (Some(String | Dyn), "lowerAscii" | "upperAscii", []) => Some(String),
+        (Some(_), _, _)
+            if !matches!(
+                function,
+                "size"
+                    | "startsWith"
+                    | "endsWith"
+                    | "contains"
+                    | "matches"
+                    | "lowerAscii"
+                    | "upperAscii"
+            ) =>
+        {
+            return Err(anyhow!("undeclared reference to '{}'", function));
+        }
+        _ => None,
+    };
+
+    result.ok_or_else(|| {
+        let args = args
+            .iter()
+            .map(Type::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+        match target {
+            Some(target) => anyhow!(
+                "no matching overload for '{}.{}({})'",
+                target,
+                function,
+                args
+            ),
+            None => anyhow!("no matching overload for '{}({})'", function, args),
+        }
+    })
+}
+
+// ---------------------------------------------------------------------------
+// Evaluation
+
+/// Runtime value; JSON arrays and objects are borrowed from the request
+#[derive(Debug, Clone)]
+enum Val<'a> {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Double(f64),
+    Str(Cow<'a, str>),
+    List(Vec<Val<'a>>),
+    Json(&'a Value),
+    Timestamp(DateTime<FixedOffset>),
+    Duration(Duration),
+}
+
+impl<'a> Val<'a> {
+    fn from_json(value: &'a Value) -> Val<'a> {
+        match value {
+            Value::Null => Val::Null,
+            Value::Bool(b) => Val::Bool(*b),
+            Value::Number(n) => match n.as_i64() {
+                Some(n) => Val::Int(n),
+                None => Val::Double(n.as_f64().unwrap_or(f64::NAN)),
+            },
+            Value::String(s) => Val::Str(Cow::Borrowed(s)),
+            Value::Array(_) | Value::Object(_) => Val::Json(value),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Val::Null => "null_type",
+            Val::Bool(_) => "bool",
+            Val::Int(_) => "int",
+            Val::Double(_) => "double",
+            Val::Str(_) => "string",
+            Val::List(_) | Val::Json(Value::Array(_)) => "list",
+            Val::Json(_) => "map",
+            Val::Timestamp(_) => "timestamp",
+            Val::Duration(_) => "duration",
+        }
+    }
+
+    /// Elements of a list, or keys of a map as CEL iterates them
+    fn items(&self) -> Option<Vec<Val<'a>>> {
+        match self {
+            Val::List(items) => Some(items.clone()),
+            Val::Json(Value::Array(items)) => Some(items.iter().map(Val::from_json).collect()),
+            Val::Json(Value::Object(map)) => Some(
+                map.keys()
+                    .map(|k| Val::Str(Cow::Borrowed(k.as_str())))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+}
+
+struct Scope<'a> {
+    event: &'a WebhookEvent<'a>,
+    locals: Vec<(&'a str, Val<'a>)>,
+}
+
+fn no_overload(op: &str, left: &Val, right: &Val) -> String {
+    format!(
+        "no such overload: {} applied to ({}, {})",
+        op,
+        left.type_name(),
+        right.type_name()
+    )
+}
+
+fn eval<'a>(expr: &'a Expr, scope: &mut Scope<'a>) -> Result<Val<'a>, String> {
+    Ok(match expr {
+        Expr::Null => Val::Null,
+        Expr::Bool(b) => Val::Bool(*b),
+        Expr::Int(n) => Val::Int(*n),
+        Expr::Double(n) => Val::Double(*n),
+        Expr::Str(s) => Val::Str(Cow::Borrowed(s)),
+        Expr::Ident(name) => {
+            if let Some((_, value)) = scope.locals.iter().rev().find(|(local, _)| local == name) {
+                return Ok(value.clone());
+            }
+            match name.as_str() {
+                "body" => Val::from_json(scope.event.body),
+                "headers" => Val::Json(scope.event.headers),
+                "query" => Val::Json(scope.event.query),
+                "method" => Val::from_json(scope.event.method),
+                "received_at" => Val::Timestamp(scope.event.received_at.fixed_offset()),
+                _ => return Err(format!("undeclared reference to '{}'", name)),
+            }
+        }
+        Expr::List(items) => Val::List(
+            items
+                .iter()
+                .map(|item| eval(item, scope))
+                .collect::<Result<_, _>>()?,
+        ),
+        Expr::Select(operand, field) => match eval(operand, scope)? {
+            Val::Json(Value::Object(map)) => Val::from_json(
+                map.get(field)
+                    .ok_or_else(|| format!("no such key: {}", field))?,
+            ),
+            other => {
+                return Err(format!(
+                    "type '{}' does not support field selection",
+                    other.type_name()
+                ))
+            }
+        },
+        Expr::Has(operand, field) => match eval(operand, scope)? {
+            Val::Json(Value::Object(map)) => Val::Bool(map.contains_key(field)),
+            other => {
+                return Err(format!(
+                    "type '{}' does not support field selection",
+                    other.type_name()
+                ))
+            }
+        },
+        Expr::Index(operand, index) => {
+            let operand = eval(operand, scope)?;
+            let index = eval(index, scope)?;
+            match (&operand, &index) {
+                (Val::Json(Value::Object(map)), Val::Str(key)) => Val::from_json(
+                    map.get(key.as_ref())
+                        .ok_or_else(|| format!("no such key: {}", key))?,
+                ),
+                (Val::Json(Value::Array(items)), Val::Int(i)) => Val::from_json(
+                    usize::try_from(*i)
+                        .ok()
+                        .and_then(|i| items.get(i))
+                        .ok_or_else(|| format!("index out of range: {}", i))?,
+                ),
+                (Val::List(items), Val::Int(i)) => usize::try_from(*i)
+                    .ok()
+                    .and_then(|i| items.get(i))
+                    .ok_or_else(|| format!("index out of range: {}", i))?
+                    .clone(),
+                _ => return Err(no_overload("_[_]", &operand, &index)),
+            }
+        }
+        Expr::Not(operand) => match eval(operand, scope)? {
+            Val::Bool(b) => Val::Bool(!b),
+            other => {
+                return Err(format!(
+                    "no such overload: !_ applied to ({})",
+                    other.type_name()
+                ))
+            }
+        },
+        Expr::Neg(operand) => match eval(operand, scope)? {
+            Val::Int(n) => Val::Int(n.checked_neg().ok_or("integer overflow")?),
+            Val::Double(n) => Val::Double(-n),
+            Val::Duration(d) => Val::Duration(-d),
+            other => {
+                return Err(format!(
+                    "no such overload: -_ applied to ({})",
+                    other.type_name()
+                ))
+            }
+        },
+        // Errors are absorbed when the other side decides the result, as in CEL
+        Expr::And(left, right) => match eval(left, scope) {
+            Ok(Val::Bool(false)) => Val::Bool(false),
+            left => match (left, eval(right, scope)) {
+                (_, Ok(Val::Bool(false))) => Val::Bool(false),
+                (Ok(Val::Bool(true)), Ok(Val::Bool(true))) => Val::Bool(true),
+                (Err(e), _) | (_, Err(e)) => return Err(e),
+                (Ok(left), Ok(right)) => return Err(no_overload("_&&_", &left, &right)),
+            },
+        },
+        Expr::Or(left, right) => match eval(left, scope) {
+            Ok(Val::Bool(true)) => Val::Bool(true),
+            left => match (left, eval(right, scope)) {
+                (_, Ok(Val::Bool(true))) => Val::Bool(true),
+                (Ok(Val::Bool(false)), Ok(Val::Bool(false))) => Val::Bool(false),
+                (Err(e), _) | (_, Err(e)) => return Err(e),
+                (Ok(left), Ok(right)) => return Err(no_overload("_||_", &left, &right)),
+            },
+        },
+        Expr::Cond(cond, then, otherwise) => match eval(cond, scope)? {
+            Val::Bool(true) => eval(then, scope)?,
+            Val::Bool(false) => eval(otherwise, scope)?,
+            other => {
+                return Err(format!(
+                    "no such overload: _?_:_ applied to ({})",
+                    other.type_name()
+                ))
+            }
+        },
+        Expr::Binary(op, left, right) => {
+            let left = eval(left, scope)?;
+            let right = eval(right, scope)?;
+            binary(*op, left, right)?
+        }
+        Expr::Call {
+            target,
+            function,
+            args,
+            regex,
+        } => {
+            let target = match target {
+                Some(target) => Some(eval(target, scope)?),
+                None => None,
+            };
+            let mut values = Vec::with_capacity(args.len());
+            for arg in args {
+                values.push(eval(arg, scope)?);
+            }
+            call(target, function, values, regex.as_ref())?
+        }
+        Expr::Comprehension {
+            kind,
+            range,
+            var,
+            body,
+        } => {
+            let range = eval(range, scope)?;
+            let items = range
+                .items()
+                .ok_or_else(|| format!("type '{}' cannot be iterated", range.type_name()))?;
+            comprehension(*kind, items, var, body, scope)?
+        }
+    })
+}
+
+fn comprehension<'a>(
+    kind: Macro,
+    items: Vec<Val<'a>>,
+    var: &'a str,
+    body: &'a Expr,
+    scope: &mut Scope<'a>,
+) -> Result<Val<'a>, String> {
+    let mut results = Vec::new();
+    let mut matched = 0;
+    let mut error = None;
+
+    for item in items {
+        scope.locals.push((var, item.clone()));
+        let result = eval(body, scope);
+        scope.locals.pop();
+
+        match (kind, result) {
+            (Macro::Map, result) => results.push(result?),
+            (_, Ok(Val::Bool(true))) => {
+                matched += 1;
+                match kind {
+                    Macro::Exists => return Ok(Val::Bool(true)),
+                    Macro::Filter => results.push(item),
+                    _ => {}
+                }
+            }
+            (_, Ok(Val::Bool(false))) => {
+                if kind == Macro::All {
+                    return Ok(Val::Bool(false));
+                }
+            }
+            (_, Ok(other)) => {
+                return Err(format!(
+                    "macro predicate must be bool, got {}",
+                    other.type_name()
+                ))
+            }
+            // Like `&&`/`||`, a later decisive element absorbs an error
+            (Macro::All | Macro::Exists, Err(e)) => error = Some(e),
+            (_, Err(e)) => return Err(e),
+        }
+    }
+
+    if let Some(e) = error {
+        return Err(e);
+    }
+    Ok(match kind {
+        Macro::All => Val::Bool(true),
+        Macro::Exists => Val::Bool(false),
+        Macro::ExistsOne => Val::Bool(matched == 1),
+        Macro::Filter | Macro::Map => Val::List(results),
+    })
+}
+
+fn binary<'a>(op: BinaryOp, left: Val<'a>, right: Val<'a>) -> Result<Val<'a>, String> {
+    use Val::*;
+
+    Ok(match op {
+        BinaryOp::Eq => Bool(equals(&left, &right)),
+        BinaryOp::Ne => Bool(!equals(&left, &right)),
+        BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            let ordering =
+                compare(&left, &right).ok_or_else(|| no_overload(op.symbol(), &left, &right))?;
+            Bool(match op {
+                BinaryOp::Lt => ordering == Ordering::Less,
+                BinaryOp::Le => ordering != Ordering::Greater,
+                BinaryOp::Gt => ordering == Ordering::Greater,
+                _ => ordering != Ordering::Less,
+            })
+        }
+        BinaryOp::In => match &right {
+            Json(Value::Object(map)) => match &left {
+                Str(key) => Bool(map.contains_key(key.as_ref())),
+                _ => Bool(false),
+            },
+            _ => {
+                let items = right
+                    .items()
+                    .ok_or_else(|| no_overload("@in", &left, &right))?;
+                Bool(items.iter().any(|item| equals(&left, item)))
+            }
+        },
+        BinaryOp::Add => match (left, right) {
+            (Int(a), Int(b)) => Int(a.checked_add(b).ok_or("integer overflow")?),
+            (Double(a), Double(b)) => Double(a + b),
+            (Str(a), Str(b)) => Str(Cow::Owned(format!("{}{}", a, b))),
+            (Timestamp(t), Duration(d)) | (Duration(d), Timestamp(t)) => {
+                Timestamp(t.checked_add_signed(d).ok_or("timestamp overflow")?)
+            }
+            (Duration(a), Duration(b)) => Duration(a.checked_add(&b).ok_or("duration overflow")?),
+            (left, right) => match (left.items(), right.items()) {
+                (Some(mut a), Some(b))
+                    if !matches!(left, Json(Value::Object(_)))
+                        && !matches!(right, Json(Value::Object(_))) =>
+                {
+                    a.extend(b);
+                    List(a)
+                }
+                _ => return Err(no_overload("_+_", &left, &right)),
+            },
+        },
+        BinaryOp::Sub => match (left, right) {
+            (Int(a), Int(b)) => Int(a.checked_sub(b).ok_or("integer overflow")?),
+            (Double(a), Double(b)) => Double(a - b),
+            (Timestamp(a), Timestamp(b)) => Duration(a.signed_duration_since(b)),
+            (Timestamp(t), Duration(d)) => {
+                Timestamp(t.checked_sub_signed(d).ok_or("timestamp overflow")?)
+            }
+            (Duration(a), Duration(b)) => Duration(a.checked_sub(&b).ok_or("duration overflow")?),
+            (left, right) => return Err(no_overload("_-_", &left, &right)),
+        },
+        BinaryOp::Mul => match (left, right) {
+            (Int(a), Int(b)) => Int(a.checked_mul(b).ok_or("integer overflow")?),
+            (Double(a), Double(b)) => Double(a * b),
+            (left, right) => return Err(no_overload("_*_", &left, &right)),
+        },
+        BinaryOp::Div => match (left, right) {
+            (Int(_), Int(0)) => return Err("division by zero".to_string()),
+            (Int(a), Int(b)) => Int(a.checked_div(b).ok_or("integer overflow")?),
+            (Double(a), Double(b)) => Double(a / b),
+            (left, right) => return Err(no_overload("_/_", &left, &right)),
+        },
+        BinaryOp::Rem => match (left, right) {
+            (Int(_), Int(0)) => return Err("modulus by zero".to_string()),
+            (Int(a), Int(b)) => Int(a.checked_rem(b).ok_or("integer overflow")?),
+            (left, right) => return Err(no_overload("_%_", &left, &right)),
+        },
+    })
+}
+
+/// Heterogeneous equality: numbers compare numerically, otherwise values of
+/// different types are never equal
+fn equals(left: &Val, right: &Val) -> bool {
+    match (left, right) {
+        (Val::Null, Val::Null) => true,
+        (Val::Bool(a), Val::Bool(b)) => a == b,
+        (Val::Str(a), Val::Str(b)) => a == b,
+        (Val::Timestamp(a), Val::Timestamp(b)) => a == b,
+        (Val::Duration(a), Val::Duration(b)) => a == b,
+        (Val::Int(_) | Val::Double(_), Val::Int(_) | Val::Double(_)) => {
+            compare(left, right) == Some(Ordering::Equal)
+        }
+        (Val::Json(Value::Object(a)), Val::Json(Value::Object(b))) => a == b,
+        _ => match (left.items(), right.items()) {
+            (Some(a), Some(b)) if left.type_name() == "list" && right.type_name() == "list" => {
+                a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| equals(a, b))
+            }
+            _ => false,
+        },
+    }
+}
+
+fn compare(left: &Val, right: &Val) -> Option<Ordering> {
+    match (left, right) {
+        (Val::Int(a), Val::Int(b)) => Some(a.cmp(b)),
+        (Val::Int(a), Val::Double(b)) => (*a as f64).partial_cmp(b),
+        (Val::Double(a), Val::Int(b)) => a.partial_cmp(&(*b as f64)),
+        (Val::Double(a), Val::Double(b)) => a.partial_cmp(b),
+        (Val::Str(a), Val::Str(b)) => Some(a.cmp(b)),
+        (Val::Bool(a), Val::Bool(b)) => Some(a.cmp(b)),
+        (Val::Timestamp(a), Val::Timestamp(b)) => Some(a.cmp(b)),
+        (Val::Duration(a), Val::Duration(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+fn call<'a>(
+    target: Option<Val<'a>>,
+    function: &str,
+    mut args: Vec<Val<'a>>,
+    regex: Option<&Regex>,
+) -> Result<Val<'a>, String> {
+    let Some(target) = target else {
+        let arg = args
+            .pop()
+            .ok_or_else(|| format!("{}() requires an argument", function))?;
+        return match function {
+            "size" => size(&arg),
+            "dyn" => Ok(arg),
+            _ => convert(function, arg),
+        };
+    };
+
+    match (function, &target, args.as_slice()) {
+        ("size", _, []) => size(&target),
+        ("startsWith", Val::Str(s), [Val::Str(prefix)]) => {
+            Ok(Val::Bool(s.starts_with(prefix.as_ref())))
+        }
+        ("endsWith", Val::Str(s), [Val::Str(suffix)]) => {
+            Ok(Val::Bool(s.ends_with(suffix.as_ref())))
+        }
+        ("contains", Val::Str(s), [Val::Str(needle)]) => Ok(Val::Bool(s.contains(needle.as_ref()))),
+        ("matches", Val::Str(s), [Val::Str(pattern)]) => match regex {
+            Some(regex) => Ok(Val::Bool(regex.is_match(s))),
+            None => Regex::new(pattern)
+                .map(|regex| Val::Bool(regex.is_match(s)))
+                .map_err(|e| format!("invalid regex {:?}: {}", pattern, e)),
+        },
+        ("lowerAscii", Val::Str(s), []) => Ok(Val::Str(Cow::Owned(s.to_ascii_lowercase()))),
+        ("upperAscii", Val::Str(s), []) => Ok(Val::Str(Cow::Owned(s.to_ascii_uppercase()))),
+        _ => {
+            let args = args
+                .iter()
+                .map(Val::type_name)
+                .collect::<Vec<_>>()
+                .join(", ");
+            Err(format!(
+                "no such overload: {}.{}({})",
+                target.type_name(),
+                function,
+                args
+            ))
+        }
+    }
+}
+
+fn size<'a>(value: &Val<'a>) -> Result<Val<'a>, String> {
+    let len = match value {
+        Val::Str(s) => s.chars().count(),
+        Val::List(items) => items.len(),
+        Val::Json(Value::Array(items)) => items.len(),
+        Val::Json(Value::Object(map)) => map.len(),
+        other => return Err(format!("no such overload: size({})", other.type_name())),
+    };
+    Ok(Val::Int(len as i64))
+}
+
+/// Type conversion functions: `int`, `double`, `string`, `timestamp`, `duration`
+fn convert<'a>(function: &str, value: Val<'a>) -> Result<Val<'a>, String> {
+    let unsupported =
+        |value: &Val| format!("no such overload: {}({})", function, value.type_name());
+
+    Ok(match (function, value) {
+        ("int", Val::Int(n)) => Val::Int(n),
+        ("int", Val::Double(n)) => {
+            if !n.is_finite() || n <= i64::MIN as f64 || n >= i64::MAX as f64 {
+                return Err("integer overflow".to_string());
+            }
+            Val::Int(n as i64)
+        }
+        ("int", Val::Str(s)) => Val::Int(
+            s.parse()
+                .map_err(|_| format!("cannot convert {:?} to int", s))?,
+        ),
+        ("int", Val::Timestamp(t)) => Val::Int(t.timestamp()),
+        ("double", Val::Int(n)) => Val::Double(n as f64),
+        ("double", Val::Double(n)) => Val::Double(n),
+        ("double", Val::Str(s)) => Val::Double(
+            s.parse()
+                .map_err(|_| format!("cannot convert {:?} to double", s))?,
+        ),
+        ("string", Val::Str(s)) => Val::Str(s),
+        ("string", Val::Int(n)) => Val::Str(Cow::Owned(n.to_string())),
+        ("string", Val::Double(n)) => Val::Str(Cow::Owned(n.to_string())),
+        ("string", Val::Bool(b)) => Val::Str(Cow::Owned(b.to_string())),
+        ("string", Val::Timestamp(t)) => Val::Str(Cow::Owned(t.to_rfc3339())),
+        ("string", Val::Duration(d)) => Val::Str(Cow::Owned(format!(
+            "{}s",
+            d.num_nanoseconds()
+                .map_or(d.num_seconds() as f64, |n| n as f64 / 1e9)
+        ))),
+        ("timestamp", Val::Timestamp(t)) => Val::Timestamp(t),
+        ("timestamp", Val::Str(s)) => Val::Timestamp(
+            DateTime::parse_from_rfc3339(&s).map_err(|_| format!("invalid timestamp {:?}", s))?,
+        ),
+        ("duration", Val::Duration(d)) => Val::Duration(d),
+        ("duration", Val::Str(s)) => {
+            Val::Duration(parse_duration(&s).ok_or_else(|| format!("invalid duration {:?}", s))?)
+        }
+        (_, value) => return Err(unsupported(&value)),
+    })
+}
+
+/// Parses a duration such as `"300s"`, `"1.5h"` or `"1h30m"`
+fn parse_duration(s: &str) -> Option<Duration> {
+    let (negative, mut rest) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    if rest == "0" {
+        return Some(Duration::zero());
+    }
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut nanos = 0f64;
+    while !rest.is_empty() {
+        let number_len = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit() || c == '.')
+            .unwrap_or(rest.len());
+        let scale = match &rest[..unit_len] {
+            "h" => 3600e9,
+            "m" => 60e9,
+            "s" => 1e9,
+            "ms" => 1e6,
+            "us" | "µs" => 1e3,
+            "ns" => 1.0,
+            _ => return None,
+        };
+        nanos += number * scale;
+        rest = &rest[unit_len..];
+    }
+
+    if nanos >= i64::MAX as f64 {
+        return None;
+    }
+    let duration = Duration::nanoseconds(nanos as i64);
+    Some(if negative { -duration } else { duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn evaluate(source: &str, body: &Value) -> Result<bool, String> {
+        evaluate_with_headers(
+            source,
+            body,
+            &json!({ "x-env": "prod", "content-type": "application/json" }),
+        )
+    }
+
+    fn evaluate_with_headers(source: &str, body: &Value, headers: &Value) -> Result<bool, String> {
+        let query = json!({ "source": "test" });
+        let method = json!("POST");
+        let event = WebhookEvent {
+            body,
+            headers,
+            query: &query,
+            method: &method,
+            received_at: "2024-05-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap(),
+        };
+        Program::compile(source)
+            .map_err(|e| e.to_string())?
+            .evaluate_bool(&event)
+    }
+
+    #[test]
+    fn test_operators_and_variables() {
+        let body = json!({ "payload": { "amount": 150, "fee": 2.5, "currency": "usd" }, "livemode": true });
+
+        assert_eq!(
+            evaluate(
+                "body.payload.amount > 100 && headers['x-env'] == 'prod'",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(evaluate("body.payload.amount + 50 == 200", &body), Ok(true));
+        assert_eq!(
+            evaluate(
+                "body.payload.fee * 2.0 == 5.0 && body.payload.amount == 150.0",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "body.payload.currency in ['usd', 'eur'] ? body.livemode : false",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("!(query.source == 'test') || 7 % 4 == 3", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "method == 'POST' && 'x-env' in headers && !('x-missing' in headers)",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(evaluate("-body.payload.amount < -100", &body), Ok(true));
+    }
+
+    #[test]
+    fn test_functions_and_macros() {
+        let body = json!({
+            "event": "meeting.started",
+            "created_at": "2024-05-01T11:58:00Z",
+            "items": [{ "sku": "A1", "qty": 1 }, { "sku": "B2", "qty": 3 }]
+        });
+
+        assert_eq!(
+            evaluate(
+                "body.event.startsWith('meeting.') && body.event.matches('^[a-z.]+$')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("size(body.items) == 2 && body.event.size() == 15", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "body.items.exists(i, i.qty > 2) && body.items.all(i, i.sku.size() == 2)",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "body.items.filter(i, i.qty > 0).map(i, i.sku) == ['A1', 'B2']",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("body.items.exists_one(i, i.sku.endsWith('1'))", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "received_at - timestamp(body.created_at) < duration('5m')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("int('42') + int(2.9) == 44 && string(1.5) == '1.5'", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("has(body.event) && !has(body.account_id)", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("body.event.upperAscii() == 'MEETING.STARTED'", &body),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let body = json!({ "event": "meeting.started", "count": "3" });
+
+        assert_eq!(
+            evaluate("body.account_id == 'a'", &body),
+            Err("no such key: account_id".to_string())
+        );
+        assert!(evaluate("body.count > 2", &body)
+            .unwrap_err()
+            .contains("no such overload"));
+        assert!(evaluate("1 / (size(body.event) - 15)", &body).is_err());
+        // A decisive side absorbs errors on the other
+        assert_eq!(
+            evaluate("body.account_id == 'a' && false", &body),
+            Ok(false)
+        );
+        assert_eq!(
+            evaluate("has(body.account_id) && body.account_id == 'a'", &body),
+            Ok(false)
+        );
+        assert_eq!(evaluate("body.account_id == 'a' || true", &body), Ok(true));
+    }
+
+    #[test]
+    fn test_type_check_errors() {
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("payload.amount > 1"),
+            "undeclared reference to 'payload'"
+        );
+        assert_eq!(
+            error("'a' > 1"),
+            "no matching overload for '_>_' applied to (string, int)"
+        );
+        assert_eq!(
+            error("1 + 1.0 == 2.0"),
+            "no matching overload for '_+_' applied to (int, double)"
+        );
+        assert_eq!(
+            error("headers['x-env'] + 1 == 2"),
+            "no matching overload for '_+_' applied to (string, int)"
+        );
+        assert_eq!(
+            error("received_at.hour == 1"),
+            "type 'timestamp' does not support field selection ('.hour')"
+        );
+        assert_eq!(
+            error("body.event.reverse()"),
+            "undeclared reference to 'reverse'"
+        );
+        assert_eq!(
+            error("1 && true"),
+            "logical operators require bool operands, got int"
+        );
+        assert!(error("body.event.matches('(')").starts_with("Invalid regex"));
+        assert_eq!(
+            error("duration('soon') > duration('1s')"),
+            "invalid duration \"soon\""
+        );
+        assert!(error("body.a ==").starts_with("Syntax error"));
+        assert!(error("'unterminated").starts_with("Syntax error"));
+        assert_eq!(error(&"(".repeat(100)), "Expression is nested too deeply");
+
+        assert_eq!(
+            Program::compile("size(body.items)").unwrap().result_type(),
+            &Type::Int
+        );
+        assert_eq!(
+            Program::compile("headers['x-env']").unwrap().result_type(),
+            &Type::String
+        );
+        assert_eq!(
+            Program::compile("body.a").unwrap().result_type(),
+            &Type::Dyn
+        );
+    }
+
+    #[test]
+    fn test_header_lookup() {
+        let body = json!({});
+        let rule = "headers['x-env'] == 'prod'";
+
+        assert_eq!(
+            evaluate_with_headers(rule, &body, &json!({ "x-env": "prod" })),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate_with_headers(rule, &body, &json!({ "x-env": "staging" })),
+            Ok(false)
+        );
+        assert_eq!(
+            evaluate_with_headers(rule, &body, &json!({})),
+            Err("no such key: x-env".to_string())
+        );
+        assert_eq!(
+            evaluate_with_headers(
+                &format!("'x-env' in headers && {}", rule),
+                &body,
+                &json!({})
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            evaluate_with_headers("headers.host == 'a'", &body, &json!({ "host": "a" })),
+            Ok(true)
+        );
+        assert_eq!(Program::compile(rule).unwrap().result_type(), &Type::Bool);
+    }
+
+    #[test]
+    fn test_has() {
+        let body = json!({ "event": "created", "payload": { "id": null }, "items": [] });
+
+        assert_eq!(evaluate("has(body.event)", &body), Ok(true));
+        assert_eq!(evaluate("has(body.missing)", &body), Ok(false));
+        // A present null field counts as set
+        assert_eq!(
+            evaluate("has(body.payload.id) && !has(body.payload.account)", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("has(query.source) && !has(query.page)", &body),
+            Ok(true)
+        );
+
+        // The operand must exist and be a map
+        assert_eq!(
+            evaluate("has(body.missing.id)", &body),
+            Err("no such key: missing".to_string())
+        );
+        assert_eq!(
+            evaluate("has(body.event.id)", &body),
+            Err("type 'string' does not support field selection".to_string())
+        );
+        assert_eq!(
+            evaluate("has(body.items.id)", &body),
+            Err("type 'list' does not support field selection".to_string())
+        );
+
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+        let usage = "has() requires a single field selection, e.g. has(body.field)";
+        assert_eq!(error("has(body)"), usage);
+        assert_eq!(error("has(body['event'])"), usage);
+        assert_eq!(error("has(body.a, body.b)"), usage);
+        assert_eq!(error("has()"), usage);
+        assert_eq!(
+            error("has(method.x)"),
+            "type 'string' does not support field selection ('.x')"
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let body = json!({
+            "items": [{ "sku": "A1", "qty": 1 }, { "sku": "B2", "qty": 3 }, { "sku": "C3", "qty": 3 }],
+            "empty": [],
+            "labels": { "team": "payments", "tier": "gold" },
+            "mixed": [1, "two"]
+        });
+
+        // all
+        assert_eq!(evaluate("body.items.all(i, i.qty > 0)", &body), Ok(true));
+        assert_eq!(evaluate("body.items.all(i, i.qty > 1)", &body), Ok(false));
+        assert_eq!(evaluate("body.empty.all(i, false)", &body), Ok(true));
+        // exists
+        assert_eq!(
+            evaluate("body.items.exists(i, i.sku == 'C3')", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("body.items.exists(i, i.qty > 3)", &body),
+            Ok(false)
+        );
+        assert_eq!(evaluate("body.empty.exists(i, true)", &body), Ok(false));
+        // exists_one
+        assert_eq!(
+            evaluate("body.items.exists_one(i, i.qty == 1)", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("body.items.exists_one(i, i.qty == 3)", &body),
+            Ok(false)
+        );
+        assert_eq!(
+            evaluate("body.items.exists_one(i, i.qty == 4)", &body),
+            Ok(false)
+        );
+        // filter and map
+        assert_eq!(
+            evaluate(
+                "body.items.filter(i, i.qty == 3).map(i, i.sku) == ['B2', 'C3']",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("body.items.map(i, i.qty * 2) == [2, 6, 6]", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("size(body.items.filter(i, i.qty > 5)) == 0", &body),
+            Ok(true)
+        );
+        assert_eq!(evaluate("body.empty.map(i, i) == []", &body), Ok(true));
+
+        // Literal lists and maps, whose keys are iterated
+        assert_eq!(
+            evaluate(
+                "[1, 2, 3].all(n, n > 0) && [1, 2, 3].map(n, n * n) == [1, 4, 9]",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "body.labels.exists(k, k == 'tier') && body.labels.all(k, k.size() == 4)",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("headers.exists_one(h, h.startsWith('x-'))", &body),
+            Ok(true)
+        );
+        // Nested macros see their own variable and the enclosing ones
+        assert_eq!(
+            evaluate(
+                "body.items.all(i, body.items.exists(j, j.qty >= i.qty))",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("[1, 2].exists(x, [2, 3].exists(x, x == 3))", &body),
+            Ok(true)
+        );
+
+        // A decisive element absorbs errors on others for all and exists only
+        assert_eq!(evaluate("body.mixed.exists(x, x > 0)", &body), Ok(true));
+        assert_eq!(evaluate("body.mixed.all(x, x < 0)", &body), Ok(false));
+        assert!(evaluate("body.mixed.all(x, x > 0)", &body)
+            .unwrap_err()
+            .contains("no such overload"));
+        assert!(evaluate("body.mixed.exists_one(x, x > 0)", &body)
+            .unwrap_err()
+            .contains("no such overload"));
+        assert!(evaluate("body.mixed.filter(x, x > 0) == [1]", &body)
+            .unwrap_err()
+            .contains("no such overload"));
+        assert!(evaluate("body.mixed.map(x, x + 1) == [2]", &body)
+            .unwrap_err()
+            .contains("no such overload"));
+
+        // Dynamic values are checked when evaluated
+        assert_eq!(
+            evaluate("body.items.all(i, i.sku)", &body),
+            Err("macro predicate must be bool, got string".to_string())
+        );
+        assert_eq!(
+            evaluate("body.items[0].sku.all(c, true)", &body),
+            Err("type 'string' cannot be iterated".to_string())
+        );
+
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+        assert_eq!(
+            error("[1, 2].all(n, n + 1)"),
+            "macro predicate must be bool, got int"
+        );
+        assert_eq!(
+            error("[1, 2].filter(n, 'yes')"),
+            "macro predicate must be bool, got string"
+        );
+        assert_eq!(
+            error("method.exists(c, true)"),
+            "type 'string' cannot be iterated"
+        );
+        assert_eq!(error("[1, 2].all(n, m > 0)"), "undeclared reference to 'm'");
+        // The macro variable is only in scope in its body
+        assert_eq!(
+            error("[1].all(n, true) && n > 0"),
+            "undeclared reference to 'n'"
+        );
+        for source in [
+            "[1].all(n)",
+            "[1].exists(1, true)",
+            "[1].map(n, n, n)",
+            "[1].filter()",
+        ] {
+            assert!(
+                error(source).contains("requires a variable and an expression"),
+                "{}",
+                source
+            );
+        }
+
+        assert_eq!(
+            Program::compile("[1, 2].map(n, string(n))")
+                .unwrap()
+                .result_type(),
+            &Type::List(Box::new(Type::String))
+        );
+        assert_eq!(
+            Program::compile("['a'].filter(s, s != '')")
+                .unwrap()
+                .result_type(),
+            &Type::List(Box::new(Type::String))
+        );
+    }
+
+    #[test]
+    fn test_timestamps_and_durations() {
+        let body = json!({ "created_at": "2024-05-01T11:58:00Z", "expires_at": "2024-05-01T14:00:00+02:00", "ttl": "90m" });
+
+        // received_at is 2024-05-01T12:00:00Z
+        assert_eq!(
+            evaluate(
+                "received_at - timestamp(body.created_at) == duration('2m')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "timestamp(body.created_at) + duration('2m') == received_at",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "duration('2m') + timestamp(body.created_at) == received_at",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "received_at - duration('2m') == timestamp(body.created_at)",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "received_at - duration(body.ttl) < timestamp(body.created_at)",
+                &body
+            ),
+            Ok(true)
+        );
+        // Offsets are compared as instants
+        assert_eq!(
+            evaluate(
+                "timestamp(body.expires_at) == timestamp('2024-05-01T12:00:00Z')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("received_at >= timestamp(body.expires_at)", &body),
+            Ok(true)
+        );
+
+        assert_eq!(
+            evaluate(
+                "duration('1h30m') == duration('90m') && duration(body.ttl) == duration('5400s')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("duration('1h') - duration('30m') == duration('30m')", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "-duration('1s') < duration('0') && duration('1ms') < duration('1s')",
+                &body
+            ),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate(
+                "timestamp(body.created_at) - received_at == -duration('2m')",
+                &body
+            ),
+            Ok(true)
+        );
+
+        assert_eq!(evaluate("int(received_at) == 1714564800", &body), Ok(true));
+        assert_eq!(
+            evaluate("string(received_at) == '2024-05-01T12:00:00+00:00'", &body),
+            Ok(true)
+        );
+        assert_eq!(
+            evaluate("string(duration('1m30s')) == '90s'", &body),
+            Ok(true)
+        );
+        assert_eq!(evaluate("timestamp(received_at) == received_at && duration(duration('1s')) == duration('1s')", &body), Ok(true));
+
+        assert_eq!(
+            evaluate("timestamp(body.ttl) > received_at", &body),
+            Err("invalid timestamp \"90m\"".to_string())
+        );
+        assert_eq!(
+            evaluate("duration(body.created_at) > duration('1s')", &body),
+            Err("invalid duration \"2024-05-01T11:58:00Z\"".to_string())
+        );
+
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+        assert_eq!(
+            error("received_at + received_at > received_at"),
+            "no matching overload for '_+_' applied to (timestamp, timestamp)"
+        );
+        assert_eq!(
+            error("duration('1s') - received_at > duration('1s')"),
+            "no matching overload for '_-_' applied to (duration, timestamp)"
+        );
+        assert_eq!(
+            error("duration('1s') * 2 > duration('1s')"),
+            "no matching overload for '_*_' applied to (duration, int)"
+        );
+        assert_eq!(
+            error("received_at > duration('1s')"),
+            "no matching overload for '_>_' applied to (timestamp, duration)"
+        );
+        assert_eq!(
+            error("timestamp('yesterday') < received_at"),
+            "invalid timestamp \"yesterday\""
+        );
+        assert_eq!(
+            error("timestamp(1) < received_at"),
+            "no matching overload for 'timestamp(int)'"
+        );
+        assert_eq!(
+            error("double(received_at) > 0.0"),
+            "no matching overload for 'double(timestamp)'"
+        );
+
+        assert_eq!(
+            Program::compile("received_at - received_at")
+                .unwrap()
+                .result_type(),
+            &Type::Duration
+        );
+        assert_eq!(
+            Program::compile("received_at + duration('1s')")
+                .unwrap()
+                .result_type(),
+            &Type::Timestamp
+        );
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+        let too_deep = "Expression is nested too deeply";
+        let within = MAX_DEPTH / 2;
+
+        // Every way of nesting is bounded: grouping, lists, calls, indexes,
+        // prefix operators, and chains of binary operators and selections
+        assert!(
+            Program::compile(&format!("{}true{}", "(".repeat(within), ")".repeat(within))).is_ok()
+        );
+        assert_eq!(
+            error(&format!(
+                "{}true{}",
+                "(".repeat(MAX_DEPTH + 1),
+                ")".repeat(MAX_DEPTH + 1)
+            )),
+            too_deep
+        );
+        assert_eq!(
+            error(&format!(
+                "{}1{}",
+                "[".repeat(MAX_DEPTH + 1),
+                "]".repeat(MAX_DEPTH + 1)
+            )),
+            too_deep
+        );
+        assert_eq!(
+            error(&format!(
+                "{}1{}",
+                "size(".repeat(MAX_DEPTH + 1),
+                ")".repeat(MAX_DEPTH + 1)
+            )),
+            too_deep
+        );
+        assert_eq!(
+            error(&format!("body{}", "[body".repeat(MAX_DEPTH + 1))),
+            too_deep
+        );
+
+        assert!(Program::compile(&format!("{}true", "!".repeat(within))).is_ok());
+        assert_eq!(error(&format!("{}true", "!".repeat(100_000))), too_deep);
+        assert_eq!(error(&format!("{}1", "-".repeat(100_000))), too_deep);
+
+        assert!(Program::compile(&vec!["true"; within].join(" || ")).is_ok());
+        assert_eq!(error(&vec!["true"; 100_000].join(" || ")), too_deep);
+        assert_eq!(error(&vec!["true"; 100_000].join(" && ")), too_deep);
+        assert_eq!(error(&vec!["1"; 100_000].join(" + ")), too_deep);
+        assert_eq!(error(&vec!["1"; 100_000].join(" * ")), too_deep);
+        assert_eq!(error(&vec!["1"; 100_000].join(" == ")), too_deep);
+        assert!(Program::compile(&format!("body{}", ".a".repeat(within))).is_ok());
+        assert_eq!(error(&format!("body{}", ".a".repeat(100_000))), too_deep);
+        assert_eq!(error(&format!("body{}", "[0]".repeat(100_000))), too_deep);
+        assert_eq!(
+            error(&format!("'a'{}", ".lowerAscii()".repeat(100_000))),
+            too_deep
+        );
+
+        // Depth is released after each nested expression, so long flat lists are fine
+        let flat = format!("[{}].all(x, x)", vec!["(true)"; 1000].join(", "));
+        assert_eq!(evaluate(&flat, &json!({})), Ok(true));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+
+        assert_eq!(
+            error("body.a == 1 )"),
+            "Syntax error at position 12: unexpected ')'"
+        );
+        assert_eq!(
+            error("body.a # 1"),
+            "Syntax error at position 7: unexpected character '#'"
+        );
+        assert_eq!(
+            error("'a\\q' == ''"),
+            "Syntax error at position 2: invalid escape \\q"
+        );
+        assert_eq!(
+            error("'\\uZZZZ' == ''"),
+            "Syntax error at position 1: invalid \\u escape"
+        );
+        assert_eq!(
+            error("\"open"),
+            "Syntax error at position 0: unterminated string"
+        );
+        assert!(error("1.2.3 == 1").starts_with("Syntax error at position"));
+        assert!(error("1e == 1").contains("invalid number"));
+        assert_eq!(error("in == 1"), "Syntax error: reserved word 'in'");
+        assert_eq!(error("{'a': 1}"), "Map literals are not supported");
+        assert!(error("body.").contains("expected a field or method name"));
+        assert!(error("body[0").contains("expected ']'"));
+        assert!(error("[1, 2").contains("expected ']'"));
+        assert!(error("size(body").contains("expected ')'"));
+        assert!(error("true ? 1").contains("expected ':'"));
+        assert!(error("").contains("expected an expression"));
+        assert!(error("body.a == ,").contains("expected an expression"));
+    }
+
+    #[test]
+    fn test_type_check_rejections() {
+        let error = |source: &str| Program::compile(source).unwrap_err().to_string();
+
+        // Unknown variables and functions
+        assert_eq!(
+            error("request.body == 1"),
+            "undeclared reference to 'request'"
+        );
+        assert_eq!(
+            error("now() > received_at"),
+            "undeclared reference to 'now'"
+        );
+        assert_eq!(
+            error("method.trim() == 'POST'"),
+            "undeclared reference to 'trim'"
+        );
+
+        // Selection and indexing
+        assert_eq!(
+            error("method.verb == 'GET'"),
+            "type 'string' does not support field selection ('.verb')"
+        );
+        assert_eq!(
+            error("[1, 2].first == 1"),
+            "type 'list(int)' does not support field selection ('.first')"
+        );
+        assert_eq!(
+            error("[1, 2]['a'] == 1"),
+            "no matching overload for '_[_]' applied to (list(int), string)"
+        );
+        assert_eq!(
+            error("headers[0] == 'a'"),
+            "no matching overload for '_[_]' applied to (map(string, string), int)"
+        );
+        assert_eq!(
+            error("method[0] == 'P'"),
+            "no matching overload for '_[_]' applied to (string, int)"
+        );
+
+        // Operators
+        assert_eq!(
+            error("!method"),
+            "no matching overload for '!_' applied to (string)"
+        );
+        assert_eq!(
+            error("-method == 1"),
+            "no matching overload for '-_' applied to (string)"
+        );
+        assert_eq!(
+            error("method || true"),
+            "logical operators require bool operands, got string"
+        );
+        assert_eq!(
+            error("true && 1"),
+            "logical operators require bool operands, got int"
+        );
+        assert_eq!(
+            error("method ? 1 : 2"),
+            "conditional requires a bool condition, got string"
+        );
+        assert_eq!(
+            error("method == 1"),
+            "no matching overload for '_==_' applied to (string, int)"
+        );
+        assert_eq!(
+            error("[1] < [2]"),
+            "no matching overload for '_<_' applied to (list(int), list(int))"
+        );
+        assert_eq!(
+            error("'a' in 'abc'"),
+            "no matching overload for '@in' applied to (string, string)"
+        );
+        assert_eq!(
+            error("'a' - 'b' == ''"),
+            "no matching overload for '_-_' applied to (string, string)"
+        );
+        assert_eq!(
+            error("2.0 % 1.0 == 0.0"),
+            "no matching overload for '_%_' applied to (double, double)"
+        );
+        assert_eq!(
+            error("'a' * 2 == 'aa'"),
+            "no matching overload for '_*_' applied to (string, int)"
+        );
+        assert_eq!(
+            error("headers + 1 == 1"),
+            "no matching overload for '_+_' applied to (map(string, string), int)"
+        );
+
+        // Functions and methods
+        assert_eq!(
+            error("size(1) == 1"),
+            "no matching overload for 'size(int)'"
+        );
+        assert_eq!(error("size() == 1"), "no matching overload for 'size()'");
+        assert_eq!(
+            error("size('a', 'b') == 1"),
+            "no matching overload for 'size(string, string)'"
+        );
+        assert_eq!(
+            error("int(true) == 1"),
+            "no matching overload for 'int(bool)'"
+        );
+        assert_eq!(
+            error("string([1]) == ''"),
+            "no matching overload for 'string(list(int))'"
+        );
+        assert_eq!(
+            error("duration(1) > duration('1s')"),
+            "no matching overload for 'duration(int)'"
+        );
+        assert_eq!(
+            error("method.startsWith(1)"),
+            "no matching overload for 'string.startsWith(int)'"
+        );
+        assert_eq!(
+            error("method.lowerAscii('x') == ''"),
+            "no matching overload for 'string.lowerAscii(string)'"
+        );
+        assert_eq!(
+            error("received_at.size() == 1"),
+            "no matching overload for 'timestamp.size()'"
+        );
+        assert_eq!(
+            error("[1].contains(1)"),
+            "no matching overload for 'list(int).contains(int)'"
+        );
+        assert!(error("method.matches('[')").starts_with("Invalid regex \"[\""));
+
+        // Dynamic values pass the checker and are checked when evaluated
+        let body = json!({ "n": 1, "s": "a", "list": [1] });
+        assert!(evaluate("body.s > 1", &body)
+            .unwrap_err()
+            .starts_with("no such overload: _>_ applied to (string, int)"));
+        assert_eq!(
+            evaluate("!body.s", &body),
+            Err("no such overload: !_ applied to (string)".to_string())
+        );
+        assert_eq!(
+            evaluate("-body.s == 1", &body),
+            Err("no such overload: -_ applied to (string)".to_string())
+        );
+        assert_eq!(
+            evaluate("body.n ? true : false", &body),
+            Err("no such overload: _?_:_ applied to (int)".to_string())
+        );
+        assert_eq!(
+            evaluate("body.n && true", &body),
+            Err("no such overload: _&&_ applied to (int, bool)".to_string())
+        );
+        assert_eq!(
+            evaluate("body.s.startsWith(body.n)", &body),
+            Err("no such overload: string.startsWith(int)".to_string())
+        );
+        assert_eq!(
+            evaluate("size(body.n) == 1", &body),
+            Err("no such overload: size(int)".to_string())
+        );
+        assert_eq!(
+            evaluate("int(body.s) == 1", &body),
+            Err("cannot convert \"a\" to int".to_string())
+        );
+        assert!(evaluate("body.s.matches(body.s + '(')", &body)
+            .unwrap_err()
+            .starts_with("invalid regex"));
+        assert_eq!(
+            evaluate("body.list[1] == 1", &body),
+            Err("index out of range: 1".to_string())
+        );
+        assert_eq!(
+            evaluate("body.list[-1] == 1", &body),
+            Err("index out of range: -1".to_string())
+        );
+        assert_eq!(
+            evaluate("body['missing'] == 1", &body),
+            Err("no such key: missing".to_string())
+        );
+        assert_eq!(
+            evaluate("body.n", &body),
+            Err("expected bool, got int".to_string())
+        );
+        assert_eq!(
+            evaluate("body.n / 0 == 1", &body),
+            Err("division by zero".to_string())
+        );
+        assert_eq!(
+            evaluate("body.n % 0 == 1", &body),
+            Err("modulus by zero".to_string())
+        );
+        assert_eq!(
+            evaluate("9223372036854775807 + body.n > 0", &body),
+            Err("integer overflow".to_string())
+        );
+        assert_eq!(
+            evaluate("int(1e19) > 0", &body),
+            Err("integer overflow".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("1h30m"), Some(Duration::minutes(90)));
+        assert_eq!(parse_duration("1.5s"), Some(Duration::milliseconds(1500)));
+        assert_eq!(parse_duration("-300ms"), Some(Duration::milliseconds(-300)));
+        assert_eq!(parse_duration("0"), Some(Duration::zero()));
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration("5 days"), None);
+    }
+}
@@ -0,0 +1,176 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+use crate::crd::ChallengeMode;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Answer to a provider endpoint-validation handshake
+#[derive(Debug, PartialEq)]
+pub enum ChallengeResponse {
+    Json(Value),
+    Text(String),
+}
+
+/// Answers handshakes carried in the query string.
+/// Microsoft Graph (`?validationToken=`) and Meta (`?hub.challenge=`) send
+/// these unsigned, so they are answered before signature verification.
+pub fn query_challenge(
+    mode: ChallengeMode,
+    query: &HashMap<String, String>,
+    verify_token: Option<&str>,
+) -> Result<Option<ChallengeResponse>> {
+    match mode {
+        ChallengeMode::MicrosoftGraph => Ok(query
+            .get("validationToken")
+            .map(|token| ChallengeResponse::Text(token.clone()))),
+        ChallengeMode::Meta => {
+            let Some(challenge) = query.get("hub.challenge") else {
+                return Ok(None);
+            };
+            if query.get("hub.mode").map(String::as_str) != Some("subscribe") {
+                return Err(anyhow!("Unsupported hub.mode"));
+            }
+            let expected = verify_token.ok_or_else(|| anyhow!("No verify token configured"))?;
+            if query.get("hub.verify_token").map(String::as_str) != Some(expected) {
+                return Err(anyhow!("Verify token mismatch"));
+            }
+            Ok(Some(ChallengeResponse::Text(challenge.clone())))
+        }
+        ChallengeMode::Zoom | ChallengeMode::Slack => Ok(None),
+    }
+}
+
+/// Answers handshakes carried in the (already verified) JSON body.
+/// Zoom expects the plain token HMAC'd with the webhook secret token,
+/// Slack expects its challenge echoed back.
+pub fn body_challenge(
+    mode: ChallengeMode,
+    body: &Value,
+    secret: Option<&str>,
+) -> Result<Option<ChallengeResponse>> {
+    match mode {
+        ChallengeMode::Zoom => {
+            if body.get("event").and_then(Value::as_str) != Some("endpoint.url_validation") {
+                return Ok(None);
+            }
+            let plain_token = body
+                .pointer("/payload/plainToken")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("Missing payload.plainToken"))?;
+            let secret = secret.ok_or_else(|| anyhow!("No secret token configured"))?;
+
+            let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
+                .map_err(|e| anyhow!("Invalid key: {}", e))?;
+            mac.update(plain_token.as_bytes());
+            let encrypted_token = hex::encode(mac.finalize().into_bytes());
+
+            Ok(Some(ChallengeResponse::Json(json!({
+                "plainToken": plain_token,
+                "encryptedToken": encrypted_token,
+            }))))
+        }
+        ChallengeMode::Slack => {
+            if body.get("type").and_then(Value::as_str) != Some("url_verification") {
+                return Ok(None);
+            }
+            let challenge = body
+                .get("challenge")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("Missing challenge"))?;
+            Ok(Some(ChallengeResponse::Json(
+                json!({ "challenge": challenge }),
+            )))
+        }
+        ChallengeMode::Meta | ChallengeMode::MicrosoftGraph => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_zoom_url_validation() {
+        let body = json!({
+            "event": "endpoint.url_validation",
+            "payload": { "plainToken": "qgg8vlvZRS6UYooatFL8Aw" }
+        });
+
+        let mut mac = HmacSha256::new_from_slice(b"zoom_secret").unwrap();
+        mac.update(b"qgg8vlvZRS6UYooatFL8Aw");
+        let expected = hex::encode(mac.finalize().into_bytes());
+
+        let response = body_challenge(ChallengeMode::Zoom, &body, Some("zoom_secret")).unwrap();
+        assert_eq!(
+            response,
+            Some(ChallengeResponse::Json(json!({
+                "plainToken": "qgg8vlvZRS6UYooatFL8Aw",
+                "encryptedToken": expected,
+            })))
+        );
+
+        // Regular events pass through
+        let event = json!({ "event": "meeting.started" });
+        assert_eq!(
+            body_challenge(ChallengeMode::Zoom, &event, Some("zoom_secret")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_slack_url_verification() {
+        let body = json!({ "type": "url_verification", "challenge": "3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P" });
+        let response = body_challenge(ChallengeMode::Slack, &body, None).unwrap();
+        assert_eq!(
+            response,
+            Some(ChallengeResponse::Json(json!({
+                "challenge": "3eZbrw1aBm2rZgRNFdxV2595E9CY3gmdALWMmHkvFXO7tYXAYM8P"
+            })))
+        );
+    }
+
+    #[test]
+    fn test_meta_hub_challenge() {
+        let request = query(&[
+            ("hub.mode", "subscribe"),
+            ("hub.verify_token", "meta_token"),
+            ("hub.challenge", "1158201444"),
+        ]);
+        assert_eq!(
+            query_challenge(ChallengeMode::Meta, &request, Some("meta_token")).unwrap(),
+            Some(ChallengeResponse::Text("1158201444".to_string()))
+        );
+        assert!(query_challenge(ChallengeMode::Meta, &request, Some("other")).is_err());
+        assert_eq!(
+            query_challenge(ChallengeMode::Meta, &query(&[]), Some("meta_token")).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_microsoft_graph_validation_token() {
+        let request = query(&[("validationToken", "Validation: Testing client application")]);
+        assert_eq!(
+            query_challenge(ChallengeMode::MicrosoftGraph, &request, None).unwrap(),
+            Some(ChallengeResponse::Text(
+                "Validation: Testing client application".to_string()
+            ))
+        );
+        // Other modes ignore the query string
+        assert_eq!(
+            query_challenge(ChallengeMode::Zoom, &request, None).unwrap(),
+            None
+        );
+    }
+}
@@ -0,0 +1,431 @@
+use anyhow::{anyhow, Result};
+use axum::body::Bytes;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::crd::{CloudEventsConfig, CloudEventsMode};
+use crate::filter::{parse_request_path, RequestPath, WebhookEvent};
+use crate::kafka::RecordHeader;
+
+const SPEC_VERSION: &str = "1.0";
+const DEFAULT_TYPE: &str = "com.example.webhooks.received";
+const STRUCTURED_CONTENT_TYPE: &str = "application/cloudevents+json";
+
+/// A handler's CloudEvents attribute mappings, compiled when it is loaded
+#[derive(Debug)]
+pub struct CloudEvents {
+    mode: CloudEventsMode,
+    id: Option<Attribute>,
+    source: Option<Attribute>,
+    event_type: Option<Attribute>,
+    subject: Option<Attribute>,
+    time: Option<RequestPath>,
+}
+
+/// Where an attribute's value comes from
+#[derive(Debug)]
+enum Attribute {
+    Path(RequestPath),
+    Literal(String),
+}
+
+/// One webhook as a CloudEvent, ready to be written to Kafka
+#[derive(Debug)]
+pub struct CloudEvent {
+    mode: CloudEventsMode,
+    id: String,
+    source: String,
+    event_type: String,
+    subject: Option<String>,
+    time: String,
+    data_content_type: Option<String>,
+    data: Bytes,
+}
+
+impl CloudEvents {
+    pub fn compile(config: &CloudEventsConfig) -> Result<CloudEvents> {
+        let time = match config.time.as_deref() {
+            Some(time) if !time.starts_with('$') => {
+                return Err(anyhow!(
+                    "cloudEvents time must be a JSONPath, not {:?}",
+                    time
+                ))
+            }
+            time => time.map(parse_request_path).transpose()?,
+        };
+
+        Ok(CloudEvents {
+            mode: config.mode,
+            id: compile_attribute("id", config.id.as_deref())?,
+            source: compile_attribute("source", config.source.as_deref())?,
+            event_type: compile_attribute("type", config.event_type.as_deref())?,
+            subject: compile_attribute("subject", config.subject.as_deref())?,
+            time,
+        })
+    }
+
+    /// Builds the CloudEvent for a webhook. Attributes whose path selects
+    /// nothing get defaults: a random `id`, `/handler/<UUID>` as `source`, a
+    /// generic `type`, no `subject` and the receive time.
+    pub fn event(&self, event: &WebhookEvent, body: &Bytes, handler_id: Uuid) -> CloudEvent {
+        let time = self
+            .time
+            .as_ref()
+            .and_then(|path| path.query(event).first().copied().and_then(parse_time))
+            .unwrap_or(event.received_at);
+
+        CloudEvent {
+            mode: self.mode,
+            id: resolve(&self.id, event).unwrap_or_else(|| Uuid::new_v4().to_string()),
+            source: resolve(&self.source, event)
+                .unwrap_or_else(|| format!("/handler/{}", handler_id)),
+            event_type: resolve(&self.event_type, event)
+                .unwrap_or_else(|| DEFAULT_TYPE.to_string()),
+            subject: resolve(&self.subject, event),
+            time: time.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+            data_content_type: event
+                .headers
+                .get("content-type")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            data: body.clone(),
+        }
+    }
+}
+
+impl CloudEvent {
+    /// The record value and headers. Binary mode carries the attributes in
+    /// `ce_*` headers and the body as the value; structured mode writes one
+    /// JSON document. A dead-letter reason is added as the
+    /// `deadletterreason` extension attribute.
+    pub fn record(&self, dead_letter_reason: Option<&str>) -> (Vec<u8>, Vec<RecordHeader>) {
+        match self.mode {
+            CloudEventsMode::Binary => {
+                let mut headers: Vec<RecordHeader> = self
+                    .attributes(dead_letter_reason)
+                    .into_iter()
+                    .map(|(name, value)| (format!("ce_{}", name), value.into_bytes()))
+                    .collect();
+                if let Some(content_type) = &self.data_content_type {
+                    headers.push((
+                        "content-type".to_string(),
+                        content_type.clone().into_bytes(),
+                    ));
+                }
+                (self.data.to_vec(), headers)
+            }
+            CloudEventsMode::Structured => {
+                let mut document: serde_json::Map<String, Value> = self
+                    .attributes(dead_letter_reason)
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), Value::String(value)))
+                    .collect();
+                match serde_json::from_slice::<Value>(&self.data) {
+                    Ok(data) => {
+                        let content_type = self
+                            .data_content_type
+                            .as_deref()
+                            .unwrap_or("application/json");
+                        document.insert("datacontenttype".to_string(), json!(content_type));
+                        document.insert("data".to_string(), data);
+                    }
+                    Err(_) => {
+                        if let Some(content_type) = &self.data_content_type {
+                            document.insert("datacontenttype".to_string(), json!(content_type));
+                        }
+                        document
+                            .insert("data_base64".to_string(), json!(BASE64.encode(&self.data)));
+                    }
+                }
+                let headers = vec![(
+                    "content-type".to_string(),
+                    STRUCTURED_CONTENT_TYPE.as_bytes().to_vec(),
+                )];
+                (Value::Object(document).to_string().into_bytes(), headers)
+            }
+        }
+    }
+
+    /// Replaces the data, e.g. with a transformed JSON body
+    pub fn set_json_data(&mut self, data: Bytes) {
+        self.data_content_type = Some("application/json".to_string());
+        self.data = data;
+    }
+
+    fn attributes(&self, dead_letter_reason: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut attributes = vec![
+            ("specversion", SPEC_VERSION.to_string()),
+            ("id", self.id.clone()),
+            ("source", self.source.clone()),
+            ("type", self.event_type.clone()),
+        ];
+        if let Some(subject) = &self.subject {
+            attributes.push(("subject", subject.clone()));
+        }
+        attributes.push(("time", self.time.clone()));
+        if let Some(reason) = dead_letter_reason {
+            attributes.push(("deadletterreason", reason.to_string()));
+        }
+        attributes
+    }
+}
+
+/// A `$`-prefixed value is a path into the request; anything else is literal
+fn compile_attribute(name: &str, value: Option<&str>) -> Result<Option<Attribute>> {
+    match value {
+        None => Ok(None),
+        Some("") => Err(anyhow!("cloudEvents {} must not be empty", name)),
+        Some(value) if value.starts_with('$') => {
+            Ok(Some(Attribute::Path(parse_request_path(value)?)))
+        }
+        Some(value) => Ok(Some(Attribute::Literal(value.to_string()))),
+    }
+}
+
+fn resolve(attribute: &Option<Attribute>, event: &WebhookEvent) -> Option<String> {
+    match attribute.as_ref()? {
+        Attribute::Literal(value) => Some(value.clone()),
+        Attribute::Path(path) => match path.query(event).first()? {
+            Value::String(s) if !s.is_empty() => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            Value::Bool(b) => Some(b.to_string()),
+            _ => None,
+        },
+    }
+}
+
+/// An RFC 3339 string or Unix seconds
+fn parse_time(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(s) => DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|t| t.with_timezone(&Utc)),
+        Value::Number(n) => DateTime::from_timestamp(n.as_i64()?, 0),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(value: Value) -> CloudEventsConfig {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn header<'a>(headers: &'a [RecordHeader], name: &str) -> Option<&'a str> {
+        headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| std::str::from_utf8(value).unwrap())
+    }
+
+    #[test]
+    fn test_cloud_events() {
+        let handler_id = Uuid::new_v4();
+        let body = json!({ "event": "meeting.started", "event_ts": 1700000000, "payload": { "object": { "id": 42 } } });
+        let bytes = Bytes::from(body.to_string());
+        let headers = json!({ "content-type": "application/json" });
+        let empty = json!({});
+        let event = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &empty,
+            method: &empty,
+            received_at: Utc::now(),
+        };
+
+        let binary = CloudEvents::compile(&config(json!({
+            "type": "$.event",
+            "source": "https://zoom.us",
+            "subject": "$.payload.object.id",
+            "time": "$.event_ts"
+        })))
+        .unwrap()
+        .event(&event, &bytes, handler_id);
+        let (value, record_headers) = binary.record(None);
+        assert_eq!(value, bytes.to_vec());
+        assert_eq!(header(&record_headers, "ce_specversion"), Some("1.0"));
+        assert_eq!(header(&record_headers, "ce_type"), Some("meeting.started"));
+        assert_eq!(
+            header(&record_headers, "ce_source"),
+            Some("https://zoom.us")
+        );
+        assert_eq!(header(&record_headers, "ce_subject"), Some("42"));
+        assert_eq!(
+            header(&record_headers, "ce_time"),
+            Some("2023-11-14T22:13:20Z")
+        );
+        assert_eq!(
+            header(&record_headers, "content-type"),
+            Some("application/json")
+        );
+        assert!(Uuid::parse_str(header(&record_headers, "ce_id").unwrap()).is_ok());
+
+        let structured =
+            CloudEvents::compile(&config(json!({ "mode": "structured", "id": "$.missing" })))
+                .unwrap();
+        let (value, record_headers) = structured
+            .event(&event, &bytes, handler_id)
+            .record(Some("Matched route 1 with action dead_letter"));
+        let document: Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(
+            header(&record_headers, "content-type"),
+            Some("application/cloudevents+json")
+        );
+        assert_eq!(
+            document["source"],
+            json!(format!("/handler/{}", handler_id))
+        );
+        assert_eq!(document["type"], json!("com.example.webhooks.received"));
+        assert_eq!(document["data"], body);
+        assert_eq!(
+            document["deadletterreason"],
+            json!("Matched route 1 with action dead_letter")
+        );
+        assert!(document.get("subject").is_none());
+
+        let (value, _) = structured
+            .event(&event, &Bytes::from_static(&[0xff, 0x00]), handler_id)
+            .record(None);
+        let document: Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(document["data_base64"], json!("/wA="));
+
+        assert!(CloudEvents::compile(&config(json!({ "time": "now" }))).is_err());
+        assert!(CloudEvents::compile(&config(json!({ "type": "" }))).is_err());
+        assert!(CloudEvents::compile(&config(json!({ "subject": "$[" }))).is_err());
+    }
+
+    /// The attributes of a record in either mode, without the data
+    fn record_attributes(
+        mode: CloudEventsMode,
+        (value, headers): (Vec<u8>, Vec<RecordHeader>),
+    ) -> Value {
+        match mode {
+            CloudEventsMode::Binary => headers
+                .iter()
+                .filter_map(|(name, value)| {
+                    let name = name.strip_prefix("ce_")?;
+                    Some((name.to_string(), json!(std::str::from_utf8(value).unwrap())))
+                })
+                .collect::<serde_json::Map<_, _>>()
+                .into(),
+            CloudEventsMode::Structured => {
+                let mut document: Value = serde_json::from_slice(&value).unwrap();
+                let object = document.as_object_mut().unwrap();
+                object.remove("data");
+                object.remove("datacontenttype");
+                document
+            }
+        }
+    }
+
+    #[test]
+    fn test_cloud_event_attributes() {
+        let handler_id = Uuid::new_v4();
+        let received_at = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let body = json!({
+            "event": "meeting.started",
+            "account": "",
+            "payload": { "object": { "id": "m-1", "host": { "id": "h-1" } } },
+            "event_ts": "yesterday",
+            "sent_at": "2026-02-03T04:05:06.789+01:00",
+            "flags": [true]
+        });
+        let bytes = Bytes::from(body.to_string());
+        let headers = json!({ "x-zm-request-id": "req-7" });
+        let empty = json!({});
+        let event = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &empty,
+            method: &empty,
+            received_at,
+        };
+
+        for mode in [CloudEventsMode::Binary, CloudEventsMode::Structured] {
+            let mut config = config(json!({
+                "id": "$request.headers['x-zm-request-id']",
+                "source": "zoom",
+                "type": "$.event",
+                "subject": "$.flags[0]",
+                "time": "$.sent_at"
+            }));
+            config.mode = mode;
+            let cloud_events = CloudEvents::compile(&config).unwrap();
+
+            // `$`-paths select from the request, anything else is literal
+            let record = cloud_events.event(&event, &bytes, handler_id).record(None);
+            assert_eq!(
+                record_attributes(mode, record),
+                json!({
+                    "specversion": "1.0",
+                    "id": "req-7",
+                    "source": "zoom",
+                    "type": "meeting.started",
+                    "subject": "true",
+                    "time": "2026-02-03T03:05:06.789Z"
+                }),
+                "{:?}",
+                mode
+            );
+
+            // Paths that select nothing, an empty string or an object fall back to defaults,
+            // and unparseable or missing timestamps to the receive time
+            for (subject, time) in [
+                ("$.missing", "$.event_ts"),
+                ("$.account", "$.missing"),
+                ("$.payload", "$.flags"),
+            ] {
+                config.id = Some("$.missing".to_string());
+                config.source = Some("$.account".to_string());
+                config.event_type = Some("$.payload.object".to_string());
+                config.subject = Some(subject.to_string());
+                config.time = Some(time.to_string());
+                let cloud_event = CloudEvents::compile(&config)
+                    .unwrap()
+                    .event(&event, &bytes, handler_id);
+                let attributes = record_attributes(mode, cloud_event.record(None));
+                assert!(Uuid::parse_str(attributes["id"].as_str().unwrap()).is_ok());
+                assert_eq!(
+                    attributes["source"],
+                    json!(format!("/handler/{}", handler_id))
+                );
+                assert_eq!(attributes["type"], json!(DEFAULT_TYPE));
+                assert!(attributes.get("subject").is_none());
+                assert_eq!(attributes["time"], json!("2026-01-01T00:00:00Z"));
+            }
+
+            // A literal may contain `$` past its first character
+            config.source = Some("urn:$.event".to_string());
+            let record = CloudEvents::compile(&config)
+                .unwrap()
+                .event(&event, &bytes, handler_id)
+                .record(None);
+            assert_eq!(
+                record_attributes(mode, record)["source"],
+                json!("urn:$.event")
+            );
+        }
+
+        // Without a request content type, binary mode sets none and structured
+        // mode declares JSON data
+        let cloud_event = CloudEvents::compile(&config(json!({})))
+            .unwrap()
+            .event(&event, &bytes, handler_id);
+        let (value, headers) = cloud_event.record(None);
+        assert_eq!(value, bytes.to_vec());
+        assert_eq!(header(&headers, "content-type"), None);
+        let cloud_event = CloudEvents::compile(&config(json!({ "mode": "structured" })))
+            .unwrap()
+            .event(&event, &bytes, handler_id);
+        let (value, _) = cloud_event.record(None);
+        let document: Value = serde_json::from_slice(&value).unwrap();
+        assert_eq!(document["datacontenttype"], json!("application/json"));
+        assert_eq!(document["data"], body);
+    }
+}
@@ -10,6 +10,9 @@ pub struct Config {
     pub api_signing_key: String,
     pub external_url: String,
     pub namespace: String,
+    pub schema_registry_url: Option<String>,
+    pub schema_registry_username: Option<String>,
+    pub schema_registry_password: Option<String>,
 }
 
 impl Config {
@@ -23,12 +26,13 @@ impl Config {
                 .context("KAFKA_SASL_PASSWORD must be set")?,
             kafka_sasl_mechanism: env::var("KAFKA_SASL_MECHANISM")
                 .unwrap_or_else(|_| "SCRAM-SHA-512".to_string()),
-            api_signing_key: env::var("API_SIGNING_KEY")
-                .context("API_SIGNING_KEY must be set")?,
+            api_signing_key: env::var("API_SIGNING_KEY").context("API_SIGNING_KEY must be set")?,
             external_url: env::var("EXTERNAL_URL")
                 .unwrap_or_else(|_| "http://localhost:8080".to_string()),
-            namespace: env::var("NAMESPACE")
-                .unwrap_or_else(|_| "default".to_string()),
+            namespace: env::var("NAMESPACE").unwrap_or_else(|_| "default".to_string()),
+            schema_registry_url: env::var("SCHEMA_REGISTRY_URL").ok(),
+            schema_registry_username: env::var("SCHEMA_REGISTRY_USERNAME").ok(),
+            schema_registry_password: env::var("SCHEMA_REGISTRY_PASSWORD").ok(),
         })
     }
-}
\ No newline at end of file
+}
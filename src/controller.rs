@@ -1,77 +1,604 @@
-use futures::StreamExt;
+use futures::{StreamExt, TryStreamExt};
+use k8s_openapi::api::core::v1::{ConfigMap, Secret};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
 use kube::{
-    api::ListParams,
-    runtime::{watcher, WatchStreamExt},
-    Api, Client,
+    api::{Patch, PatchParams},
+    runtime::{
+        controller::{Action, Controller},
+        reflector::{self, ObjectRef, Store},
+        watcher::{self, watcher, Event},
+        WatchStreamExt,
+    },
+    Api, Client, ResourceExt,
 };
-use std::collections::HashMap;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::sync::Arc;
-use tokio::sync::RwLock;
+use std::time::Duration;
 use uuid::Uuid;
 
-use crate::crd::WebhookHandler;
-use crate::state::HandlerConfig;
+use crate::crd::{
+    ChallengeMode, InvalidPayloadPolicy, PartialFailurePolicy, WebhookHandler, WebhookHandlerSpec,
+    WebhookHandlerStatus,
+};
+use crate::filter::Plan;
+use crate::kafka::is_valid_topic_name;
+use crate::output::Output;
+use crate::schema::{resolve_config_map_ref, PayloadSchema, ResolvedSchema};
+use crate::secrets::{resolve_secret_ref, ResolvedSecret};
+use crate::signature::{check_secret, VerificationKey};
+use crate::state::{AppState, HandlerConfig};
+
+const FIELD_MANAGER: &str = "webhook-operator";
+
+/// Interval after which a handler is reconciled again, so that topics
+/// created or deleted out of band are reflected in its status
+const RESYNC_INTERVAL: Duration = Duration::from_secs(300);
+const ERROR_REQUEUE_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Error {
+    Kube(kube::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Kube(e) => write!(f, "Kubernetes API error: {}", e),
+        }
+    }
+}
 
-pub async fn watch_handlers(
+impl std::error::Error for Error {}
+
+impl From<kube::Error> for Error {
+    fn from(e: kube::Error) -> Self {
+        Error::Kube(e)
+    }
+}
+
+struct Context {
     client: Client,
-    namespace: String,
-    handlers: Arc<RwLock<HashMap<Uuid, HandlerConfig>>>,
+    state: AppState,
+    store: Store<WebhookHandler>,
+}
+
+pub async fn watch_handlers(client: Client, state: AppState) {
+    tracing::info!(
+        "Starting WebhookHandler controller for namespace: {}",
+        state.namespace
+    );
+
+    let api: Api<WebhookHandler> = Api::namespaced(client.clone(), &state.namespace);
+    let (reader, writer) = reflector::store();
+
+    // Every replica keeps its own registry, so deletions are applied straight
+    // from the watch stream rather than through a finalizer. The watcher
+    // re-lists after every disconnect, and handlers deleted while we were away
+    // are pruned once that list completes.
+    let handlers = state.handlers.clone();
+    let store = reader.clone();
+    let stream = watcher(api, watcher::Config::default())
+        .default_backoff()
+        .reflect(writer)
+        .and_then(move |event| {
+            let handlers = handlers.clone();
+            let store = store.clone();
+            async move {
+                prune_handlers(&mut *handlers.write().await, &store, &event);
+                Ok(event)
+            }
+        })
+        .touched_objects();
+
+    let context = Arc::new(Context {
+        client: client.clone(),
+        state: state.clone(),
+        store: reader.clone(),
+    });
+
+    // Re-reconcile handlers whose signing key Secret changed
+    let secrets: Api<Secret> = Api::namespaced(client, &state.namespace);
+    let store = reader.clone();
+    let secret_mapper = move |secret: Secret| {
+        let name = secret.name_any();
+        store
+            .state()
+            .iter()
+            .filter(|handler| {
+                handler
+                    .spec
+                    .signing_keys()
+                    .iter()
+                    .filter_map(|key| key.secret_ref.as_ref())
+                    .any(|secret_ref| secret_ref.name == name)
+            })
+            .map(|handler| ObjectRef::from_obj(handler.as_ref()))
+            .collect::<Vec<_>>()
+    };
+
+    // Re-reconcile handlers whose payload schema ConfigMap changed
+    let config_maps: Api<ConfigMap> = Api::namespaced(context.client.clone(), &state.namespace);
+    let store = reader.clone();
+    let config_map_mapper = move |config_map: ConfigMap| {
+        let name = config_map.name_any();
+        store
+            .state()
+            .iter()
+            .filter(|handler| {
+                handler
+                    .spec
+                    .payload_schema
+                    .as_ref()
+                    .and_then(|schema| schema.config_map_ref.as_ref())
+                    .is_some_and(|config_map_ref| config_map_ref.name == name)
+            })
+            .map(|handler| ObjectRef::from_obj(handler.as_ref()))
+            .collect::<Vec<_>>()
+    };
+
+    Controller::for_stream(stream, reader)
+        .watches(secrets, watcher::Config::default(), secret_mapper)
+        .watches(config_maps, watcher::Config::default(), config_map_mapper)
+        .run(reconcile, error_policy, context)
+        .for_each(|result| async move {
+            match result {
+                Ok((object, _)) => tracing::debug!("Reconciled {}", object.name),
+                Err(e) => tracing::error!("Reconcile error: {}", e),
+            }
+        })
+        .await;
+
+    tracing::warn!("Handler controller stream ended");
+}
+
+/// Removes handlers that no longer exist in the cluster from the registry
+fn prune_handlers(
+    map: &mut HashMap<Uuid, HandlerConfig>,
+    store: &Store<WebhookHandler>,
+    event: &Event<WebhookHandler>,
 ) {
-    tracing::info!("Starting WebhookHandler watcher for namespace: {}", namespace);
-
-    let api: Api<WebhookHandler> = Api::namespaced(client, &namespace);
-
-    // Load existing handlers first
-    match api.list(&ListParams::default()).await {
-        Ok(list) => {
-            let mut map = handlers.write().await;
-            for handler in list.items {
-                if let Some(uuid) = parse_uuid_from_name(&handler.metadata.name) {
-                    let config = HandlerConfig {
-                        topic: handler.spec.topic.clone(),
-                        signature_key: handler.spec.signature_key.clone(),
-                        filters: handler.spec.filters.clone(),
-                        routes: handler.spec.routes.clone(),
-                    };
-                    map.insert(uuid, config);
-                    tracing::info!("Loaded existing handler: {} -> {}", uuid, handler.spec.topic);
+    match event {
+        Event::Delete(handler) => {
+            if let Some(uuid) = parse_uuid_from_name(&handler.metadata.name) {
+                if map.remove(&uuid).is_some() {
+                    tracing::info!("Handler removed: {}", uuid);
                 }
             }
-            tracing::info!("Loaded {} existing handlers", map.len());
         }
-        Err(e) => {
-            tracing::error!("Failed to list existing handlers: {}", e);
-        }
-    }
-
-    // Watch for changes
-    let stream = watcher(api, watcher::Config::default()).applied_objects();
-    futures::pin_mut!(stream);
-
-    while let Some(result) = stream.next().await {
-        match result {
-            Ok(handler) => {
-                if let Some(uuid) = parse_uuid_from_name(&handler.metadata.name) {
-                    let config = HandlerConfig {
-                        topic: handler.spec.topic.clone(),
-                        signature_key: handler.spec.signature_key.clone(),
-                        filters: handler.spec.filters.clone(),
-                        routes: handler.spec.routes.clone(),
-                    };
-                    handlers.write().await.insert(uuid, config);
-                    tracing::info!("Handler updated: {} -> {}", uuid, handler.spec.topic);
+        Event::InitDone => {
+            let live: HashSet<Uuid> = store
+                .state()
+                .iter()
+                .filter_map(|handler| parse_uuid_from_name(&handler.metadata.name))
+                .collect();
+            map.retain(|uuid, _| {
+                let keep = live.contains(uuid);
+                if !keep {
+                    tracing::info!("Handler removed while watch was disconnected: {}", uuid);
+                }
+                keep
+            });
+        }
+        Event::Apply(_) | Event::Init | Event::InitApply(_) => {}
+    }
+}
+
+/// Adds a reconciled handler to the registry unless it was deleted meanwhile.
+/// The reflector store is updated before `prune_handlers` takes the registry
+/// lock, so checking it under that lock cannot resurrect a pruned handler.
+fn load_handler(
+    map: &mut HashMap<Uuid, HandlerConfig>,
+    store: &Store<WebhookHandler>,
+    handler: &WebhookHandler,
+    uuid: Uuid,
+    config: HandlerConfig,
+) -> bool {
+    let live = store
+        .get(&ObjectRef::from_obj(handler))
+        .is_some_and(|current| current.metadata.deletion_timestamp.is_none());
+    if live {
+        map.insert(uuid, config);
+    }
+    live
+}
+
+async fn reconcile(handler: Arc<WebhookHandler>, ctx: Arc<Context>) -> Result<Action, Error> {
+    let name = handler.name_any();
+    let Some(uuid) = parse_uuid_from_name(&handler.metadata.name) else {
+        tracing::warn!("Ignoring WebhookHandler with unexpected name: {}", name);
+        return Ok(Action::await_change());
+    };
+
+    let generation = handler.metadata.generation;
+    let previous = handler
+        .status
+        .as_ref()
+        .map(|s| s.conditions.as_slice())
+        .unwrap_or_default();
+
+    let compiled = compile_spec(&handler.spec);
+    let valid = validation_check(&compiled);
+    let namespace = handler
+        .namespace()
+        .unwrap_or_else(|| ctx.state.namespace.clone());
+    let (secret, signature_keys) = resolve_secret(&ctx.client, &namespace, &handler.spec).await?;
+    let (schema, payload_schema) =
+        resolve_payload_schema(&ctx.client, &namespace, &handler.spec).await?;
+    let topics = check_topics(&ctx.state, &handler.spec).await;
+
+    let serving =
+        valid.status == Some(true) && secret.status == Some(true) && schema.status == Some(true);
+    let ready = serving && topics.status != Some(false);
+
+    if let (true, Ok((plan, output))) = (serving, compiled) {
+        let output = output.with_resolved_payload_schema(payload_schema);
+        let config = handler_config(
+            &handler.spec,
+            signature_keys,
+            Arc::new(plan),
+            Arc::new(output),
+        );
+        let topic = config.topic.clone();
+        if load_handler(
+            &mut *ctx.state.handlers.write().await,
+            &ctx.store,
+            &handler,
+            uuid,
+            config,
+        ) {
+            tracing::info!("Handler updated: {} -> {}", uuid, topic);
+        } else {
+            tracing::info!(
+                "Handler {} was deleted while being reconciled; not loading it",
+                uuid
+            );
+        }
+    } else if ctx.state.handlers.write().await.remove(&uuid).is_some() {
+        tracing::warn!("Handler {} is no longer valid and was unloaded", uuid);
+    }
+
+    let status = WebhookHandlerStatus {
+        handler_url: Some(format!("{}/handler/{}", ctx.state.external_url, uuid)),
+        ready,
+        observed_generation: generation,
+        conditions: vec![
+            valid.into_condition("Valid", generation, previous),
+            topics.into_condition("TopicExists", generation, previous),
+            secret.into_condition("SecretResolved", generation, previous),
+            schema.into_condition("SchemaResolved", generation, previous),
+        ],
+    };
+
+    if handler.status.as_ref() != Some(&status) {
+        let api: Api<WebhookHandler> = Api::namespaced(ctx.client.clone(), &namespace);
+        api.patch_status(
+            &name,
+            &PatchParams::apply(FIELD_MANAGER),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await?;
+        tracing::debug!("Updated status for handler {}: ready={}", uuid, ready);
+    }
+
+    Ok(Action::requeue(RESYNC_INTERVAL))
+}
+
+fn error_policy(handler: Arc<WebhookHandler>, error: &Error, _ctx: Arc<Context>) -> Action {
+    tracing::warn!(
+        "Failed to reconcile handler {}: {}",
+        handler.name_any(),
+        error
+    );
+    Action::requeue(ERROR_REQUEUE_INTERVAL)
+}
+
+/// Outcome of a single status check; `None` means the check could not be run
+struct Check {
+    status: Option<bool>,
+    reason: &'static str,
+    message: String,
+}
+
+impl Check {
+    fn into_condition(
+        self,
+        type_: &str,
+        generation: Option<i64>,
+        previous: &[Condition],
+    ) -> Condition {
+        let status = match self.status {
+            Some(true) => "True",
+            Some(false) => "False",
+            None => "Unknown",
+        };
+
+        // Only move the transition time when the condition actually flips
+        let last_transition_time = previous
+            .iter()
+            .find(|c| c.type_ == type_ && c.status == status)
+            .map(|c| c.last_transition_time.clone())
+            .unwrap_or_else(|| Time(chrono::Utc::now()));
+
+        Condition {
+            type_: type_.to_string(),
+            status: status.to_string(),
+            reason: self.reason.to_string(),
+            message: self.message,
+            observed_generation: generation,
+            last_transition_time,
+        }
+    }
+}
+
+/// Validates the spec and compiles its filters and routes
+fn compile_spec(spec: &WebhookHandlerSpec) -> Result<(Plan, Output), String> {
+    if !is_valid_topic_name(&spec.topic) {
+        Err(format!("Invalid topic name: {:?}", spec.topic))
+    } else if spec.signature_key.is_some() && spec.signature_key_secret_ref.is_some() {
+        Err("signatureKey and signatureKeySecretRef are mutually exclusive".to_string())
+    } else if spec
+        .signing_keys()
+        .iter()
+        .any(|k| k.key.is_some() == k.secret_ref.is_some())
+    {
+        Err("Each signing key must set exactly one of key or secretRef".to_string())
+    } else if spec.challenge_mode == Some(ChallengeMode::Zoom) && spec.signing_keys().is_empty() {
+        Err("Zoom challenge mode requires a signature key".to_string())
+    } else if spec.challenge_mode == Some(ChallengeMode::Meta) && spec.verify_token.is_none() {
+        Err("Meta challenge mode requires a verify token".to_string())
+    } else if let Some(topic) = spec
+        .routes
+        .iter()
+        .flatten()
+        .flat_map(|route| &route.mapping)
+        .map(|mapping| &mapping.topic)
+        .find(|topic| !is_valid_topic_name(topic))
+    {
+        Err(format!("Invalid route topic name: {:?}", topic))
+    } else if let Some(topic) = spec
+        .dead_letter_topic
+        .as_ref()
+        .filter(|t| !is_valid_topic_name(t))
+    {
+        Err(format!("Invalid dead-letter topic name: {:?}", topic))
+    } else {
+        let plan = Plan::compile(
+            spec.filters.as_deref().unwrap_or_default(),
+            spec.routes.as_deref().unwrap_or_default(),
+        )
+        .and_then(|plan| plan.with_message_key(spec))
+        .map_err(|e| e.to_string())?;
+        let output = Output::compile(spec).map_err(|e| e.to_string())?;
+        if spec.dead_letter_topic.is_none() && plan.uses_dead_letter() {
+            return Err(
+                "onMissing: dead_letter and action: dead_letter require deadLetterTopic"
+                    .to_string(),
+            );
+        }
+        if spec.dead_letter_topic.is_none()
+            && spec.partial_failure == PartialFailurePolicy::DeadLetter
+        {
+            return Err("partialFailure: dead_letter requires deadLetterTopic".to_string());
+        }
+        let on_invalid = spec.payload_schema.as_ref().map(|schema| schema.on_invalid);
+        if spec.dead_letter_topic.is_none() && on_invalid == Some(InvalidPayloadPolicy::DeadLetter)
+        {
+            return Err(
+                "payloadSchema onInvalid: dead_letter requires deadLetterTopic".to_string(),
+            );
+        }
+        Ok((plan, output))
+    }
+}
+
+fn validation_check<T>(compiled: &Result<T, String>) -> Check {
+    match compiled {
+        Ok(_) => Check {
+            status: Some(true),
+            reason: "SpecValid",
+            message: "Spec is valid".to_string(),
+        },
+        Err(message) => Check {
+            status: Some(false),
+            reason: "InvalidSpec",
+            message: message.clone(),
+        },
+    }
+}
+
+/// Resolves the handler's unexpired signing keys, inline or from Secrets
+async fn resolve_secret(
+    client: &Client,
+    namespace: &str,
+    spec: &WebhookHandlerSpec,
+) -> Result<(Check, Vec<VerificationKey>), Error> {
+    let configured = spec.signing_keys();
+    if configured.is_empty() {
+        let check = Check {
+            status: Some(true),
+            reason: "NotRequired",
+            message: "Signature verification is disabled".to_string(),
+        };
+        return Ok((check, Vec::new()));
+    }
+
+    let now = chrono::Utc::now();
+    let mut keys = Vec::new();
+    let mut missing = Vec::new();
+    let mut invalid = Vec::new();
+    for signing_key in configured {
+        let not_after = signing_key.not_after.map(|t| t.0);
+        if not_after.is_some_and(|t| t <= now) {
+            continue;
+        }
+        let secret = if let Some(secret_ref) = &signing_key.secret_ref {
+            match resolve_secret_ref(client.clone(), namespace, secret_ref).await? {
+                ResolvedSecret::Found(secret) => secret,
+                ResolvedSecret::Missing(message) => {
+                    missing.push(message);
+                    continue;
                 }
             }
-            Err(e) => {
-                tracing::error!("Watch error: {}", e);
+        } else if let Some(secret) = signing_key.key {
+            secret
+        } else {
+            continue;
+        };
+        match check_secret(spec.signature_scheme, &secret) {
+            Ok(()) => keys.push(VerificationKey { secret, not_after }),
+            Err(e) => invalid.push(e.to_string()),
+        }
+    }
+
+    let check = if !missing.is_empty() {
+        Check {
+            status: Some(false),
+            reason: "SecretNotFound",
+            message: missing.join("; "),
+        }
+    } else if !invalid.is_empty() {
+        Check {
+            status: Some(false),
+            reason: "InvalidSecret",
+            message: invalid.join("; "),
+        }
+    } else if keys.is_empty() {
+        // Never fall back to accepting unsigned requests
+        Check {
+            status: Some(false),
+            reason: "KeysExpired",
+            message: "All signing keys have expired".to_string(),
+        }
+    } else {
+        Check {
+            status: Some(true),
+            reason: "KeysResolved",
+            message: format!("{} active signing key(s)", keys.len()),
+        }
+    };
+    Ok((check, keys))
+}
+
+/// Loads and compiles a `payloadSchema` kept in a ConfigMap; inline schemas
+/// are compiled with the rest of the spec
+async fn resolve_payload_schema(
+    client: &Client,
+    namespace: &str,
+    spec: &WebhookHandlerSpec,
+) -> Result<(Check, Option<PayloadSchema>), Error> {
+    let Some((config, config_map_ref)) = spec
+        .payload_schema
+        .as_ref()
+        .and_then(|config| Some((config, config.config_map_ref.as_ref()?)))
+    else {
+        let check = Check {
+            status: Some(true),
+            reason: "NotRequired",
+            message: "No payload schema ConfigMap is referenced".to_string(),
+        };
+        return Ok((check, None));
+    };
+
+    let schema = match resolve_config_map_ref(client.clone(), namespace, config_map_ref).await? {
+        ResolvedSchema::Found(schema) => schema,
+        ResolvedSchema::Missing(message) => {
+            let check = Check {
+                status: Some(false),
+                reason: "ConfigMapNotFound",
+                message,
+            };
+            return Ok((check, None));
+        }
+    };
+
+    Ok(match PayloadSchema::compile(&schema, config.on_invalid) {
+        Ok(payload_schema) => {
+            let check = Check {
+                status: Some(true),
+                reason: "SchemaResolved",
+                message: format!(
+                    "Payload schema loaded from ConfigMap {}",
+                    config_map_ref.name
+                ),
+            };
+            (check, Some(payload_schema))
+        }
+        Err(e) => {
+            let check = Check {
+                status: Some(false),
+                reason: "InvalidSchema",
+                message: format!("ConfigMap {}: {}", config_map_ref.name, e),
+            };
+            (check, None)
+        }
+    })
+}
+
+async fn check_topics(state: &AppState, spec: &WebhookHandlerSpec) -> Check {
+    let existing = match state.kafka_producer.list_topics().await {
+        Ok(topics) => topics,
+        Err(e) => {
+            return Check {
+                status: None,
+                reason: "KafkaUnavailable",
+                message: e.to_string(),
             }
         }
+    };
+
+    let mut missing: Vec<&str> = std::iter::once(&spec.topic)
+        .chain(
+            spec.routes
+                .iter()
+                .flatten()
+                .flat_map(|r| r.mapping.iter().map(|m| &m.topic)),
+        )
+        .chain(&spec.dead_letter_topic)
+        .map(String::as_str)
+        .filter(|topic| !existing.contains(*topic))
+        .collect();
+    missing.sort_unstable();
+    missing.dedup();
+
+    if missing.is_empty() {
+        Check {
+            status: Some(true),
+            reason: "TopicsFound",
+            message: "All topics exist".to_string(),
+        }
+    } else {
+        Check {
+            status: Some(false),
+            reason: "TopicsMissing",
+            message: format!("Missing topics: {}", missing.join(", ")),
+        }
     }
+}
 
-    tracing::warn!("Handler watcher stream ended");
+fn handler_config(
+    spec: &WebhookHandlerSpec,
+    signature_keys: Vec<VerificationKey>,
+    plan: Arc<Plan>,
+    output: Arc<Output>,
+) -> HandlerConfig {
+    HandlerConfig {
+        topic: spec.topic.clone(),
+        signature_keys,
+        signature_scheme: spec.signature_scheme,
+        challenge_mode: spec.challenge_mode,
+        verify_token: spec.verify_token.clone(),
+        plan,
+        dead_letter_topic: spec.dead_letter_topic.clone(),
+        routing_mode: spec.routing_mode,
+        include_default_topic: spec.include_default_topic,
+        partial_failure: spec.partial_failure,
+        output,
+    }
 }
 
-fn parse_uuid_from_name(name: &Option<String>) -> Option<Uuid> {
+pub fn parse_uuid_from_name(name: &Option<String>) -> Option<Uuid> {
     name.as_ref()?
         .strip_prefix("handler-")
         .and_then(|s| Uuid::parse_str(s).ok())
@@ -80,6 +607,48 @@ fn parse_uuid_from_name(name: &Option<String>) -> Option<Uuid> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::crd::{
+        Filter, FilterOperator, FilterRule, FilterValue, KeyFallback, MatchMode, MessageFormat,
+        MissingPolicy, PartialFailurePolicy, RoutingMode, SignatureScheme,
+    };
+    use kube::runtime::watcher::Event;
+
+    fn spec(topic: &str) -> WebhookHandlerSpec {
+        WebhookHandlerSpec {
+            topic: topic.to_string(),
+            signature_key: None,
+            signature_key_secret_ref: None,
+            signature_keys: None,
+            signature_scheme: SignatureScheme::Default,
+            challenge_mode: None,
+            verify_token: None,
+            filters: None,
+            routes: None,
+            dead_letter_topic: None,
+            routing_mode: RoutingMode::FirstMatch,
+            include_default_topic: false,
+            partial_failure: PartialFailurePolicy::Fail,
+            key_path: None,
+            key_template: None,
+            key_fallback: KeyFallback::HandlerId,
+            null_key: false,
+            message_format: MessageFormat::Envelope,
+            cloud_events: None,
+            serializer: None,
+            payload_schema: None,
+            transform: None,
+        }
+    }
+
+    fn validate_spec(spec: &WebhookHandlerSpec) -> Check {
+        validation_check(&compile_spec(spec))
+    }
+
+    fn handler(uuid: Uuid, topic: &str) -> WebhookHandler {
+        let mut handler = WebhookHandler::new(&format!("handler-{}", uuid), spec(topic));
+        handler.metadata.namespace = Some("default".to_string());
+        handler
+    }
 
     #[test]
     fn test_parse_uuid_from_name() {
@@ -91,7 +660,305 @@ mod tests {
     #[test]
     fn test_parse_uuid_from_name_invalid() {
         assert_eq!(parse_uuid_from_name(&Some("invalid".to_string())), None);
-        assert_eq!(parse_uuid_from_name(&Some("handler-invalid".to_string())), None);
+        assert_eq!(
+            parse_uuid_from_name(&Some("handler-invalid".to_string())),
+            None
+        );
         assert_eq!(parse_uuid_from_name(&None), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_delete_event_removes_handler() {
+        let uuid = Uuid::new_v4();
+        let (reader, _writer) = reflector::store();
+        let mut map = HashMap::from([(
+            uuid,
+            handler_config(&spec("topic-a"), Vec::new(), Arc::default(), Arc::default()),
+        )]);
+
+        prune_handlers(&mut map, &reader, &Event::Delete(handler(uuid, "topic-a")));
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    fn test_relist_prunes_vanished_handlers() {
+        let kept = Uuid::new_v4();
+        let vanished = Uuid::new_v4();
+        let (reader, mut writer) = reflector::store();
+        let mut map = HashMap::from([
+            (
+                kept,
+                handler_config(&spec("kept"), Vec::new(), Arc::default(), Arc::default()),
+            ),
+            (
+                vanished,
+                handler_config(
+                    &spec("vanished"),
+                    Vec::new(),
+                    Arc::default(),
+                    Arc::default(),
+                ),
+            ),
+        ]);
+
+        for event in [
+            Event::Init,
+            Event::InitApply(handler(kept, "kept")),
+            Event::InitDone,
+        ] {
+            writer.apply_watcher_event(&event);
+            prune_handlers(&mut map, &reader, &event);
+        }
+
+        assert!(map.contains_key(&kept));
+        assert!(!map.contains_key(&vanished));
+    }
+
+    #[test]
+    fn test_deleted_handler_is_not_loaded() {
+        let uuid = Uuid::new_v4();
+        let (reader, mut writer) = reflector::store();
+        let mut map = HashMap::new();
+        let config =
+            || handler_config(&spec("topic-a"), Vec::new(), Arc::default(), Arc::default());
+
+        // Deleted (and pruned) while its secrets and topics were being resolved
+        let deleted = handler(uuid, "topic-a");
+        writer.apply_watcher_event(&Event::Apply(deleted.clone()));
+        writer.apply_watcher_event(&Event::Delete(deleted.clone()));
+        prune_handlers(&mut map, &reader, &Event::Delete(deleted.clone()));
+        assert!(!load_handler(&mut map, &reader, &deleted, uuid, config()));
+        assert!(map.is_empty());
+
+        // Being deleted
+        let mut terminating = handler(uuid, "topic-a");
+        terminating.metadata.deletion_timestamp = Some(Time(chrono::Utc::now()));
+        writer.apply_watcher_event(&Event::Apply(terminating.clone()));
+        assert!(!load_handler(
+            &mut map,
+            &reader,
+            &terminating,
+            uuid,
+            config()
+        ));
+        assert!(map.is_empty());
+
+        let live = handler(uuid, "topic-a");
+        writer.apply_watcher_event(&Event::Apply(live.clone()));
+        assert!(load_handler(&mut map, &reader, &live, uuid, config()));
+        assert!(map.contains_key(&uuid));
+    }
+
+    #[test]
+    fn test_validate_spec() {
+        assert_eq!(validate_spec(&spec("zoom.events")).status, Some(true));
+        assert_eq!(validate_spec(&spec("bad topic!")).status, Some(false));
+
+        let mut invalid_filter = spec("zoom.events");
+        invalid_filter.filters = Some(vec![Filter::Rule(FilterRule {
+            path: "$.event".to_string(),
+            operator: FilterOperator::Matches,
+            value: Some(FilterValue::String("[unclosed".to_string())),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
+        })]);
+        let check = validate_spec(&invalid_filter);
+        assert_eq!(check.status, Some(false));
+        assert!(check.message.contains("Invalid regex"));
+
+        let mut dead_letter = spec("zoom.events");
+        dead_letter.routes = Some(vec![serde_json::from_value(serde_json::json!({
+            "path": "$.payload.account_id",
+            "mapping": [],
+            "onMissing": "dead_letter"
+        }))
+        .unwrap()]);
+        let check = validate_spec(&dead_letter);
+        assert_eq!(check.status, Some(false));
+        assert!(check.message.contains("deadLetterTopic"));
+
+        dead_letter.dead_letter_topic = Some("zoom.dead-letter".to_string());
+        assert_eq!(validate_spec(&dead_letter).status, Some(true));
+
+        let mut fan_out = spec("zoom.events");
+        fan_out.routing_mode = RoutingMode::FanOut;
+        fan_out.partial_failure = PartialFailurePolicy::DeadLetter;
+        let check = validate_spec(&fan_out);
+        assert_eq!(check.status, Some(false));
+        assert_eq!(
+            check.message,
+            "partialFailure: dead_letter requires deadLetterTopic"
+        );
+
+        let mut cloud_events = spec("zoom.events");
+        cloud_events.cloud_events =
+            Some(serde_json::from_value(serde_json::json!({ "type": "$.event" })).unwrap());
+        let check = validate_spec(&cloud_events);
+        assert_eq!(check.status, Some(false));
+        assert_eq!(
+            check.message,
+            "cloudEvents requires messageFormat: cloudevents"
+        );
+        cloud_events.message_format = MessageFormat::CloudEvents;
+        assert_eq!(validate_spec(&cloud_events).status, Some(true));
+
+        let mut serializer = spec("zoom.events");
+        serializer.serializer = Some(
+            serde_json::from_value(
+                serde_json::json!({ "format": "avro", "schema": "{\"type\": \"map\"}" }),
+            )
+            .unwrap(),
+        );
+        let check = validate_spec(&serializer);
+        assert_eq!(check.status, Some(false));
+        assert!(check.message.starts_with("Invalid serializer schema"));
+
+        serializer.serializer =
+            Some(serde_json::from_value(serde_json::json!({ "format": "avro" })).unwrap());
+        serializer.routes = Some(vec![serde_json::from_value(serde_json::json!({
+            "topicTemplate": "zoom.{$.event}"
+        }))
+        .unwrap()]);
+        let check = validate_spec(&serializer);
+        assert_eq!(check.status, Some(false));
+        assert_eq!(
+            check.message,
+            "serializer requires a subject when routes use topicTemplate"
+        );
+        serializer.serializer = Some(
+            serde_json::from_value(
+                serde_json::json!({ "format": "avro", "subject": "zoom-value" }),
+            )
+            .unwrap(),
+        );
+        assert_eq!(validate_spec(&serializer).status, Some(true));
+
+        let mut payload_schema = spec("zoom.events");
+        payload_schema.payload_schema = Some(
+            serde_json::from_value(serde_json::json!({
+                "schema": { "type": "object" },
+                "configMapRef": { "name": "zoom-schema", "key": "schema.json" }
+            }))
+            .unwrap(),
+        );
+        let check = validate_spec(&payload_schema);
+        assert_eq!(check.status, Some(false));
+        assert_eq!(
+            check.message,
+            "payloadSchema requires exactly one of schema and configMapRef"
+        );
+
+        payload_schema.payload_schema = Some(
+            serde_json::from_value(
+                serde_json::json!({ "schema": { "type": "object" }, "onInvalid": "dead_letter" }),
+            )
+            .unwrap(),
+        );
+        let check = validate_spec(&payload_schema);
+        assert_eq!(check.status, Some(false));
+        assert_eq!(
+            check.message,
+            "payloadSchema onInvalid: dead_letter requires deadLetterTopic"
+        );
+        payload_schema.dead_letter_topic = Some("zoom.dead-letter".to_string());
+        assert_eq!(validate_spec(&payload_schema).status, Some(true));
+    }
+
+    #[test]
+    fn test_condition_keeps_transition_time_when_unchanged() {
+        let first = validate_spec(&spec("zoom.events")).into_condition("Valid", Some(1), &[]);
+        let earlier = Time(first.last_transition_time.0 - chrono::Duration::hours(1));
+        let previous = vec![Condition {
+            last_transition_time: earlier.clone(),
+            ..first
+        }];
+
+        let same = validate_spec(&spec("zoom.events")).into_condition("Valid", Some(2), &previous);
+        assert_eq!(same.last_transition_time, earlier);
+        assert_eq!(same.observed_generation, Some(2));
+
+        let flipped =
+            validate_spec(&spec("bad topic!")).into_condition("Valid", Some(3), &previous);
+        assert_ne!(flipped.last_transition_time, earlier);
+        assert_eq!(flipped.status, "False");
+    }
+
+    #[tokio::test]
+    async fn test_config_map_schema_reload() {
+        let mut server = mockito::Server::new_async().await;
+        let client = Client::try_from(kube::Config::new(server.url().parse().unwrap())).unwrap();
+        let state = AppState {
+            handlers: Arc::default(),
+            kafka_producer: Arc::new(crate::kafka::KafkaProducer::in_memory()),
+            schema_registry: None,
+            api_signing_key: String::new(),
+            external_url: "https://hooks.example.com".to_string(),
+            namespace: "default".to_string(),
+        };
+        let (reader, mut writer) = reflector::store();
+        let context = Arc::new(Context {
+            client,
+            state: state.clone(),
+            store: reader,
+        });
+
+        let uuid = Uuid::new_v4();
+        let mut handler = handler(uuid, "zoom.events");
+        handler.spec.payload_schema = Some(
+            serde_json::from_value(
+                json!({ "configMapRef": { "name": "zoom-schema", "key": "schema.json" } }),
+            )
+            .unwrap(),
+        );
+        writer.apply_watcher_event(&Event::Apply(handler.clone()));
+        let handler = Arc::new(handler);
+        let status_path = format!(
+            "/apis/webhooks.example.com/v1/namespaces/default/webhookhandlers/handler-{}/status",
+            uuid
+        );
+        let status = server
+            .mock("PATCH", status_path.as_str())
+            .match_query(mockito::Matcher::Any)
+            .with_body(serde_json::to_string(&*handler).unwrap())
+            .expect(2)
+            .create_async()
+            .await;
+
+        let config_map = |schema: serde_json::Value| {
+            json!({
+                "apiVersion": "v1",
+                "kind": "ConfigMap",
+                "metadata": { "name": "zoom-schema", "namespace": "default" },
+                "data": { "schema.json": schema.to_string() }
+            })
+            .to_string()
+        };
+        let validate = |body: serde_json::Value| {
+            let output = state.handlers.try_read().unwrap()[&uuid].output.clone();
+            output.payload_schema().unwrap().validate(&body)
+        };
+
+        // Loaded from the ConfigMap when the handler is reconciled
+        let mock = server
+            .mock("GET", "/api/v1/namespaces/default/configmaps/zoom-schema")
+            .with_body(config_map(json!({ "required": ["event"] })))
+            .create_async()
+            .await;
+        reconcile(handler.clone(), context.clone()).await.unwrap();
+        assert!(validate(json!({ "event": "created" })).is_ok());
+        assert!(validate(json!({ "payload": {} })).is_err());
+
+        // A changed ConfigMap replaces the schema on the next reconcile
+        mock.remove_async().await;
+        server
+            .mock("GET", "/api/v1/namespaces/default/configmaps/zoom-schema")
+            .with_body(config_map(json!({ "required": ["payload"] })))
+            .create_async()
+            .await;
+        reconcile(handler, context).await.unwrap();
+        assert!(validate(json!({ "event": "created" })).is_err());
+        assert!(validate(json!({ "payload": {} })).is_ok());
+        status.assert_async().await;
+    }
+}
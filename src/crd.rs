@@ -1,3 +1,4 @@
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{Condition, Time};
 use kube::CustomResource;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
@@ -13,58 +14,632 @@ use serde::{Deserialize, Serialize};
 #[kube(printcolumn = r#"{"name":"Topic", "type":"string", "jsonPath":".spec.topic"}"#)]
 #[kube(printcolumn = r#"{"name":"URL", "type":"string", "jsonPath":".status.handlerUrl"}"#)]
 #[kube(printcolumn = r#"{"name":"Ready", "type":"boolean", "jsonPath":".status.ready"}"#)]
+#[serde(rename_all = "camelCase")]
 pub struct WebhookHandlerSpec {
     /// Default topic for events that don't match any routing rules
     pub topic: String,
-    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(alias = "signature_key", skip_serializing_if = "Option::is_none")]
     pub signature_key: Option<String>,
+    /// Secret key holding the signing key; preferred over an inline `signatureKey`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature_key_secret_ref: Option<SecretKeyRef>,
+    /// Additional signing keys, e.g. while a secret is being rotated.
+    /// A request is accepted if it matches any key that has not expired.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature_keys: Option<Vec<SigningKey>>,
+    /// How incoming webhooks are signed; defaults to X-Signature/X-Timestamp
+    #[serde(default)]
+    pub signature_scheme: SignatureScheme,
+    /// Provider endpoint-validation handshake to answer automatically
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub challenge_mode: Option<ChallengeMode>,
+    /// Token expected in Meta's `hub.verify_token` when `challengeMode` is `meta`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_token: Option<String>,
     /// Optional filters to discard events
     #[serde(skip_serializing_if = "Option::is_none")]
     pub filters: Option<Vec<Filter>>,
     /// Optional routing rules to send events to different topics
     #[serde(skip_serializing_if = "Option::is_none")]
     pub routes: Option<Vec<Route>>,
+    /// Topic for events diverted by an `onMissing` or `partialFailure` `dead_letter` policy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dead_letter_topic: Option<String>,
+    /// Whether an event goes to the first matching route or every matching route
+    #[serde(default)]
+    pub routing_mode: RoutingMode,
+    /// With `fan_out`, also publish to `topic` when routes matched
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_default_topic: bool,
+    /// What to do when some, but not all, `fan_out` destinations fail
+    #[serde(default)]
+    pub partial_failure: PartialFailurePolicy,
+    /// Path of the Kafka message key (e.g. "$.payload.object.id" or
+    /// "$request.headers['x-request-id']"); defaults to the handler UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_path: Option<String>,
+    /// Kafka message key built from the request instead of `keyPath`,
+    /// e.g. "{$.payload.account_id}:{$.payload.object.id}"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_template: Option<String>,
+    /// Key used when `keyPath` or `keyTemplate` selects nothing
+    #[serde(default, skip_serializing_if = "KeyFallback::is_default")]
+    pub key_fallback: KeyFallback,
+    /// Publish without a key, so events are spread across partitions
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub null_key: bool,
+    /// How events are written to Kafka
+    #[serde(default, skip_serializing_if = "MessageFormat::is_default")]
+    pub message_format: MessageFormat,
+    /// CloudEvents attributes and mode for `messageFormat: cloudevents`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cloud_events: Option<CloudEventsConfig>,
+    /// Encode record values against a Schema Registry schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serializer: Option<SerializerConfig>,
+    /// JSON Schema the parsed body is validated against before filtering
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload_schema: Option<PayloadSchemaConfig>,
+    /// Reshapes the body after filtering and before it is published
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transform: Option<TransformConfig>,
 }
 
+impl WebhookHandlerSpec {
+    /// All configured signing keys, including the single-key shorthands
+    pub fn signing_keys(&self) -> Vec<SigningKey> {
+        let mut keys = self.signature_keys.clone().unwrap_or_default();
+        if let Some(secret_ref) = &self.signature_key_secret_ref {
+            keys.push(SigningKey {
+                key: None,
+                secret_ref: Some(secret_ref.clone()),
+                not_after: None,
+            });
+        }
+        if let Some(key) = &self.signature_key {
+            keys.push(SigningKey {
+                key: Some(key.clone()),
+                secret_ref: None,
+                not_after: None,
+            });
+        }
+        keys
+    }
+}
+
+/// How routes pick the topics an event is published to
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutingMode {
+    /// The first route that matches picks one topic
+    #[default]
+    FirstMatch,
+    /// Every matching mapping of every route adds a topic
+    FanOut,
+}
+
+/// Response to a fan-out publish where only some topics failed
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PartialFailurePolicy {
+    /// Fail the webhook with 500 so the provider retries it
+    #[default]
+    Fail,
+    /// Acknowledge the webhook if at least one topic succeeded
+    Accept,
+    /// Send the event to `deadLetterTopic` for each failed topic
+    DeadLetter,
+}
+
+/// Shape of the Kafka records a handler publishes
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageFormat {
+    /// A JSON object holding the request headers, parsed body and receive time
+    #[default]
+    Envelope,
+    /// The original body bytes, with the request headers, handler ID, receive
+    /// time and routing decision as Kafka record headers
+    Raw,
+    /// CloudEvents 1.0, configured by `cloudEvents`
+    #[serde(rename = "cloudevents")]
+    CloudEvents,
+}
+
+/// CloudEvents output. Each attribute is a JSONPath into the request (e.g.
+/// "$.event") or, if it does not start with `$`, a literal value.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CloudEventsConfig {
+    #[serde(default)]
+    pub mode: CloudEventsMode,
+    /// Defaults to a random UUID
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    /// Defaults to "/handler/<UUID>"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+    /// Defaults to "com.example.webhooks.received"
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub event_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// JSONPath of an RFC 3339 or Unix-seconds timestamp; defaults to the receive time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+}
+
+/// CloudEvents Kafka protocol binding content mode
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CloudEventsMode {
+    /// Attributes in `ce_*` record headers, the body as the record value
+    #[default]
+    Binary,
+    /// One `application/cloudevents+json` document holding attributes and data
+    Structured,
+}
+
+/// Schema Registry encoding of record values, in the Confluent wire format
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SerializerConfig {
+    pub format: SchemaFormat,
+    /// Registry subject; defaults to "<topic>-value" for each topic published to
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    /// Schema to register under the subject; defaults to the subject's latest version
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    /// Protobuf message to encode, by full name (e.g. "shop.Order"); defaults
+    /// to the first message in the schema
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaFormat {
+    Avro,
+    Protobuf,
+    JsonSchema,
+}
+
+impl SchemaFormat {
+    /// The registry's `schemaType` name
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SchemaFormat::Avro => "AVRO",
+            SchemaFormat::Protobuf => "PROTOBUF",
+            SchemaFormat::JsonSchema => "JSON",
+        }
+    }
+}
+
+impl MessageFormat {
+    pub fn is_default(&self) -> bool {
+        *self == MessageFormat::Envelope
+    }
+}
+
+/// Kafka message key for events whose `keyPath` or `keyTemplate` selects nothing
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyFallback {
+    /// Key by the handler UUID
+    #[default]
+    HandlerId,
+    /// Publish without a key
+    Null,
+    /// Reject the webhook with 422
+    Reject,
+}
+
+impl KeyFallback {
+    pub fn is_default(&self) -> bool {
+        *self == KeyFallback::HandlerId
+    }
+}
+
+/// A signing key, set inline or read from a Secret
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SigningKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_ref: Option<SecretKeyRef>,
+    /// The key is no longer accepted after this time
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<Time>,
+}
+
+/// Reference to a key in a Secret in the handler's namespace
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+pub struct SecretKeyRef {
+    /// Name of the Secret
+    pub name: String,
+    /// Key within the Secret's data
+    pub key: String,
+}
+
+/// Declarative reshaping of the parsed body, applied as `fields`, `drop`,
+/// `patch`, then `flatten`
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TransformConfig {
+    /// JSONPaths to keep; everything else is left out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fields: Option<Vec<String>>,
+    /// JSONPaths to remove wherever they match, all matched before any is removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drop: Option<Vec<String>>,
+    /// RFC 6902 JSON Patch operations (add, remove, replace, move, copy, test)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<Vec<serde_json::Value>>,
+    /// Flatten nested objects into top-level keys joined by this separator
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flatten: Option<String>,
+    /// Match routes against the transformed body instead of the original
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub route_on_output: bool,
+}
+
+/// A JSON Schema for request bodies, given inline or by ConfigMap key
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PayloadSchemaConfig {
+    /// The schema document itself
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<serde_json::Value>,
+    /// A ConfigMap key holding the schema as JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub config_map_ref: Option<ConfigMapKeyRef>,
+    #[serde(default, skip_serializing_if = "InvalidPayloadPolicy::is_default")]
+    pub on_invalid: InvalidPayloadPolicy,
+}
+
+/// Reference to a key in a ConfigMap in the handler's namespace
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq, Eq)]
+pub struct ConfigMapKeyRef {
+    /// Name of the ConfigMap
+    pub name: String,
+    /// Key within the ConfigMap's data
+    pub key: String,
+}
+
+/// What happens to a body that does not match the handler's `payloadSchema`
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidPayloadPolicy {
+    /// Return 422 so the provider sees the error
+    #[default]
+    Reject,
+    /// Send the event to the dead-letter topic
+    DeadLetter,
+    /// Publish it anyway, with a `schema-valid: false` record header
+    Pass,
+}
+
+impl InvalidPayloadPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == InvalidPayloadPolicy::Reject
+    }
+}
+
+/// Signature verification scheme used by the webhook provider
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SignatureScheme {
+    /// `X-Signature: sha256=<hex>` over `"{X-Timestamp}.{body}"`
+    #[default]
+    Default,
+    /// `Stripe-Signature: t=<ts>,v1=<hex>`
+    Stripe,
+    /// `X-Hub-Signature-256: sha256=<hex>` over the body
+    Github,
+    /// `x-zm-signature: v0=<hex>` with `x-zm-request-timestamp`
+    Zoom,
+    /// `X-Slack-Signature: v0=<hex>` with `X-Slack-Request-Timestamp`
+    Slack,
+    /// `X-Shopify-Hmac-Sha256: <base64>` over the body
+    Shopify,
+    /// Standard Webhooks / Svix `webhook-signature: v1,<base64>`
+    StandardWebhooks,
+}
+
+/// Endpoint-validation handshake answered without publishing to Kafka
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChallengeMode {
+    /// `endpoint.url_validation` answered with an HMAC'd `encryptedToken`
+    Zoom,
+    /// `url_verification` answered with its `challenge`
+    Slack,
+    /// GET with `hub.challenge`, checked against `verifyToken`
+    Meta,
+    /// `validationToken` query parameter echoed back as text
+    MicrosoftGraph,
+}
+
+/// A filter is either a single rule or a boolean group of filters.
+/// A list of filters on the handler is an implicit `all` group.
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
-pub struct Filter {
-    /// JSONPath expression to extract value (e.g., "$.payload.account_id")
-    pub path: String,
-    /// Operator: "equals", "not_equals", "in", "not_in", "contains", "not_contains"
-    pub operator: String,
-    /// Value(s) to compare against
-    pub value: FilterValue,
+#[serde(untagged)]
+pub enum Filter {
+    /// Passes when every nested filter passes
+    All {
+        all: Vec<Filter>,
+    },
+    /// Passes when at least one nested filter passes
+    Any {
+        any: Vec<Filter>,
+    },
+    /// Passes when the nested filter does not
+    Not {
+        not: Box<Filter>,
+    },
+    Expression(ExpressionFilter),
+    Rule(FilterRule),
+}
+
+/// A CEL expression over `body`, `headers`, `query`, `method` and `received_at`
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpressionFilter {
+    /// Must evaluate to a bool (e.g., "body.amount > 100 && headers['x-env'] == 'prod'")
+    pub expression: String,
+    /// What to do when the expression fails to evaluate, e.g. on a missing field
+    #[serde(default, skip_serializing_if = "MissingPolicy::is_default")]
+    pub on_missing: MissingPolicy,
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FilterRule {
+    /// RFC 9535 JSONPath expression to extract values (e.g., "$.payload.account_id");
+    /// `$request.headers[...]`, `$request.query...`, `$request.method` and
+    /// `$request.body...` select from the request; `$` paths always select from the body
+    pub path: String,
+    pub operator: FilterOperator,
+    /// Whether any or all values selected by `path` must satisfy the operator
+    #[serde(default, skip_serializing_if = "MatchMode::is_any")]
+    pub match_mode: MatchMode,
+    /// Value(s) to compare against; not used by `exists`/`not_exists`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<FilterValue>,
+    /// Compare strings case-insensitively (equality, substring and regex operators)
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub ignore_case: bool,
+    /// What to do when `path` selects nothing; ignored by `exists`/`not_exists`
+    #[serde(default, skip_serializing_if = "MissingPolicy::is_default")]
+    pub on_missing: MissingPolicy,
+}
+
+/// Handling of payloads that lack a filter or route path
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingPolicy {
+    /// The filter fails, or the route is skipped
+    #[default]
+    NoMatch,
+    /// The filter passes (not allowed on routes)
+    Match,
+    /// The webhook is rejected with 422 Unprocessable Entity
+    Reject,
+    /// The event is published to the handler's `deadLetterTopic`
+    DeadLetter,
+}
+
+impl MissingPolicy {
+    pub fn is_default(&self) -> bool {
+        *self == MissingPolicy::NoMatch
+    }
+}
+
+/// How a rule applies when its path selects several values. Negated operators
+/// (`not_equals`, `not_in`, `not_contains`, `not_matches`) negate the result of
+/// their positive counterpart, so `not_in` under `any` means no value is in the list.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchMode {
+    /// At least one value matches
+    #[default]
+    Any,
+    /// Every value matches
+    All,
+}
+
+impl MatchMode {
+    pub fn is_any(&self) -> bool {
+        *self == MatchMode::Any
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterOperator {
+    Equals,
+    NotEquals,
+    In,
+    NotIn,
+    Contains,
+    NotContains,
+    StartsWith,
+    EndsWith,
+    /// Regular expression match
+    Matches,
+    NotMatches,
+    /// Numeric or RFC3339 timestamp comparisons
+    Gt,
+    Gte,
+    Lt,
+    Lte,
+    /// The path is present in the payload
+    Exists,
+    NotExists,
+}
+
+impl FilterOperator {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterOperator::Equals => "equals",
+            FilterOperator::NotEquals => "not_equals",
+            FilterOperator::In => "in",
+            FilterOperator::NotIn => "not_in",
+            FilterOperator::Contains => "contains",
+            FilterOperator::NotContains => "not_contains",
+            FilterOperator::StartsWith => "starts_with",
+            FilterOperator::EndsWith => "ends_with",
+            FilterOperator::Matches => "matches",
+            FilterOperator::NotMatches => "not_matches",
+            FilterOperator::Gt => "gt",
+            FilterOperator::Gte => "gte",
+            FilterOperator::Lt => "lt",
+            FilterOperator::Lte => "lte",
+            FilterOperator::Exists => "exists",
+            FilterOperator::NotExists => "not_exists",
+        }
+    }
+}
+
+impl std::fmt::Display for FilterOperator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A filter value as written in JSON. Integers and floats compare numerically
+/// with each other; other types only match values of the same type.
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, PartialEq)]
 #[serde(untagged)]
 pub enum FilterValue {
+    Null,
+    Bool(bool),
+    Number(i64),
+    Float(f64),
     String(String),
     StringArray(Vec<String>),
-    Number(i64),
     NumberArray(Vec<i64>),
+    /// Array mixing strings, numbers, booleans and null
+    Array(Vec<FilterValue>),
 }
 
 #[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct Route {
-    /// JSONPath expression to extract value for routing (e.g., "$.payload.account_id")
-    pub path: String,
+    /// Conditions the event must meet for this route to apply; all must pass
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Vec<Filter>>,
+    /// What the route does with matching events; `drop` and `dead_letter`
+    /// routes select no topic
+    #[serde(default, skip_serializing_if = "RouteAction::is_default")]
+    pub action: RouteAction,
+    /// JSONPath expression to extract value for routing (e.g., "$.payload.account_id");
+    /// a route matches if any selected value equals a mapping value
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// CEL expression computing the value to route on, instead of `path`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expression: Option<String>,
+    /// Topic computed from the request instead of looked up in `mapping`,
+    /// e.g. "zoom.{$.payload.account_id}.events". Characters not allowed in
+    /// topic names are replaced with `_`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic_template: Option<String>,
+    /// Regex a templated topic must match in full, so senders cannot pick arbitrary topics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_topics: Option<String>,
+    /// Longest templated topic name accepted; defaults to Kafka's limit of 249
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_topic_length: Option<usize>,
     /// Mapping of values to topics
+    #[serde(default)]
     pub mapping: Vec<RouteMapping>,
+    /// What to do when `path` selects nothing, `expression` fails, or a
+    /// templated topic cannot be rendered or is not allowed
+    #[serde(default, skip_serializing_if = "MissingPolicy::is_default")]
+    pub on_missing: MissingPolicy,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema)]
+/// What a route does with the events it applies to
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteAction {
+    /// Publish to the topic the route selects
+    #[default]
+    Publish,
+    /// Acknowledge the webhook without publishing it
+    Drop,
+    /// Send the event to `deadLetterTopic`
+    DeadLetter,
+}
+
+impl RouteAction {
+    pub fn is_default(&self) -> bool {
+        *self == RouteAction::Publish
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteAction::Publish => "publish",
+            RouteAction::Drop => "drop",
+            RouteAction::DeadLetter => "dead_letter",
+        }
+    }
+}
+
+/// A topic and the values routed to it; set exactly one of `value`,
+/// `values`, `prefix`, `glob`, `regex` or `range`
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default)]
 pub struct RouteMapping {
     /// Value to match (e.g., "account123")
-    pub value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<String>,
+    /// Any of these values
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<String>>,
+    /// Values starting with this prefix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prefix: Option<String>,
+    /// Shell-style pattern where `*` matches any characters and `?` one (e.g., "meeting.*")
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub glob: Option<String>,
+    /// Regular expression found anywhere in the value; anchor with `^...$`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    /// Numeric bounds a number must fall within
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub range: Option<NumericRange>,
     /// Topic to route to when matched
     pub topic: String,
 }
 
-#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default)]
+/// Bounds of a numeric range; unset bounds are open
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+pub struct NumericRange {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gte: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lt: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lte: Option<f64>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, JsonSchema, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
 pub struct WebhookHandlerStatus {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub handler_url: Option<String>,
+    /// True when the handler is valid, its secret resolved and no topic is known to be missing
     #[serde(default)]
     pub ready: bool,
-}
\ No newline at end of file
+    /// Spec generation the status was computed from
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub observed_generation: Option<i64>,
+    /// Valid, TopicExists and SecretResolved conditions
+    #[serde(default)]
+    pub conditions: Vec<Condition>,
+}
@@ -1,147 +1,1208 @@
 use anyhow::{anyhow, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use regex::{Regex, RegexBuilder};
 use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
 
-use crate::crd::{Filter, FilterValue, Route};
-
-/// Evaluates all filters against the JSON payload
-/// Returns true if the event should be processed (passes all filters)
-/// Returns false if the event should be discarded (fails any filter)
-pub fn should_process_event(payload: &Value, filters: &[Filter]) -> Result<bool> {
-    for filter in filters {
-        if !evaluate_filter(payload, filter)? {
-            tracing::debug!("Event filtered out by rule: path={}, operator={}", 
-                filter.path, filter.operator);
-            return Ok(false);
-        }
-    }
-    Ok(true)
-}
-
-/// Evaluates a single filter against the payload
-fn evaluate_filter(payload: &Value, filter: &Filter) -> Result<bool> {
-    let extracted_value = extract_json_path(payload, &filter.path)?;
-    
-    match filter.operator.as_str() {
-        "equals" => Ok(match_equals(&extracted_value, &filter.value)),
-        "not_equals" => Ok(!match_equals(&extracted_value, &filter.value)),
-        "in" => Ok(match_in(&extracted_value, &filter.value)),
-        "not_in" => Ok(!match_in(&extracted_value, &filter.value)),
-        "contains" => Ok(match_contains(&extracted_value, &filter.value)),
-        "not_contains" => Ok(!match_contains(&extracted_value, &filter.value)),
-        _ => Err(anyhow!("Unknown filter operator: {}", filter.operator)),
-    }
-}
-
-/// Determines the target topic based on routing rules
-/// Returns the matched topic or None if no rule matches (use default topic)
-pub fn route_to_topic(payload: &Value, routes: &[Route]) -> Result<Option<String>> {
-    for route in routes {
-        let extracted_value = extract_json_path(payload, &route.path)?;
-        
-        // Try to match against each mapping
-        for mapping in &route.mapping {
-            if value_matches_string(&extracted_value, &mapping.value) {
-                tracing::debug!("Event routed to topic '{}' based on path={}, value={}", 
-                    mapping.topic, route.path, mapping.value);
-                return Ok(Some(mapping.topic.clone()));
-            }
-        }
-    }
-    
-    Ok(None) // No route matched, use default topic
-}
-
-/// Extracts a value from JSON using a simplified JSONPath-like syntax
-/// Supports: $.field, $.field.nested, $.array[0]
-fn extract_json_path(payload: &Value, path: &str) -> Result<Value> {
-    let path = path.strip_prefix("$.").unwrap_or(path);
-    let parts: Vec<&str> = path.split('.').collect();
-    
-    let mut current = payload;
-    
-    for part in parts {
-        // Handle array indexing like "field[0]"
-        if let Some((field, index_str)) = part.split_once('[') {
-            let index_str = index_str.trim_end_matches(']');
-            let index: usize = index_str.parse()
-                .map_err(|_| anyhow!("Invalid array index: {}", index_str))?;
-            
-            current = current.get(field)
-                .ok_or_else(|| anyhow!("Field not found: {}", field))?;
-            
-            current = current.get(index)
-                .ok_or_else(|| anyhow!("Array index out of bounds: {}", index))?;
-        } else {
-            current = current.get(part)
-                .ok_or_else(|| anyhow!("Field not found: {}", part))?;
-        }
-    }
-    
-    Ok(current.clone())
-}
-
-fn match_equals(extracted: &Value, filter_value: &FilterValue) -> bool {
-    match filter_value {
-        FilterValue::String(s) => {
-            if let Some(extracted_str) = extracted.as_str() {
-                extracted_str == s
-            } else {
-                false
-            }
-        }
-        FilterValue::Number(n) => {
-            if let Some(extracted_num) = extracted.as_i64() {
-                extracted_num == *n
-            } else {
-                false
+use crate::cel::{Program, Type};
+use crate::crd::{
+    Filter, FilterOperator, FilterRule, FilterValue, KeyFallback, MatchMode, MissingPolicy,
+    NumericRange, Route, RouteAction, RouteMapping, RoutingMode, WebhookHandlerSpec,
+};
+use crate::kafka::is_valid_topic_name;
+use crate::template::Template;
+
+/// Kafka's limit on topic name length
+const MAX_TOPIC_LENGTH: usize = 249;
+
+/// A received webhook as seen by filters and routes. Paths select from the
+/// document `{ body, headers, query, method }`; bare paths select from `body`.
+pub struct WebhookEvent<'a> {
+    pub body: &'a Value,
+    /// Header names (lowercase) to values
+    pub headers: &'a Value,
+    /// Query parameters to values
+    pub query: &'a Value,
+    /// HTTP method as a string
+    pub method: &'a Value,
+    pub received_at: DateTime<Utc>,
+}
+
+/// A filter or route path selected nothing (or an expression or topic
+/// template failed) and its `onMissing` policy rejects or dead-letters the
+/// event instead of deciding the rule
+#[derive(Debug)]
+pub struct MissingPath {
+    /// The path, expression or template source
+    pub path: String,
+    pub policy: MissingPolicy,
+    /// What went wrong, when it was not a path selecting nothing
+    pub error: Option<String>,
+}
+
+impl std::fmt::Display for MissingPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.error {
+            Some(error) => f.write_str(error),
+            None => write!(f, "Path matched no values: {}", self.path),
+        }
+    }
+}
+
+impl std::error::Error for MissingPath {}
+
+/// What to do with a webhook, as decided by a handler's filters and routes
+#[derive(Debug, PartialEq)]
+pub enum Outcome<'a> {
+    /// A filter discarded the event
+    Filtered,
+    /// A `drop` route discarded the event
+    Dropped,
+    /// A `dead_letter` route matched, for the given reason
+    DeadLetter(String),
+    /// Topics to publish to and the numbers (from 1) of the routes that
+    /// picked them; both empty when no route matched
+    Publish {
+        topics: Vec<Cow<'a, str>>,
+        routes: Vec<usize>,
+    },
+}
+
+/// A handler's filters, routes and message key, compiled once when the
+/// handler is loaded: paths are parsed, regexes built and `in` lists hashed,
+/// so evaluating a webhook does no parsing.
+#[derive(Debug, Default)]
+pub struct Plan {
+    filters: Vec<CompiledFilter>,
+    routes: Vec<CompiledRoute>,
+    message_key: MessageKey,
+    key_fallback: KeyFallback,
+}
+
+/// Where the Kafka message key comes from
+#[derive(Debug, Default)]
+enum MessageKey {
+    /// The handler UUID, so all of a handler's events share a partition
+    #[default]
+    HandlerId,
+    /// No key, so Kafka spreads events across partitions
+    Null,
+    Path(String, RequestPath),
+    Template(Template),
+}
+
+#[derive(Debug)]
+enum CompiledFilter {
+    All(Vec<CompiledFilter>),
+    Any(Vec<CompiledFilter>),
+    Not(Box<CompiledFilter>),
+    Expression(Program, MissingPolicy),
+    Rule(CompiledRule),
+}
+
+#[derive(Debug)]
+struct CompiledRule {
+    source: String,
+    path: RequestPath,
+    operator: FilterOperator,
+    test: Test,
+    /// Negated operators negate the quantified result of their positive form
+    negated: bool,
+    match_mode: MatchMode,
+    on_missing: MissingPolicy,
+}
+
+/// The positive form of an operator with its value prepared for matching
+#[derive(Debug)]
+enum Test {
+    Exists,
+    Equals(FilterValue, bool),
+    In(ValueSet),
+    Contains(String, bool),
+    StartsWith(String, bool),
+    EndsWith(String, bool),
+    Matches(Regex),
+    Compare(Threshold),
+}
+
+#[derive(Debug)]
+enum Threshold {
+    Number(FilterValue),
+    Time(DateTime<FixedOffset>),
+}
+
+/// Values of an `in`/`not_in` list, grouped by type for lookup
+#[derive(Debug, Default)]
+struct ValueSet {
+    strings: HashSet<String>,
+    integers: HashSet<i64>,
+    floats: Vec<f64>,
+    bools: Vec<bool>,
+    null: bool,
+    ignore_case: bool,
+}
+
+#[derive(Debug)]
+struct CompiledRoute {
+    source: String,
+    /// Conditions the event must meet for the route to apply
+    when: Vec<CompiledFilter>,
+    selector: Selector,
+    /// Description and topic of each mapping
+    mapping: Vec<(String, String)>,
+    /// `value`/`values` entries to the indices of the mappings listing them
+    lookup: HashMap<String, Vec<usize>>,
+    /// Prefix, glob, regex and range mappings, tested in turn
+    patterns: Vec<(usize, Pattern)>,
+    on_missing: MissingPolicy,
+}
+
+/// How a route mapping matches: exact values are hashed, patterns tested in turn
+enum Matcher<'a> {
+    Values(&'a [String]),
+    Pattern(Pattern),
+}
+
+#[derive(Debug)]
+enum Pattern {
+    Prefix(String),
+    /// Also used for globs
+    Regex(Regex),
+    Range(NumericRange),
+}
+
+/// What a route matches its mapping against, the template its topic is
+/// rendered from, or the action it takes instead of publishing
+#[derive(Debug)]
+enum Selector {
+    Path(RequestPath),
+    Expression(Program),
+    Template(TopicTemplate),
+    Action(RouteAction),
+}
+
+#[derive(Debug)]
+struct TopicTemplate {
+    template: Template,
+    /// Anchored allowlist
+    allowed: Option<Regex>,
+    max_length: usize,
+}
+
+/// A JSONPath relative to one part of the request
+#[derive(Debug)]
+pub(crate) struct RequestPath {
+    section: Section,
+    path: JsonPath,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Section {
+    Body,
+    Headers,
+    Query,
+    Method,
+}
+
+impl RequestPath {
+    pub(crate) fn query<'a>(&self, event: &WebhookEvent<'a>) -> Vec<&'a Value> {
+        let root = match self.section {
+            Section::Body => event.body,
+            Section::Headers => event.headers,
+            Section::Query => event.query,
+            Section::Method => event.method,
+        };
+        self.path.query(root).all()
+    }
+}
+
+impl Plan {
+    /// Compiles filters and routes, rejecting anything that would otherwise
+    /// only fail when a webhook is received
+    pub fn compile(filters: &[Filter], routes: &[Route]) -> Result<Plan> {
+        Ok(Plan {
+            filters: filters.iter().map(compile_filter).collect::<Result<_>>()?,
+            routes: routes.iter().map(compile_route).collect::<Result<_>>()?,
+            ..Plan::default()
+        })
+    }
+
+    /// Compiles the handler's `keyPath`, `keyTemplate` or `nullKey`
+    pub fn with_message_key(mut self, spec: &WebhookHandlerSpec) -> Result<Plan> {
+        self.message_key = match (&spec.key_path, &spec.key_template) {
+            (Some(_), Some(_)) => {
+                return Err(anyhow!("keyPath and keyTemplate are mutually exclusive"))
+            }
+            (Some(_), _) | (_, Some(_)) if spec.null_key => {
+                return Err(anyhow!(
+                    "nullKey must not be combined with keyPath or keyTemplate"
+                ))
+            }
+            (Some(path), None) => MessageKey::Path(path.clone(), parse_request_path(path)?),
+            (None, Some(template)) => MessageKey::Template(Template::parse(template)?),
+            (None, None) if spec.null_key => MessageKey::Null,
+            (None, None) if !spec.key_fallback.is_default() => {
+                return Err(anyhow!("keyFallback requires keyPath or keyTemplate"))
             }
+            (None, None) => MessageKey::HandlerId,
+        };
+        self.key_fallback = spec.key_fallback;
+        Ok(self)
+    }
+
+    /// The Kafka message key for an event: the value `keyPath` selects or
+    /// `keyTemplate` renders, else `keyFallback`. `None` publishes without a key.
+    pub fn message_key(
+        &self,
+        event: &WebhookEvent,
+        handler_id: Uuid,
+    ) -> Result<Option<String>, MissingPath> {
+        let missing = match &self.message_key {
+            MessageKey::HandlerId => return Ok(Some(handler_id.to_string())),
+            MessageKey::Null => return Ok(None),
+            MessageKey::Path(source, path) => match path.query(event).first() {
+                Some(Value::String(s)) => return Ok(Some(s.clone())),
+                Some(Value::Number(n)) => return Ok(Some(n.to_string())),
+                Some(Value::Bool(b)) => return Ok(Some(b.to_string())),
+                _ => source.as_str(),
+            },
+            MessageKey::Template(template) => match template.render(event, str::to_string) {
+                Ok(key) => return Ok(Some(key)),
+                Err(placeholder) => placeholder,
+            },
+        };
+
+        match self.key_fallback {
+            KeyFallback::HandlerId => Ok(Some(handler_id.to_string())),
+            KeyFallback::Null => Ok(None),
+            KeyFallback::Reject => Err(MissingPath {
+                path: missing.to_string(),
+                policy: MissingPolicy::Reject,
+                error: Some(format!(
+                    "Message key path matched no string, number or bool: {}",
+                    missing
+                )),
+            }),
         }
-        _ => false,
+    }
+
+    /// Evaluates all filters against the JSON payload
+    /// Returns true if the event should be processed (passes all filters)
+    /// Returns false if the event should be discarded (fails any filter)
+    pub fn should_process_event(&self, event: &WebhookEvent) -> Result<bool, MissingPath> {
+        for filter in &self.filters {
+            if !filter.evaluate(event)? {
+                tracing::debug!("Event filtered out by filter: {}", filter.describe());
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Evaluates the routes in order, for an event that has passed the filters
+    pub fn route(
+        &self,
+        event: &WebhookEvent,
+        routing_mode: RoutingMode,
+    ) -> Result<Outcome<'_>, MissingPath> {
+        let fan_out = routing_mode == RoutingMode::FanOut;
+        let mut topics: Vec<Cow<str>> = Vec::new();
+        let mut routes = Vec::new();
+        for (index, route) in self.routes.iter().enumerate() {
+            if !route.when_matches(event)? {
+                continue;
+            }
+
+            // Actions apply to events no earlier route has sent anywhere
+            let matched = match &route.selector {
+                Selector::Action(RouteAction::Drop) if topics.is_empty() => {
+                    tracing::debug!("Event dropped by route {}", index + 1);
+                    return Ok(Outcome::Dropped);
+                }
+                Selector::Action(RouteAction::DeadLetter) if topics.is_empty() => {
+                    return Ok(Outcome::DeadLetter(format!(
+                        "Matched route {} with action dead_letter",
+                        index + 1
+                    )));
+                }
+                Selector::Action(_) => continue,
+                _ => route.topics(event, fan_out)?,
+            };
+            if matched.is_empty() {
+                continue;
+            }
+            if !fan_out {
+                return Ok(Outcome::Publish {
+                    topics: matched,
+                    routes: vec![index + 1],
+                });
+            }
+            routes.push(index + 1);
+            for topic in matched {
+                if !topics.contains(&topic) {
+                    topics.push(topic);
+                }
+            }
+        }
+
+        Ok(Outcome::Publish { topics, routes }) // Empty if no route matched: use the default topic
+    }
+
+    /// Whether any filter or route sends events to the dead-letter topic
+    pub fn uses_dead_letter(&self) -> bool {
+        self.filters.iter().any(CompiledFilter::uses_dead_letter)
+            || self.routes.iter().any(|route| {
+                route.on_missing == MissingPolicy::DeadLetter
+                    || matches!(route.selector, Selector::Action(RouteAction::DeadLetter))
+                    || route.when.iter().any(CompiledFilter::uses_dead_letter)
+            })
     }
 }
 
-fn match_in(extracted: &Value, filter_value: &FilterValue) -> bool {
-    match filter_value {
-        FilterValue::StringArray(arr) => {
-            if let Some(extracted_str) = extracted.as_str() {
-                arr.contains(&extracted_str.to_string())
-            } else {
-                false
+impl CompiledRoute {
+    fn when_matches(&self, event: &WebhookEvent) -> Result<bool, MissingPath> {
+        for filter in &self.when {
+            if !filter.evaluate(event)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Topics this route sends the event to: the earliest matched mapping, or
+    /// with `fan_out` every matched mapping. A missing value yields no topics
+    /// unless `onMissing` rejects or dead-letters the event.
+    fn topics(
+        &self,
+        event: &WebhookEvent,
+        fan_out: bool,
+    ) -> Result<Vec<Cow<'_, str>>, MissingPath> {
+        let missing = |error| match self.on_missing {
+            MissingPolicy::NoMatch | MissingPolicy::Match => Ok(Vec::new()),
+            policy => Err(MissingPath {
+                path: self.source.clone(),
+                policy,
+                error,
+            }),
+        };
+
+        // A path selecting nothing, or an expression that fails or is null, is missing
+        let values: Vec<Cow<Value>> = match &self.selector {
+            Selector::Path(path) => {
+                let nodes = path.query(event);
+                if nodes.is_empty() {
+                    return missing(None);
+                }
+                nodes.into_iter().map(Cow::Borrowed).collect()
+            }
+            Selector::Expression(program) => match program.evaluate_scalar(event) {
+                Ok(Value::Null) => return missing(None),
+                Ok(value) => vec![Cow::Owned(value)],
+                Err(error) => {
+                    return missing(Some(format!(
+                        "Expression {} failed: {}",
+                        program.source(),
+                        error
+                    )))
+                }
+            },
+            Selector::Template(template) => {
+                return match template.render(event) {
+                    Ok(topic) => {
+                        tracing::debug!(
+                            "Event routed to topic '{}' by template {}",
+                            topic,
+                            self.source
+                        );
+                        Ok(vec![Cow::Owned(topic)])
+                    }
+                    Err(err) => missing(err.error).map_err(|e| MissingPath {
+                        path: err.path,
+                        ..e
+                    }),
+                };
+            }
+            Selector::Action(_) => return Ok(Vec::new()),
+        };
+
+        let mut matched = Vec::new();
+        for value in &values {
+            let key = route_key(value);
+            if let Some(indices) = key.as_ref().and_then(|key| self.lookup.get(key.as_ref())) {
+                matched.extend_from_slice(indices);
+            }
+            for (index, pattern) in &self.patterns {
+                if pattern.matches(value, key.as_deref()) {
+                    matched.push(*index);
+                }
             }
         }
-        FilterValue::NumberArray(arr) => {
-            if let Some(extracted_num) = extracted.as_i64() {
-                arr.contains(&extracted_num)
-            } else {
-                false
+        matched.sort_unstable();
+        matched.dedup();
+        if !fan_out {
+            // The earliest mapping matched by any selected value wins
+            matched.truncate(1);
+        }
+
+        Ok(matched
+            .into_iter()
+            .map(|index| {
+                let (description, topic) = &self.mapping[index];
+                tracing::debug!(
+                    "Event routed to topic '{}' based on path={}, {}",
+                    topic,
+                    self.source,
+                    description
+                );
+                Cow::Borrowed(topic.as_str())
+            })
+            .collect())
+    }
+}
+
+impl Pattern {
+    /// Tests a selected value; string patterns see its route key
+    fn matches(&self, value: &Value, key: Option<&str>) -> bool {
+        match self {
+            Pattern::Prefix(prefix) => key.is_some_and(|key| key.starts_with(prefix.as_str())),
+            Pattern::Regex(regex) => key.is_some_and(|key| regex.is_match(key)),
+            Pattern::Range(range) => value.as_f64().is_some_and(|n| {
+                range.gt.is_none_or(|gt| n > gt)
+                    && range.gte.is_none_or(|gte| n >= gte)
+                    && range.lt.is_none_or(|lt| n < lt)
+                    && range.lte.is_none_or(|lte| n <= lte)
+            }),
+        }
+    }
+}
+
+impl TopicTemplate {
+    /// Renders the topic, replacing characters Kafka does not allow in topic
+    /// names, and checks it against the length cap and allowlist
+    fn render(&self, event: &WebhookEvent) -> Result<String, MissingPath> {
+        let source = self.template.source();
+        let topic = self
+            .template
+            .render(event, sanitize_topic)
+            .map_err(|path| MissingPath {
+                path: path.to_string(),
+                policy: MissingPolicy::NoMatch,
+                error: None,
+            })?;
+
+        let rejected = |reason: &str| MissingPath {
+            path: source.to_string(),
+            policy: MissingPolicy::NoMatch,
+            error: Some(format!(
+                "Topic {:?} from template {} {}",
+                topic, source, reason
+            )),
+        };
+        if topic.len() > self.max_length {
+            return Err(rejected(&format!(
+                "is longer than {} characters",
+                self.max_length
+            )));
+        }
+        if !is_valid_topic_name(&topic) {
+            return Err(rejected("is not a valid topic name"));
+        }
+        if self
+            .allowed
+            .as_ref()
+            .is_some_and(|allowed| !allowed.is_match(&topic))
+        {
+            return Err(rejected("is not allowed"));
+        }
+        Ok(topic)
+    }
+}
+
+impl CompiledFilter {
+    /// Evaluates a filter, recursing into `all`/`any`/`not` groups
+    fn evaluate(&self, event: &WebhookEvent) -> Result<bool, MissingPath> {
+        match self {
+            CompiledFilter::All(all) => {
+                for filter in all {
+                    if !filter.evaluate(event)? {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
             }
+            CompiledFilter::Any(any) => {
+                for filter in any {
+                    if filter.evaluate(event)? {
+                        return Ok(true);
+                    }
+                }
+                Ok(false)
+            }
+            CompiledFilter::Not(not) => Ok(!not.evaluate(event)?),
+            CompiledFilter::Expression(program, on_missing) => match program.evaluate_bool(event) {
+                Ok(matched) => Ok(matched),
+                Err(error) => match on_missing {
+                    MissingPolicy::NoMatch => Ok(false),
+                    MissingPolicy::Match => Ok(true),
+                    policy => Err(MissingPath {
+                        path: program.source().to_string(),
+                        policy: *policy,
+                        error: Some(format!("Expression {} failed: {}", program.source(), error)),
+                    }),
+                },
+            },
+            CompiledFilter::Rule(rule) => rule.evaluate(event),
         }
-        _ => false,
     }
+
+    /// Short description of a filter for log messages
+    fn describe(&self) -> String {
+        match self {
+            CompiledFilter::All(all) => format!("all group of {}", all.len()),
+            CompiledFilter::Any(any) => format!("any group of {}", any.len()),
+            CompiledFilter::Not(not) => format!("not ({})", not.describe()),
+            CompiledFilter::Expression(program, _) => format!("expression={}", program.source()),
+            CompiledFilter::Rule(rule) => {
+                format!("path={}, operator={}", rule.source, rule.operator)
+            }
+        }
+    }
+
+    fn uses_dead_letter(&self) -> bool {
+        match self {
+            CompiledFilter::All(filters) | CompiledFilter::Any(filters) => {
+                filters.iter().any(CompiledFilter::uses_dead_letter)
+            }
+            CompiledFilter::Not(not) => not.uses_dead_letter(),
+            CompiledFilter::Expression(_, on_missing) => *on_missing == MissingPolicy::DeadLetter,
+            CompiledFilter::Rule(rule) => rule.on_missing == MissingPolicy::DeadLetter,
+        }
+    }
+}
+
+impl CompiledRule {
+    /// Evaluates a single filter rule against the payload
+    fn evaluate(&self, event: &WebhookEvent) -> Result<bool, MissingPath> {
+        let nodes = self.path.query(event);
+        if nodes.is_empty() {
+            return match (&self.test, self.on_missing) {
+                // exists fails and not_exists passes
+                (Test::Exists, _) => Ok(self.negated),
+                (_, MissingPolicy::NoMatch) => Ok(false),
+                (_, MissingPolicy::Match) => Ok(true),
+                (_, policy) => Err(MissingPath {
+                    path: self.source.clone(),
+                    policy,
+                    error: None,
+                }),
+            };
+        }
+
+        let matched = match self.match_mode {
+            MatchMode::Any => nodes
+                .iter()
+                .any(|node| self.test.matches(node, self.operator)),
+            MatchMode::All => nodes
+                .iter()
+                .all(|node| self.test.matches(node, self.operator)),
+        };
+        Ok(matched != self.negated)
+    }
+}
+
+impl Test {
+    fn matches(&self, extracted: &Value, operator: FilterOperator) -> bool {
+        match self {
+            Test::Exists => true,
+            Test::Equals(value, ignore_case) => scalar_equals(extracted, value, *ignore_case),
+            Test::In(set) => set.contains(extracted),
+            Test::Contains(s, ignore_case) => {
+                match_string(extracted, s, *ignore_case, |e, v| e.contains(v))
+            }
+            Test::StartsWith(s, ignore_case) => {
+                match_string(extracted, s, *ignore_case, |e, v| e.starts_with(v))
+            }
+            Test::EndsWith(s, ignore_case) => {
+                match_string(extracted, s, *ignore_case, |e, v| e.ends_with(v))
+            }
+            Test::Matches(regex) => extracted.as_str().is_some_and(|s| regex.is_match(s)),
+            Test::Compare(threshold) => {
+                compare(extracted, threshold).is_some_and(|ordering| match operator {
+                    FilterOperator::Gt => ordering == Ordering::Greater,
+                    FilterOperator::Gte => ordering != Ordering::Less,
+                    FilterOperator::Lt => ordering == Ordering::Less,
+                    FilterOperator::Lte => ordering != Ordering::Greater,
+                    _ => false,
+                })
+            }
+        }
+    }
+}
+
+impl ValueSet {
+    fn new(items: &[FilterValue], ignore_case: bool) -> ValueSet {
+        let mut set = ValueSet {
+            ignore_case,
+            ..ValueSet::default()
+        };
+        for item in items {
+            match item {
+                FilterValue::Null => set.null = true,
+                FilterValue::Bool(b) => set.bools.push(*b),
+                FilterValue::Number(n) => {
+                    set.integers.insert(*n);
+                }
+                FilterValue::Float(f) => set.floats.push(*f),
+                FilterValue::String(s) if ignore_case => {
+                    set.strings.insert(s.to_lowercase());
+                }
+                FilterValue::String(s) => {
+                    set.strings.insert(s.clone());
+                }
+                FilterValue::StringArray(_)
+                | FilterValue::NumberArray(_)
+                | FilterValue::Array(_) => {}
+            }
+        }
+        set
+    }
+
+    /// Same rules as `scalar_equals` against each element
+    fn contains(&self, extracted: &Value) -> bool {
+        match extracted {
+            Value::Null => self.null,
+            Value::Bool(b) => self.bools.contains(b),
+            Value::String(s) if self.ignore_case => self.strings.contains(&s.to_lowercase()),
+            Value::String(s) => self.strings.contains(s),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => self.integers.contains(&i) || self.floats.contains(&(i as f64)),
+                None => n.as_f64().is_some_and(|f| {
+                    self.floats.contains(&f) || self.integers.iter().any(|i| *i as f64 == f)
+                }),
+            },
+            _ => false,
+        }
+    }
+}
+
+fn compile_filter(filter: &Filter) -> Result<CompiledFilter> {
+    Ok(match filter {
+        Filter::All { all } => {
+            CompiledFilter::All(all.iter().map(compile_filter).collect::<Result<_>>()?)
+        }
+        Filter::Any { any } => {
+            CompiledFilter::Any(any.iter().map(compile_filter).collect::<Result<_>>()?)
+        }
+        Filter::Not { not } => CompiledFilter::Not(Box::new(compile_filter(not)?)),
+        Filter::Expression(filter) => {
+            let program = compile_expression(&filter.expression)?;
+            if !matches!(program.result_type(), Type::Bool | Type::Dyn) {
+                return Err(anyhow!(
+                    "Expression {:?} must evaluate to bool, not {}",
+                    filter.expression,
+                    program.result_type()
+                ));
+            }
+            CompiledFilter::Expression(program, filter.on_missing)
+        }
+        Filter::Rule(rule) => CompiledFilter::Rule(compile_rule(rule)?),
+    })
+}
+
+fn compile_rule(rule: &FilterRule) -> Result<CompiledRule> {
+    use FilterOperator::*;
+
+    if rule.path.is_empty() {
+        return Err(anyhow!("Filter path must not be empty"));
+    }
+    let path = parse_request_path(&rule.path)?;
+
+    let negated = matches!(
+        rule.operator,
+        NotEquals | NotIn | NotContains | NotMatches | NotExists
+    );
+    let compiled = |test| CompiledRule {
+        source: rule.path.clone(),
+        path,
+        operator: rule.operator,
+        test,
+        negated,
+        match_mode: rule.match_mode,
+        on_missing: rule.on_missing,
+    };
+
+    let value = match (rule.operator, &rule.value) {
+        (Exists | NotExists, _) => return Ok(compiled(Test::Exists)),
+        (operator, None) => return Err(anyhow!("Operator {} requires a value", operator)),
+        (_, Some(value)) => value,
+    };
+
+    if let FilterValue::Array(items) = value {
+        if items
+            .iter()
+            .any(|item| matches!(item, FilterValue::Array(_)))
+        {
+            return Err(anyhow!("Nested arrays are not supported in filter values"));
+        }
+    }
+
+    let ignore_case = rule.ignore_case;
+    let test = match (rule.operator, value) {
+        (Equals | NotEquals, _) => Test::Equals(value.clone(), ignore_case),
+        (In | NotIn, FilterValue::StringArray(items)) => Test::In(ValueSet::new(
+            &items
+                .iter()
+                .cloned()
+                .map(FilterValue::String)
+                .collect::<Vec<_>>(),
+            ignore_case,
+        )),
+        (In | NotIn, FilterValue::NumberArray(items)) => Test::In(ValueSet::new(
+            &items
+                .iter()
+                .copied()
+                .map(FilterValue::Number)
+                .collect::<Vec<_>>(),
+            ignore_case,
+        )),
+        (In | NotIn, FilterValue::Array(items)) => Test::In(ValueSet::new(items, ignore_case)),
+        (In | NotIn, _) => {
+            return Err(anyhow!(
+                "Operator {} requires an array value",
+                rule.operator
+            ));
+        }
+        (Matches | NotMatches, FilterValue::String(pattern)) => {
+            Test::Matches(build_regex(pattern, ignore_case)?)
+        }
+        (Contains | NotContains, FilterValue::String(s)) => {
+            Test::Contains(fold_case(s, ignore_case), ignore_case)
+        }
+        (StartsWith, FilterValue::String(s)) => {
+            Test::StartsWith(fold_case(s, ignore_case), ignore_case)
+        }
+        (EndsWith, FilterValue::String(s)) => {
+            Test::EndsWith(fold_case(s, ignore_case), ignore_case)
+        }
+        (Contains | NotContains | StartsWith | EndsWith | Matches | NotMatches, _) => {
+            return Err(anyhow!(
+                "Operator {} requires a string value",
+                rule.operator
+            ));
+        }
+        (Gt | Gte | Lt | Lte, FilterValue::Number(_) | FilterValue::Float(_)) => {
+            Test::Compare(Threshold::Number(value.clone()))
+        }
+        (Gt | Gte | Lt | Lte, FilterValue::String(s)) => {
+            let time = DateTime::parse_from_rfc3339(s).map_err(|_| {
+                anyhow!(
+                    "Operator {} requires a number or RFC3339 timestamp, got {:?}",
+                    rule.operator,
+                    s
+                )
+            })?;
+            Test::Compare(Threshold::Time(time))
+        }
+        (Gt | Gte | Lt | Lte, _) => {
+            return Err(anyhow!(
+                "Operator {} requires a number or RFC3339 timestamp",
+                rule.operator
+            ));
+        }
+        (Exists | NotExists, _) => unreachable!("handled above"),
+    };
+    Ok(compiled(test))
+}
+
+fn compile_route(route: &Route) -> Result<CompiledRoute> {
+    let when = route
+        .when
+        .iter()
+        .flatten()
+        .map(compile_filter)
+        .collect::<Result<_>>()?;
+
+    if route.action != RouteAction::Publish {
+        if route.path.is_some()
+            || route.expression.is_some()
+            || route.topic_template.is_some()
+            || route.allowed_topics.is_some()
+            || route.max_topic_length.is_some()
+            || !route.mapping.is_empty()
+        {
+            return Err(anyhow!(
+                "Route with action {} must not select a topic",
+                route.action.as_str()
+            ));
+        }
+        return Ok(CompiledRoute {
+            source: format!("action={}", route.action.as_str()),
+            when,
+            selector: Selector::Action(route.action),
+            mapping: Vec::new(),
+            lookup: HashMap::new(),
+            patterns: Vec::new(),
+            on_missing: route.on_missing,
+        });
+    }
+
+    let (source, selector) = match (&route.path, &route.expression, &route.topic_template) {
+        (Some(path), None, None) => {
+            if path.is_empty() {
+                return Err(anyhow!("Route path must not be empty"));
+            }
+            (path, Selector::Path(parse_request_path(path)?))
+        }
+        (None, Some(expression), None) => {
+            let program = compile_expression(expression)?;
+            if !matches!(
+                program.result_type(),
+                Type::String | Type::Int | Type::Double | Type::Bool | Type::Dyn
+            ) {
+                return Err(anyhow!(
+                    "Route expression {:?} must evaluate to a string, number or bool, not {}",
+                    expression,
+                    program.result_type()
+                ));
+            }
+            (expression, Selector::Expression(program))
+        }
+        (None, None, Some(template)) => {
+            if !route.mapping.is_empty() {
+                return Err(anyhow!("Route with topicTemplate must not have a mapping"));
+            }
+            (
+                template,
+                Selector::Template(compile_topic_template(template, route)?),
+            )
+        }
+        _ => {
+            return Err(anyhow!(
+                "Route must set exactly one of path, expression or topicTemplate"
+            ))
+        }
+    };
+    if route.topic_template.is_none()
+        && (route.allowed_topics.is_some() || route.max_topic_length.is_some())
+    {
+        return Err(anyhow!(
+            "allowedTopics and maxTopicLength require topicTemplate"
+        ));
+    }
+    if route.on_missing == MissingPolicy::Match {
+        return Err(anyhow!("onMissing: match is not supported on routes"));
+    }
+
+    let mut lookup: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut patterns = Vec::new();
+    let mut mapping = Vec::new();
+    for (index, entry) in route.mapping.iter().enumerate() {
+        let (description, matcher) = compile_mapping(entry)?;
+        match matcher {
+            Matcher::Values(values) => {
+                for value in values {
+                    let indices = lookup.entry(value.clone()).or_default();
+                    if !indices.contains(&index) {
+                        indices.push(index);
+                    }
+                }
+            }
+            Matcher::Pattern(pattern) => patterns.push((index, pattern)),
+        }
+        mapping.push((description, entry.topic.clone()));
+    }
+
+    Ok(CompiledRoute {
+        source: source.clone(),
+        when,
+        selector,
+        mapping,
+        lookup,
+        patterns,
+        on_missing: route.on_missing,
+    })
 }
 
-fn match_contains(extracted: &Value, filter_value: &FilterValue) -> bool {
-    match filter_value {
-        FilterValue::String(s) => {
-            if let Some(extracted_str) = extracted.as_str() {
-                extracted_str.contains(s)
-            } else {
-                false
+/// Compiles a route mapping into the exact values it lists, or a pattern,
+/// with a description for log messages
+fn compile_mapping(mapping: &RouteMapping) -> Result<(String, Matcher<'_>)> {
+    let matcher = match (
+        &mapping.value,
+        &mapping.values,
+        &mapping.prefix,
+        &mapping.glob,
+        &mapping.regex,
+        &mapping.range,
+    ) {
+        (Some(value), None, None, None, None, None) => (
+            format!("value={}", value),
+            Matcher::Values(std::slice::from_ref(value)),
+        ),
+        (None, Some(values), None, None, None, None) => {
+            (format!("values={:?}", values), Matcher::Values(values))
+        }
+        (None, None, Some(prefix), None, None, None) => (
+            format!("prefix={}", prefix),
+            Matcher::Pattern(Pattern::Prefix(prefix.clone())),
+        ),
+        (None, None, None, Some(glob), None, None) => (
+            format!("glob={}", glob),
+            Matcher::Pattern(Pattern::Regex(glob_regex(glob)?)),
+        ),
+        (None, None, None, None, Some(regex), None) => (
+            format!("regex={}", regex),
+            Matcher::Pattern(Pattern::Regex(build_regex(regex, false)?)),
+        ),
+        (None, None, None, None, None, Some(range)) => {
+            if range.gt.is_some() && range.gte.is_some()
+                || range.lt.is_some() && range.lte.is_some()
+            {
+                return Err(anyhow!(
+                    "Route range must not set both gt and gte, or both lt and lte"
+                ));
             }
+            if range == &NumericRange::default() {
+                return Err(anyhow!("Route range must set at least one bound"));
+            }
+            (
+                format!("range={:?}", range),
+                Matcher::Pattern(Pattern::Range(range.clone())),
+            )
+        }
+        _ => {
+            return Err(anyhow!(
+                "Route mapping for topic {:?} must set exactly one of value, values, prefix, glob, regex or range",
+                mapping.topic
+            ));
+        }
+    };
+    Ok(matcher)
+}
+
+/// Translates a glob (`*` any characters, `?` one character) into an anchored regex
+fn glob_regex(glob: &str) -> Result<Regex> {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str(".*"),
+            '?' => pattern.push('.'),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).map_err(|e| anyhow!("Invalid glob {:?}: {}", glob, e))
+}
+
+fn compile_topic_template(source: &str, route: &Route) -> Result<TopicTemplate> {
+    let template = Template::parse(source)?;
+    if let Some(literal) = template
+        .literals()
+        .find(|literal| !literal.chars().all(is_topic_char))
+    {
+        return Err(anyhow!(
+            "Topic template {:?} has characters not allowed in topic names: {:?}",
+            source,
+            literal
+        ));
+    }
+
+    let max_length = route.max_topic_length.unwrap_or(MAX_TOPIC_LENGTH);
+    if max_length == 0 || max_length > MAX_TOPIC_LENGTH {
+        return Err(anyhow!(
+            "maxTopicLength must be between 1 and {}",
+            MAX_TOPIC_LENGTH
+        ));
+    }
+    let allowed = route
+        .allowed_topics
+        .as_deref()
+        .map(|pattern| {
+            Regex::new(&format!("^(?:{})$", pattern))
+                .map_err(|e| anyhow!("Invalid regex {:?}: {}", pattern, e))
+        })
+        .transpose()?;
+
+    Ok(TopicTemplate {
+        template,
+        allowed,
+        max_length,
+    })
+}
+
+fn is_topic_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-')
+}
+
+/// Replaces characters that are not allowed in Kafka topic names with `_`
+pub(crate) fn sanitize_topic(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if is_topic_char(c) { c } else { '_' })
+        .collect()
+}
+
+fn compile_expression(expression: &str) -> Result<Program> {
+    Program::compile(expression).map_err(|e| anyhow!("Invalid expression {:?}: {}", expression, e))
+}
+
+/// Root of paths that select from the request rather than the body
+const REQUEST_ROOT: &str = "$request";
+
+/// Parses a path into the request section it selects from and the JSONPath
+/// within it. `$request.headers[...]`, `$request.query...`, `$request.method`
+/// and `$request.body...` select from the request; any other path, e.g.
+/// `$.headers`, is a plain JSONPath into the body, so body fields are never
+/// shadowed. Header names are lowercased, since that is how they are received.
+pub(crate) fn parse_request_path(path: &str) -> Result<RequestPath> {
+    let Some(request) = path.strip_prefix(REQUEST_ROOT) else {
+        return Ok(RequestPath {
+            section: Section::Body,
+            path: parse_json_path(path)?,
+        });
+    };
+
+    let (section, rest) = match split_name(request) {
+        Some(("body", rest)) => (Section::Body, rest.to_string()),
+        Some(("headers", rest)) => match split_name(rest) {
+            Some((name, rest)) => (Section::Headers, format!("[{:?}]{}", name.to_ascii_lowercase(), rest)),
+            None => (Section::Headers, rest.to_string()),
+        },
+        Some(("query", rest)) => (Section::Query, rest.to_string()),
+        Some(("method", rest)) => (Section::Method, rest.to_string()),
+        _ => {
+            return Err(anyhow!(
+                "Invalid request path {:?}: expected $request.body, $request.headers, $request.query or $request.method",
+                path
+            ))
+        }
+    };
+
+    Ok(RequestPath {
+        section,
+        path: parse_json_path(&format!("${}", rest))?,
+    })
+}
+
+/// Splits a leading `.name`, `['name']` or `["name"]` segment off a path
+fn split_name(path: &str) -> Option<(&str, &str)> {
+    if let Some(rest) = path.strip_prefix('.') {
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(rest.len());
+        return (end > 0).then(|| rest.split_at(end));
+    }
+    let rest = path.strip_prefix('[')?;
+    let quote = rest.chars().next().filter(|c| *c == '\'' || *c == '"')?;
+    let end = rest[1..].find(quote)? + 1;
+    let name = &rest[1..end];
+    let rest = rest[end + 1..].strip_prefix(']')?;
+    (!name.contains('\\')).then_some((name, rest))
+}
+
+/// Parses an RFC 9535 JSONPath expression. Paths without the leading `$`
+/// (e.g. `payload.account_id`) are taken relative to the root, as before.
+fn parse_json_path(path: &str) -> Result<JsonPath> {
+    let path = if path.starts_with('$') {
+        path.to_string()
+    } else {
+        format!("$.{}", path)
+    };
+    JsonPath::parse(&path).map_err(|e| anyhow!("Invalid JSONPath {:?}: {}", path, e))
+}
+
+/// Compares a payload value with a scalar filter value.
+/// Integers and floats compare numerically (`12 == 12.0`); otherwise the types
+/// must agree, so the string `"12"` never equals the number `12` and `null`
+/// only equals `null`.
+fn scalar_equals(extracted: &Value, expected: &FilterValue, ignore_case: bool) -> bool {
+    match (expected, extracted) {
+        (FilterValue::Null, Value::Null) => true,
+        (FilterValue::Bool(b), Value::Bool(e)) => b == e,
+        (FilterValue::String(s), Value::String(e)) => str_equals(e, s, ignore_case),
+        (FilterValue::Number(_) | FilterValue::Float(_), Value::Number(_)) => {
+            compare_numbers(extracted, expected) == Some(Ordering::Equal)
         }
         _ => false,
     }
 }
 
-fn value_matches_string(value: &Value, target: &str) -> bool {
-    if let Some(s) = value.as_str() {
-        s == target
-    } else if let Some(n) = value.as_i64() {
-        n.to_string() == target
-    } else if let Some(b) = value.as_bool() {
-        b.to_string() == target
+/// Orders a payload number against a numeric filter value, exactly when both
+/// are integers and as floats otherwise
+fn compare_numbers(extracted: &Value, expected: &FilterValue) -> Option<Ordering> {
+    match expected {
+        FilterValue::Number(n) => match extracted.as_i64() {
+            Some(e) => Some(e.cmp(n)),
+            None => extracted.as_f64()?.partial_cmp(&(*n as f64)),
+        },
+        FilterValue::Float(f) => extracted.as_f64()?.partial_cmp(f),
+        _ => None,
+    }
+}
+
+/// Applies a substring-style predicate to a string value; `needle` is
+/// already lowercased when `ignore_case` is set
+fn match_string(
+    extracted: &Value,
+    needle: &str,
+    ignore_case: bool,
+    predicate: fn(&str, &str) -> bool,
+) -> bool {
+    match extracted.as_str() {
+        Some(s) if ignore_case => predicate(&s.to_lowercase(), needle),
+        Some(s) => predicate(s, needle),
+        None => false,
+    }
+}
+
+fn build_regex(pattern: &str, ignore_case: bool) -> Result<Regex> {
+    RegexBuilder::new(pattern)
+        .case_insensitive(ignore_case)
+        .build()
+        .map_err(|e| anyhow!("Invalid regex {:?}: {}", pattern, e))
+}
+
+/// Orders the extracted value against the threshold.
+/// Numbers compare numerically (integers and floats alike), strings compare
+/// as RFC3339 timestamps; anything else is incomparable.
+fn compare(extracted: &Value, threshold: &Threshold) -> Option<Ordering> {
+    match threshold {
+        Threshold::Number(n) => compare_numbers(extracted, n),
+        Threshold::Time(threshold) => {
+            let extracted = DateTime::parse_from_rfc3339(extracted.as_str()?).ok()?;
+            Some(extracted.cmp(threshold))
+        }
+    }
+}
+
+fn str_equals(a: &str, b: &str, ignore_case: bool) -> bool {
+    if ignore_case {
+        a.chars()
+            .flat_map(char::to_lowercase)
+            .eq(b.chars().flat_map(char::to_lowercase))
+    } else {
+        a == b
+    }
+}
+
+fn fold_case(s: &str, ignore_case: bool) -> String {
+    if ignore_case {
+        s.to_lowercase()
     } else {
-        false
+        s.to_string()
+    }
+}
+
+/// The string a payload value is matched against route mapping values as
+fn route_key(value: &Value) -> Option<Cow<'_, str>> {
+    match value {
+        Value::String(s) => Some(Cow::Borrowed(s)),
+        Value::Bool(b) => Some(Cow::Borrowed(if *b { "true" } else { "false" })),
+        Value::Number(n) => n.as_i64().map(|n| Cow::Owned(n.to_string())),
+        _ => None,
     }
 }
 
@@ -150,6 +1211,69 @@ mod tests {
     use super::*;
     use serde_json::json;
 
+    fn event(body: &Value) -> WebhookEvent<'_> {
+        static EMPTY: Value = Value::Null;
+        WebhookEvent {
+            body,
+            headers: &EMPTY,
+            query: &EMPTY,
+            method: &EMPTY,
+            received_at: Utc::now(),
+        }
+    }
+
+    fn should_process_event(payload: &Value, filters: &[Filter]) -> Result<bool> {
+        Ok(Plan::compile(filters, &[])?.should_process_event(&event(payload))?)
+    }
+
+    fn evaluate(payload: &Value, filter: &Filter) -> Result<bool> {
+        should_process_event(payload, std::slice::from_ref(filter))
+    }
+
+    fn evaluate_filter(payload: &Value, rule: &FilterRule) -> Result<bool> {
+        evaluate(payload, &Filter::Rule(rule.clone()))
+    }
+
+    fn route_to_topic(payload: &Value, routes: &[Route]) -> Result<Option<String>> {
+        let plan = Plan::compile(&[], routes)?;
+        Ok(topics(&plan, &event(payload), RoutingMode::FirstMatch)?
+            .into_iter()
+            .next())
+    }
+
+    /// The filters, then the routes, as `handle_webhook` applies them
+    fn outcome<'a>(
+        plan: &'a Plan,
+        event: &WebhookEvent,
+        routing_mode: RoutingMode,
+    ) -> Result<Outcome<'a>, MissingPath> {
+        if !plan.should_process_event(event)? {
+            return Ok(Outcome::Filtered);
+        }
+        plan.route(event, routing_mode)
+    }
+
+    fn topics(
+        plan: &Plan,
+        event: &WebhookEvent,
+        routing_mode: RoutingMode,
+    ) -> Result<Vec<String>, MissingPath> {
+        match outcome(plan, event, routing_mode)? {
+            Outcome::Publish { topics, .. } => {
+                Ok(topics.into_iter().map(Cow::into_owned).collect())
+            }
+            other => panic!("Expected topics, got {:?}", other),
+        }
+    }
+
+    fn validate_rules(filters: &[Filter], routes: &[Route]) -> Result<()> {
+        Plan::compile(filters, routes).map(|_| ())
+    }
+
+    fn query_json_path<'a>(payload: &'a Value, path: &str) -> Result<Vec<&'a Value>> {
+        Ok(parse_request_path(path)?.path.query(payload).all())
+    }
+
     #[test]
     fn test_extract_json_path_simple() {
         let payload = json!({
@@ -159,11 +1283,117 @@ mod tests {
             }
         });
 
-        let result = extract_json_path(&payload, "$.event").unwrap();
-        assert_eq!(result, json!("meeting.started"));
+        let result = query_json_path(&payload, "$.event").unwrap();
+        assert_eq!(result, vec![&json!("meeting.started")]);
+
+        let result = query_json_path(&payload, "$.payload.account_id").unwrap();
+        assert_eq!(result, vec![&json!("acc123")]);
+
+        // Paths without the leading `$` still resolve from the root
+        let result = query_json_path(&payload, "payload.account_id").unwrap();
+        assert_eq!(result, vec![&json!("acc123")]);
+    }
+
+    #[test]
+    fn test_json_path_rfc9535_syntax() {
+        let payload = json!({
+            "a.b": 1,
+            "items": [
+                { "sku": "A1", "qty": 1 },
+                { "sku": "B2", "qty": 3, "meta": { "sku": "C3" } }
+            ]
+        });
+
+        let query = |path| query_json_path(&payload, path).unwrap();
+        assert_eq!(query("$['a.b']"), vec![&json!(1)]);
+        assert_eq!(query("$.items[*].sku"), vec![&json!("A1"), &json!("B2")]);
+        assert_eq!(query("$.items[-1].sku"), vec![&json!("B2")]);
+        assert_eq!(query("$.items[?(@.qty > 1)].sku"), vec![&json!("B2")]);
+        assert_eq!(query("$..sku").len(), 3);
+        assert!(query("$.missing").is_empty());
+
+        assert!(validate_rules(
+            &[rule("$.items[", FilterOperator::Exists, FilterValue::Null)],
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_filter_match_mode() {
+        let payload = json!({ "items": [{ "sku": "A1" }, { "sku": "B2" }] });
+        let with_mode = |operator, value: &str, match_mode| {
+            Filter::Rule(FilterRule {
+                path: "$.items[*].sku".to_string(),
+                operator,
+                match_mode,
+                value: Some(FilterValue::String(value.to_string())),
+                ignore_case: false,
+                on_missing: MissingPolicy::NoMatch,
+            })
+        };
+
+        assert!(evaluate(
+            &payload,
+            &with_mode(FilterOperator::Equals, "B2", MatchMode::Any)
+        )
+        .unwrap());
+        assert!(!evaluate(
+            &payload,
+            &with_mode(FilterOperator::Equals, "B2", MatchMode::All)
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &with_mode(FilterOperator::Matches, "^[A-Z][0-9]$", MatchMode::All)
+        )
+        .unwrap());
 
-        let result = extract_json_path(&payload, "$.payload.account_id").unwrap();
-        assert_eq!(result, json!("acc123"));
+        // Negated operators: no value equals B2 / not every value equals B2
+        assert!(!evaluate(
+            &payload,
+            &with_mode(FilterOperator::NotEquals, "B2", MatchMode::Any)
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &with_mode(FilterOperator::NotEquals, "B2", MatchMode::All)
+        )
+        .unwrap());
+
+        // Filter expressions select the values to compare
+        let bulk = json!({ "items": [{ "sku": "A1", "qty": 1 }, { "sku": "B2", "qty": 5 }] });
+        let bulk_rule = rule(
+            "$.items[?(@.qty >= 5)].sku",
+            FilterOperator::In,
+            FilterValue::StringArray(vec!["B2".to_string()]),
+        );
+        assert!(evaluate(&bulk, &bulk_rule).unwrap());
+    }
+
+    #[test]
+    fn test_route_any_selected_value() {
+        let payload = json!({ "tags": ["internal", "billing"] });
+        let routes = vec![Route {
+            when: None,
+            action: RouteAction::Publish,
+            path: Some("$.tags[*]".to_string()),
+            expression: None,
+            topic_template: None,
+            allowed_topics: None,
+            max_topic_length: None,
+            mapping: vec![crate::crd::RouteMapping {
+                value: Some("billing".to_string()),
+                topic: "billing.events".to_string(),
+                ..Default::default()
+            }],
+            on_missing: MissingPolicy::NoMatch,
+        }];
+
+        assert_eq!(
+            route_to_topic(&payload, &routes).unwrap(),
+            Some("billing.events".to_string())
+        );
     }
 
     #[test]
@@ -174,18 +1404,24 @@ mod tests {
             }
         });
 
-        let filter = Filter {
+        let filter = FilterRule {
             path: "$.payload.account_id".to_string(),
-            operator: "equals".to_string(),
-            value: FilterValue::String("acc123".to_string()),
+            operator: FilterOperator::Equals,
+            value: Some(FilterValue::String("acc123".to_string())),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
         };
 
         assert!(evaluate_filter(&payload, &filter).unwrap());
 
-        let filter_no_match = Filter {
+        let filter_no_match = FilterRule {
             path: "$.payload.account_id".to_string(),
-            operator: "equals".to_string(),
-            value: FilterValue::String("acc999".to_string()),
+            operator: FilterOperator::Equals,
+            value: Some(FilterValue::String("acc999".to_string())),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
         };
 
         assert!(!evaluate_filter(&payload, &filter_no_match).unwrap());
@@ -199,24 +1435,30 @@ mod tests {
             }
         });
 
-        let filter = Filter {
+        let filter = FilterRule {
             path: "$.payload.account_id".to_string(),
-            operator: "not_in".to_string(),
-            value: FilterValue::StringArray(vec![
+            operator: FilterOperator::NotIn,
+            value: Some(FilterValue::StringArray(vec![
                 "blocked1".to_string(),
                 "blocked2".to_string(),
-            ]),
+            ])),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
         };
 
         assert!(evaluate_filter(&payload, &filter).unwrap());
 
-        let filter_blocked = Filter {
+        let filter_blocked = FilterRule {
             path: "$.payload.account_id".to_string(),
-            operator: "not_in".to_string(),
-            value: FilterValue::StringArray(vec![
+            operator: FilterOperator::NotIn,
+            value: Some(FilterValue::StringArray(vec![
                 "acc123".to_string(),
                 "blocked2".to_string(),
-            ]),
+            ])),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
         };
 
         assert!(!evaluate_filter(&payload, &filter_blocked).unwrap());
@@ -229,13 +1471,16 @@ mod tests {
         });
 
         // Only allow meeting.started and meeting.ended
-        let filter = Filter {
+        let filter = FilterRule {
             path: "$.event".to_string(),
-            operator: "in".to_string(),
-            value: FilterValue::StringArray(vec![
+            operator: FilterOperator::In,
+            value: Some(FilterValue::StringArray(vec![
                 "meeting.started".to_string(),
                 "meeting.ended".to_string(),
-            ]),
+            ])),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
         };
 
         assert!(evaluate_filter(&payload, &filter).unwrap());
@@ -250,17 +1495,26 @@ mod tests {
         });
 
         let routes = vec![Route {
-            path: "$.payload.account_id".to_string(),
+            when: None,
+            action: RouteAction::Publish,
+            path: Some("$.payload.account_id".to_string()),
+            expression: None,
+            topic_template: None,
+            allowed_topics: None,
+            max_topic_length: None,
             mapping: vec![
                 crate::crd::RouteMapping {
-                    value: "acc123".to_string(),
+                    value: Some("acc123".to_string()),
                     topic: "zoom-acc123".to_string(),
+                    ..Default::default()
                 },
                 crate::crd::RouteMapping {
-                    value: "acc456".to_string(),
+                    value: Some("acc456".to_string()),
                     topic: "zoom-acc456".to_string(),
+                    ..Default::default()
                 },
             ],
+            on_missing: MissingPolicy::NoMatch,
         }];
 
         let result = route_to_topic(&payload, &routes).unwrap();
@@ -276,13 +1530,19 @@ mod tests {
         });
 
         let routes = vec![Route {
-            path: "$.payload.account_id".to_string(),
-            mapping: vec![
-                crate::crd::RouteMapping {
-                    value: "acc123".to_string(),
-                    topic: "zoom-acc123".to_string(),
-                },
-            ],
+            when: None,
+            action: RouteAction::Publish,
+            path: Some("$.payload.account_id".to_string()),
+            expression: None,
+            topic_template: None,
+            allowed_topics: None,
+            max_topic_length: None,
+            mapping: vec![crate::crd::RouteMapping {
+                value: Some("acc123".to_string()),
+                topic: "zoom-acc123".to_string(),
+                ..Default::default()
+            }],
+            on_missing: MissingPolicy::NoMatch,
         }];
 
         let result = route_to_topic(&payload, &routes).unwrap();
@@ -302,29 +1562,1007 @@ mod tests {
         });
 
         // Filter: Discard events from blocked accounts
-        let filters = vec![Filter {
+        let filters = vec![Filter::Rule(FilterRule {
             path: "$.payload.account_id".to_string(),
-            operator: "not_in".to_string(),
-            value: FilterValue::StringArray(vec![
+            operator: FilterOperator::NotIn,
+            value: Some(FilterValue::StringArray(vec![
                 "blocked_acc_1".to_string(),
                 "blocked_acc_2".to_string(),
-            ]),
-        }];
+            ])),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
+        })];
 
         assert!(should_process_event(&payload, &filters).unwrap());
 
         // Route: Send to account-specific topic
         let routes = vec![Route {
-            path: "$.payload.account_id".to_string(),
-            mapping: vec![
-                crate::crd::RouteMapping {
-                    value: "zoom_acc_123".to_string(),
-                    topic: "zoom.account-123.events".to_string(),
-                },
-            ],
+            when: None,
+            action: RouteAction::Publish,
+            path: Some("$.payload.account_id".to_string()),
+            expression: None,
+            topic_template: None,
+            allowed_topics: None,
+            max_topic_length: None,
+            mapping: vec![crate::crd::RouteMapping {
+                value: Some("zoom_acc_123".to_string()),
+                topic: "zoom.account-123.events".to_string(),
+                ..Default::default()
+            }],
+            on_missing: MissingPolicy::NoMatch,
         }];
 
         let topic = route_to_topic(&payload, &routes).unwrap();
         assert_eq!(topic, Some("zoom.account-123.events".to_string()));
     }
-}
\ No newline at end of file
+
+    fn rule(path: &str, operator: FilterOperator, value: FilterValue) -> Filter {
+        Filter::Rule(FilterRule {
+            path: path.to_string(),
+            operator,
+            value: Some(value),
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
+        })
+    }
+
+    #[test]
+    fn test_filter_groups_deserialize() {
+        let filters: Vec<Filter> = serde_json::from_value(json!([
+            { "any": [
+                { "path": "$.event", "operator": "equals", "value": "meeting.started" },
+                { "not": { "path": "$.payload.account_id", "operator": "in", "value": ["a", "b"] } }
+            ] },
+            { "all": [] }
+        ]))
+        .unwrap();
+
+        assert!(matches!(&filters[0], Filter::Any { any } if any.len() == 2));
+        assert!(matches!(&filters[1], Filter::All { all } if all.is_empty()));
+    }
+
+    #[test]
+    fn test_filter_any_group() {
+        // event is meeting.started OR account is in the VIP list
+        let filters = vec![Filter::Any {
+            any: vec![
+                rule(
+                    "$.event",
+                    FilterOperator::Equals,
+                    FilterValue::String("meeting.started".to_string()),
+                ),
+                rule(
+                    "$.payload.account_id",
+                    FilterOperator::In,
+                    FilterValue::StringArray(vec!["vip1".to_string(), "vip2".to_string()]),
+                ),
+            ],
+        }];
+
+        let started = json!({ "event": "meeting.started", "payload": { "account_id": "acc1" } });
+        let vip = json!({ "event": "meeting.ended", "payload": { "account_id": "vip2" } });
+        let other = json!({ "event": "meeting.ended", "payload": { "account_id": "acc1" } });
+
+        assert!(should_process_event(&started, &filters).unwrap());
+        assert!(should_process_event(&vip, &filters).unwrap());
+        assert!(!should_process_event(&other, &filters).unwrap());
+    }
+
+    #[test]
+    fn test_filter_not_and_nested_all() {
+        // Drop events that are both test-mode AND from the sandbox account
+        let filters = vec![Filter::Not {
+            not: Box::new(Filter::All {
+                all: vec![
+                    rule(
+                        "$.mode",
+                        FilterOperator::Equals,
+                        FilterValue::String("test".to_string()),
+                    ),
+                    rule(
+                        "$.account",
+                        FilterOperator::Equals,
+                        FilterValue::String("sandbox".to_string()),
+                    ),
+                ],
+            }),
+        }];
+
+        let dropped = json!({ "mode": "test", "account": "sandbox" });
+        let kept = json!({ "mode": "test", "account": "acme" });
+
+        assert!(!should_process_event(&dropped, &filters).unwrap());
+        assert!(should_process_event(&kept, &filters).unwrap());
+    }
+
+    #[test]
+    fn test_validate_rules_recurses_into_groups() {
+        let filters = vec![Filter::Any {
+            any: vec![Filter::Not {
+                not: Box::new(rule(
+                    "$.event",
+                    FilterOperator::Matches,
+                    FilterValue::String("(".to_string()),
+                )),
+            }],
+        }];
+        let err = validate_rules(&filters, &[]).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid regex \"(\""));
+    }
+
+    #[test]
+    fn test_validate_rule_values() {
+        let missing = Filter::Rule(FilterRule {
+            path: "$.event".to_string(),
+            operator: FilterOperator::Equals,
+            value: None,
+            ignore_case: false,
+            on_missing: MissingPolicy::NoMatch,
+            match_mode: MatchMode::Any,
+        });
+        let err = validate_rules(&[missing], &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Operator equals requires a value");
+
+        let not_a_time = rule(
+            "$.created",
+            FilterOperator::Gt,
+            FilterValue::String("yesterday".to_string()),
+        );
+        assert!(validate_rules(&[not_a_time], &[]).is_err());
+
+        let exists: Filter =
+            serde_json::from_value(json!({ "path": "$.event", "operator": "exists" })).unwrap();
+        assert!(validate_rules(&[exists], &[]).is_ok());
+
+        let unknown = serde_json::from_value::<FilterRule>(
+            json!({ "path": "$.event", "operator": "approximately", "value": "x" }),
+        );
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn test_filter_string_operators() {
+        let payload = json!({ "event": "Meeting.Started", "email": "alice@example.com" });
+
+        assert!(should_process_event(
+            &payload,
+            &[rule(
+                "$.event",
+                FilterOperator::StartsWith,
+                FilterValue::String("Meeting.".to_string())
+            )]
+        )
+        .unwrap());
+        assert!(!should_process_event(
+            &payload,
+            &[rule(
+                "$.event",
+                FilterOperator::EndsWith,
+                FilterValue::String("started".to_string())
+            )]
+        )
+        .unwrap());
+        assert!(should_process_event(
+            &payload,
+            &[rule(
+                "$.email",
+                FilterOperator::Matches,
+                FilterValue::String(r"@example\.com$".to_string())
+            )]
+        )
+        .unwrap());
+        assert!(should_process_event(
+            &payload,
+            &[rule(
+                "$.email",
+                FilterOperator::NotMatches,
+                FilterValue::String("^bob@".to_string())
+            )]
+        )
+        .unwrap());
+
+        let ignore_case: Vec<Filter> = serde_json::from_value(json!([
+            { "path": "$.event", "operator": "equals", "value": "meeting.started", "ignoreCase": true },
+            { "path": "$.event", "operator": "ends_with", "value": "STARTED", "ignoreCase": true },
+            { "path": "$.event", "operator": "matches", "value": "^meeting\\.", "ignoreCase": true }
+        ]))
+        .unwrap();
+        assert!(should_process_event(&payload, &ignore_case).unwrap());
+    }
+
+    #[test]
+    fn test_filter_comparisons() {
+        let payload = json!({
+            "amount": 12.5,
+            "count": 3,
+            "created_at": "2024-05-01T10:00:00Z"
+        });
+
+        let number = |n| FilterValue::Number(n);
+        assert!(evaluate(&payload, &rule("$.amount", FilterOperator::Gt, number(12))).unwrap());
+        assert!(!evaluate(&payload, &rule("$.amount", FilterOperator::Lte, number(12))).unwrap());
+        assert!(evaluate(&payload, &rule("$.count", FilterOperator::Gte, number(3))).unwrap());
+        assert!(!evaluate(&payload, &rule("$.count", FilterOperator::Lt, number(3))).unwrap());
+
+        // Timestamps compare chronologically, regardless of offset
+        let time = |s: &str| FilterValue::String(s.to_string());
+        assert!(evaluate(
+            &payload,
+            &rule(
+                "$.created_at",
+                FilterOperator::Gt,
+                time("2024-05-01T11:00:00+02:00")
+            )
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &rule(
+                "$.created_at",
+                FilterOperator::Lt,
+                time("2024-05-02T00:00:00Z")
+            )
+        )
+        .unwrap());
+
+        // Values that cannot be compared never match
+        assert!(!evaluate(
+            &payload,
+            &rule("$.created_at", FilterOperator::Gt, number(0))
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_filter_value_types_deserialize() {
+        let value = |v| serde_json::from_value::<FilterValue>(v).unwrap();
+
+        assert_eq!(value(json!(null)), FilterValue::Null);
+        assert_eq!(value(json!(false)), FilterValue::Bool(false));
+        assert_eq!(value(json!(12)), FilterValue::Number(12));
+        assert_eq!(value(json!(12.5)), FilterValue::Float(12.5));
+        assert_eq!(
+            value(json!(["a"])),
+            FilterValue::StringArray(vec!["a".to_string()])
+        );
+        assert_eq!(value(json!([1, 2])), FilterValue::NumberArray(vec![1, 2]));
+        assert_eq!(
+            value(json!(["a", 1, 1.5, true, null])),
+            FilterValue::Array(vec![
+                FilterValue::String("a".to_string()),
+                FilterValue::Number(1),
+                FilterValue::Float(1.5),
+                FilterValue::Bool(true),
+                FilterValue::Null,
+            ])
+        );
+    }
+
+    #[test]
+    fn test_filter_bool_float_and_null() {
+        // Drop Stripe test-mode events
+        let live_only = vec![rule(
+            "$.livemode",
+            FilterOperator::Equals,
+            FilterValue::Bool(true),
+        )];
+        assert!(should_process_event(&json!({ "livemode": true }), &live_only).unwrap());
+        assert!(!should_process_event(&json!({ "livemode": false }), &live_only).unwrap());
+        assert!(!should_process_event(&json!({ "livemode": "true" }), &live_only).unwrap());
+
+        let payload = json!({ "amount": 12.5, "count": 12, "deleted_at": null });
+        assert!(evaluate(
+            &payload,
+            &rule("$.amount", FilterOperator::Equals, FilterValue::Float(12.5))
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &rule("$.count", FilterOperator::Equals, FilterValue::Float(12.0))
+        )
+        .unwrap());
+        assert!(!evaluate(
+            &payload,
+            &rule("$.amount", FilterOperator::Equals, FilterValue::Number(12))
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &rule("$.amount", FilterOperator::Lt, FilterValue::Float(12.75))
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &rule("$.deleted_at", FilterOperator::Equals, FilterValue::Null)
+        )
+        .unwrap());
+        assert!(!evaluate(
+            &payload,
+            &rule("$.count", FilterOperator::Equals, FilterValue::Null)
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_filter_mixed_array_and_cross_type() {
+        let allowed = FilterValue::Array(vec![
+            FilterValue::String("12".to_string()),
+            FilterValue::Float(2.5),
+            FilterValue::Null,
+        ]);
+        let is_allowed = |payload| {
+            evaluate(&payload, &rule("$.v", FilterOperator::In, allowed.clone())).unwrap()
+        };
+
+        assert!(is_allowed(json!({ "v": "12" })));
+        assert!(is_allowed(json!({ "v": 2.5 })));
+        assert!(is_allowed(json!({ "v": null })));
+        // No coercion between strings and numbers
+        assert!(!is_allowed(json!({ "v": 12 })));
+        assert!(!is_allowed(json!({ "v": "2.5" })));
+
+        let nested = rule(
+            "$.v",
+            FilterOperator::In,
+            FilterValue::Array(vec![FilterValue::Array(vec![])]),
+        );
+        assert!(validate_rules(&[nested], &[]).is_err());
+    }
+
+    #[test]
+    fn test_missing_path_policies() {
+        let payload = json!({ "event": "app.deauthorized" });
+        let with_policy = |on_missing| {
+            Filter::Rule(FilterRule {
+                path: "$.payload.account_id".to_string(),
+                operator: FilterOperator::NotIn,
+                match_mode: MatchMode::Any,
+                value: Some(FilterValue::StringArray(vec!["blocked".to_string()])),
+                ignore_case: false,
+                on_missing,
+            })
+        };
+
+        assert!(!evaluate(&payload, &with_policy(MissingPolicy::NoMatch)).unwrap());
+        assert!(evaluate(&payload, &with_policy(MissingPolicy::Match)).unwrap());
+        // A negated group flips the no-match result like any other
+        let not = Filter::Not {
+            not: Box::new(with_policy(MissingPolicy::NoMatch)),
+        };
+        assert!(evaluate(&payload, &not).unwrap());
+
+        let err = evaluate(&payload, &with_policy(MissingPolicy::Reject)).unwrap_err();
+        let missing = err.downcast_ref::<MissingPath>().unwrap();
+        assert_eq!(missing.path, "$.payload.account_id");
+        assert_eq!(missing.policy, MissingPolicy::Reject);
+    }
+
+    #[test]
+    fn test_route_missing_path() {
+        let payload = json!({ "event": "app.deauthorized" });
+        let mut routes: Vec<Route> = serde_json::from_value(json!([
+            { "path": "$.payload.account_id", "mapping": [{ "value": "acc1", "topic": "acc1.events" }] },
+            { "path": "$.event", "mapping": [{ "value": "app.deauthorized", "topic": "app.events" }] }
+        ]))
+        .unwrap();
+
+        // Missing paths skip the route by default
+        assert_eq!(
+            route_to_topic(&payload, &routes).unwrap(),
+            Some("app.events".to_string())
+        );
+
+        routes[0].on_missing = MissingPolicy::DeadLetter;
+        let err = route_to_topic(&payload, &routes).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<MissingPath>().unwrap().policy,
+            MissingPolicy::DeadLetter
+        );
+        assert!(Plan::compile(&[], &routes).unwrap().uses_dead_letter());
+
+        routes[0].on_missing = MissingPolicy::Match;
+        assert!(validate_rules(&[], &routes).is_err());
+    }
+
+    #[test]
+    fn test_plan_compile() {
+        let scalar_in = rule(
+            "$.event",
+            FilterOperator::In,
+            FilterValue::String("a".to_string()),
+        );
+        let err = validate_rules(&[scalar_in], &[]).unwrap_err();
+        assert_eq!(err.to_string(), "Operator in requires an array value");
+
+        // With duplicate mapping values the first mapping wins, as before
+        let routes: Vec<Route> = serde_json::from_value(json!([{
+            "path": "$.ids[*]",
+            "mapping": [
+                { "value": "2", "topic": "second" },
+                { "value": "1", "topic": "first" },
+                { "value": "2", "topic": "duplicate" }
+            ]
+        }]))
+        .unwrap();
+        let plan = Plan::compile(&[], &routes).unwrap();
+        assert_eq!(
+            topics(
+                &plan,
+                &event(&json!({ "ids": [1, 2] })),
+                RoutingMode::FirstMatch
+            )
+            .unwrap(),
+            vec!["second"]
+        );
+        assert_eq!(
+            topics(
+                &plan,
+                &event(&json!({ "ids": [1] })),
+                RoutingMode::FirstMatch
+            )
+            .unwrap(),
+            vec!["first"]
+        );
+        assert!(topics(
+            &plan,
+            &event(&json!({ "ids": [3] })),
+            RoutingMode::FirstMatch
+        )
+        .unwrap()
+        .is_empty());
+
+        let empty = Plan::default();
+        assert!(empty.should_process_event(&event(&json!({}))).unwrap());
+        assert!(!empty.uses_dead_letter());
+    }
+
+    #[test]
+    fn test_filter_exists() {
+        let payload = json!({ "payload": { "object": { "id": "1" } } });
+        let exists = |path: &str, operator| {
+            Filter::Rule(FilterRule {
+                path: path.to_string(),
+                operator,
+                value: None,
+                ignore_case: false,
+                on_missing: MissingPolicy::NoMatch,
+                match_mode: MatchMode::Any,
+            })
+        };
+
+        assert!(evaluate(
+            &payload,
+            &exists("$.payload.object.id", FilterOperator::Exists)
+        )
+        .unwrap());
+        assert!(!evaluate(
+            &payload,
+            &exists("$.payload.object.topic", FilterOperator::Exists)
+        )
+        .unwrap());
+        assert!(evaluate(
+            &payload,
+            &exists("$.payload.object.topic", FilterOperator::NotExists)
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_expression_filters() {
+        let filters: Vec<Filter> = serde_json::from_value(json!([
+            { "expression": "body.amount > 100 && headers['x-env'] == 'prod'" },
+            { "any": [{ "expression": "query.source == 'billing'" }, { "path": "$.priority", "operator": "equals", "value": true }] }
+        ]))
+        .unwrap();
+        assert!(matches!(filters[0], Filter::Expression(_)));
+
+        let plan = Plan::compile(&filters, &[]).unwrap();
+        let headers = json!({ "x-env": "prod" });
+        let query = json!({ "source": "billing" });
+        let method = json!("POST");
+        let body = json!({ "amount": 150 });
+        let mut webhook = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &query,
+            method: &method,
+            received_at: Utc::now(),
+        };
+        assert!(plan.should_process_event(&webhook).unwrap());
+
+        let small = json!({ "amount": 50 });
+        webhook.body = &small;
+        assert!(!plan.should_process_event(&webhook).unwrap());
+
+        // A failed evaluation (here a missing field) follows onMissing
+        let missing = json!({});
+        webhook.body = &missing;
+        assert!(!plan.should_process_event(&webhook).unwrap());
+
+        let mut reject = filters.clone();
+        if let Filter::Expression(filter) = &mut reject[0] {
+            filter.on_missing = MissingPolicy::Reject;
+        }
+        let err = Plan::compile(&reject, &[])
+            .unwrap()
+            .should_process_event(&webhook)
+            .unwrap_err();
+        assert_eq!(err.policy, MissingPolicy::Reject);
+        assert_eq!(
+            err.to_string(),
+            "Expression body.amount > 100 && headers['x-env'] == 'prod' failed: no such key: amount"
+        );
+    }
+
+    #[test]
+    fn test_expression_type_checked_at_compile() {
+        let filter = |expression: &str| {
+            let filters: Vec<Filter> =
+                serde_json::from_value(json!([{ "expression": expression }])).unwrap();
+            validate_rules(&filters, &[]).map_err(|e| e.to_string())
+        };
+
+        assert!(filter("body.event.startsWith('meeting.')").is_ok());
+        assert_eq!(
+            filter("size(body.items)").unwrap_err(),
+            "Expression \"size(body.items)\" must evaluate to bool, not int"
+        );
+        assert_eq!(
+            filter("body.amount > 'x' && amount").unwrap_err(),
+            "Invalid expression \"body.amount > 'x' && amount\": undeclared reference to 'amount'"
+        );
+        assert!(filter("headers['x-env'] > 1")
+            .unwrap_err()
+            .contains("no matching overload"));
+    }
+
+    #[test]
+    fn test_expression_routes() {
+        let routes: Vec<Route> = serde_json::from_value(json!([
+            {
+                "expression": "body.region.lowerAscii()",
+                "mapping": [{ "value": "eu", "topic": "eu.events" }]
+            },
+            {
+                "expression": "size(body.items) > 10",
+                "mapping": [{ "value": "true", "topic": "bulk.events" }]
+            }
+        ]))
+        .unwrap();
+
+        let payload = json!({ "region": "EU", "items": [] });
+        assert_eq!(
+            route_to_topic(&payload, &routes).unwrap(),
+            Some("eu.events".to_string())
+        );
+        let payload = json!({ "region": "us", "items": vec![0; 11] });
+        assert_eq!(
+            route_to_topic(&payload, &routes).unwrap(),
+            Some("bulk.events".to_string())
+        );
+
+        // Missing fields skip the route unless onMissing says otherwise
+        let payload = json!({ "items": [] });
+        assert_eq!(route_to_topic(&payload, &routes).unwrap(), None);
+
+        let both: Vec<Route> = serde_json::from_value(json!([
+            { "path": "$.region", "expression": "body.region", "mapping": [] }
+        ]))
+        .unwrap();
+        assert_eq!(
+            validate_rules(&[], &both).unwrap_err().to_string(),
+            "Route must set exactly one of path, expression or topicTemplate"
+        );
+        let list: Vec<Route> =
+            serde_json::from_value(json!([{ "expression": "[1, 2]", "mapping": [] }])).unwrap();
+        assert!(validate_rules(&[], &list).is_err());
+    }
+
+    #[test]
+    fn test_request_paths() {
+        let body = json!({ "action": "opened", "headers": "body field" });
+        let headers = json!({ "x-github-event": "pull_request" });
+        let query = json!({ "tenant": "acme" });
+        let method = json!("POST");
+        let webhook = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &query,
+            method: &method,
+            received_at: Utc::now(),
+        };
+        let select = |path: &str| -> Vec<Value> {
+            parse_request_path(path)
+                .unwrap()
+                .query(&webhook)
+                .into_iter()
+                .cloned()
+                .collect()
+        };
+
+        assert_eq!(
+            select("$request.headers['x-github-event']"),
+            vec![json!("pull_request")]
+        );
+        assert_eq!(
+            select("$request.headers[\"X-GitHub-Event\"]"),
+            vec![json!("pull_request")]
+        );
+        assert_eq!(
+            select("$request.headers.X-GitHub-Event"),
+            vec![json!("pull_request")]
+        );
+        assert_eq!(select("$request.query.tenant"), vec![json!("acme")]);
+        assert_eq!(select("$request['query']['tenant']"), vec![json!("acme")]);
+        assert_eq!(select("$request.method"), vec![json!("POST")]);
+        assert_eq!(select("$request.body.action"), vec![json!("opened")]);
+        assert_eq!(select("$request.body.headers"), vec![json!("body field")]);
+        // `$` paths always select from the body, even for request section names
+        assert_eq!(select("$.headers"), vec![json!("body field")]);
+        assert_eq!(select("$.query.tenant"), Vec::<Value>::new());
+        assert_eq!(select("$.action"), vec![json!("opened")]);
+        assert_eq!(select("action"), vec![json!("opened")]);
+
+        assert!(parse_request_path("$request.cookies").is_err());
+        assert!(parse_request_path("$request").is_err());
+
+        let routes: Vec<Route> = serde_json::from_value(json!([{
+            "path": "$request.query.tenant",
+            "mapping": [{ "value": "acme", "topic": "acme.events" }]
+        }]))
+        .unwrap();
+        let plan = Plan::compile(
+            &[rule(
+                "$request.headers['x-github-event']",
+                FilterOperator::Equals,
+                FilterValue::String("pull_request".to_string()),
+            )],
+            &routes,
+        )
+        .unwrap();
+        assert!(plan.should_process_event(&webhook).unwrap());
+        assert_eq!(
+            topics(&plan, &webhook, RoutingMode::FirstMatch).unwrap(),
+            vec!["acme.events"]
+        );
+    }
+
+    #[test]
+    fn test_topic_template_routes() {
+        let mut routes: Vec<Route> = serde_json::from_value(json!([{
+            "topicTemplate": "zoom.{$.payload.account_id}.events",
+            "allowedTopics": "zoom\\.acc[a-z0-9_]+\\.events",
+            "maxTopicLength": 40
+        }]))
+        .unwrap();
+
+        let payload = |account_id: Value| json!({ "payload": { "account_id": account_id } });
+        let topic = |account_id: Value| route_to_topic(&payload(account_id), &routes);
+        assert_eq!(
+            topic(json!("acc123")).unwrap(),
+            Some("zoom.acc123.events".to_string())
+        );
+        // Values are sanitized to legal topic characters
+        assert_eq!(
+            topic(json!("acc 1/2")).unwrap(),
+            Some("zoom.acc_1_2.events".to_string())
+        );
+        // Topics outside the allowlist, too long or missing fall through to the default
+        assert_eq!(topic(json!("admin")).unwrap(), None);
+        assert_eq!(
+            topic(json!(format!("acc{}", "x".repeat(30)))).unwrap(),
+            None
+        );
+        assert_eq!(route_to_topic(&json!({}), &routes).unwrap(), None);
+
+        routes[0].on_missing = MissingPolicy::Reject;
+        let err = route_to_topic(&payload(json!("admin")), &routes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Topic \"zoom.admin.events\" from template zoom.{$.payload.account_id}.events is not allowed"
+        );
+        let err = route_to_topic(&json!({}), &routes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Path matched no values: $.payload.account_id"
+        );
+
+        let invalid = |route: Value| {
+            validate_rules(&[], &[serde_json::from_value(route).unwrap()])
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(
+            invalid(json!({ "topicTemplate": "zoom/{$.a}" })).contains("characters not allowed")
+        );
+        assert!(
+            invalid(json!({ "topicTemplate": "zoom.{$.a}", "maxTopicLength": 300 }))
+                .contains("maxTopicLength")
+        );
+        assert!(
+            invalid(json!({ "topicTemplate": "zoom.{$.a}", "allowedTopics": "(" }))
+                .contains("Invalid regex")
+        );
+        assert!(invalid(json!({ "path": "$.a", "allowedTopics": "zoom" }))
+            .contains("require topicTemplate"));
+        assert!(invalid(json!({
+            "topicTemplate": "zoom.{$.a}",
+            "mapping": [{ "value": "a", "topic": "b" }]
+        }))
+        .contains("must not have a mapping"));
+    }
+
+    #[test]
+    fn test_route_to_topics_fan_out() {
+        let routes: Vec<Route> = serde_json::from_value(json!([
+            {
+                "path": "$.event",
+                "mapping": [
+                    { "value": "meeting.ended", "topic": "billing.events" },
+                    { "value": "meeting.started", "topic": "live.events" },
+                    { "value": "meeting.ended", "topic": "analytics.events" }
+                ]
+            },
+            {
+                "path": "$.payload.tags[*]",
+                "mapping": [
+                    { "value": "vip", "topic": "vip.events" },
+                    { "value": "trial", "topic": "analytics.events" }
+                ]
+            }
+        ]))
+        .unwrap();
+        let plan = Plan::compile(&[], &routes).unwrap();
+        let payload = json!({ "event": "meeting.ended", "payload": { "tags": ["trial", "vip"] } });
+
+        let fan_out = topics(&plan, &event(&payload), RoutingMode::FanOut).unwrap();
+        assert_eq!(
+            fan_out,
+            vec!["billing.events", "analytics.events", "vip.events"]
+        );
+        // First-match routing still picks a single topic
+        assert_eq!(
+            topics(&plan, &event(&payload), RoutingMode::FirstMatch).unwrap(),
+            vec!["billing.events"]
+        );
+
+        assert!(topics(
+            &plan,
+            &event(&json!({ "event": "other" })),
+            RoutingMode::FanOut
+        )
+        .unwrap()
+        .is_empty());
+    }
+
+    #[test]
+    fn test_route_mapping_matchers() {
+        let routes: Vec<Route> = serde_json::from_value(json!([
+            {
+                "path": "$.event",
+                "mapping": [
+                    { "values": ["recording.completed", "recording.deleted"], "topic": "recordings" },
+                    { "glob": "meeting.*", "topic": "meetings" },
+                    { "prefix": "webinar.", "topic": "webinars" },
+                    { "regex": "^user\\.(created|deleted)$", "topic": "users" }
+                ]
+            },
+            {
+                "path": "$.payload.amount",
+                "mapping": [
+                    { "range": { "gte": 10000 }, "topic": "large-payments" },
+                    { "range": { "gt": 0, "lt": 10000 }, "topic": "payments" }
+                ]
+            }
+        ]))
+        .unwrap();
+        let topic = |payload: Value| route_to_topic(&payload, &routes).unwrap();
+
+        assert_eq!(
+            topic(json!({ "event": "recording.deleted" })),
+            Some("recordings".to_string())
+        );
+        assert_eq!(
+            topic(json!({ "event": "meeting.started" })),
+            Some("meetings".to_string())
+        );
+        assert_eq!(topic(json!({ "event": "meeting" })), None);
+        assert_eq!(
+            topic(json!({ "event": "webinar.ended" })),
+            Some("webinars".to_string())
+        );
+        assert_eq!(
+            topic(json!({ "event": "user.created" })),
+            Some("users".to_string())
+        );
+        assert_eq!(topic(json!({ "event": "user.updated" })), None);
+        assert_eq!(
+            topic(json!({ "payload": { "amount": 25000.5 } })),
+            Some("large-payments".to_string())
+        );
+        assert_eq!(
+            topic(json!({ "payload": { "amount": 50 } })),
+            Some("payments".to_string())
+        );
+        assert_eq!(topic(json!({ "payload": { "amount": 0 } })), None);
+        // Ranges only match numbers
+        assert_eq!(topic(json!({ "payload": { "amount": "50" } })), None);
+
+        let invalid = |mapping: Value| {
+            let route = json!([{ "path": "$.event", "mapping": [mapping] }]);
+            validate_rules(&[], &serde_json::from_value::<Vec<Route>>(route).unwrap())
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(
+            invalid(json!({ "value": "a", "prefix": "b", "topic": "t" }))
+                .contains("exactly one of value")
+        );
+        assert!(invalid(json!({ "topic": "t" })).contains("exactly one of value"));
+        assert!(invalid(json!({ "regex": "(", "topic": "t" })).starts_with("Invalid regex"));
+        assert!(invalid(json!({ "range": {}, "topic": "t" })).contains("at least one bound"));
+        assert!(
+            invalid(json!({ "range": { "gt": 1, "gte": 2 }, "topic": "t" }))
+                .contains("both gt and gte")
+        );
+    }
+
+    #[test]
+    fn test_route_conditions_and_actions() {
+        let routes: Vec<Route> = serde_json::from_value(json!([
+            {
+                "when": [{ "path": "$.payload.test", "operator": "equals", "value": true }],
+                "action": "drop"
+            },
+            {
+                "when": [{ "expression": "body.payload.amount > 1000" }],
+                "path": "$.event",
+                "mapping": [{ "glob": "payment.*", "topic": "large-payments" }]
+            },
+            {
+                "path": "$.event",
+                "mapping": [{ "glob": "payment.*", "topic": "payments" }]
+            },
+            {
+                "when": [{ "path": "$.event", "operator": "matches", "value": "^refund\\." }],
+                "action": "dead_letter"
+            }
+        ]))
+        .unwrap();
+        let plan = Plan::compile(&[], &routes).unwrap();
+        assert!(plan.uses_dead_letter());
+        let evaluate =
+            |payload: Value, routing_mode| outcome(&plan, &event(&payload), routing_mode).unwrap();
+
+        let payment =
+            |amount: i64| json!({ "event": "payment.created", "payload": { "amount": amount } });
+        let publish = |topics: &[&'static str], routes: Vec<usize>| Outcome::Publish {
+            topics: topics.iter().map(|topic| Cow::Borrowed(*topic)).collect(),
+            routes,
+        };
+        assert_eq!(
+            evaluate(payment(5000), RoutingMode::FirstMatch),
+            publish(&["large-payments"], vec![2])
+        );
+        assert_eq!(
+            evaluate(payment(50), RoutingMode::FirstMatch),
+            publish(&["payments"], vec![3])
+        );
+        assert_eq!(
+            evaluate(payment(5000), RoutingMode::FanOut),
+            publish(&["large-payments", "payments"], vec![2, 3])
+        );
+        assert_eq!(
+            evaluate(
+                json!({ "event": "payment.created", "payload": { "test": true } }),
+                RoutingMode::FirstMatch
+            ),
+            Outcome::Dropped
+        );
+        assert_eq!(
+            evaluate(
+                json!({ "event": "refund.created" }),
+                RoutingMode::FirstMatch
+            ),
+            Outcome::DeadLetter("Matched route 4 with action dead_letter".to_string())
+        );
+        assert_eq!(
+            evaluate(json!({ "event": "other" }), RoutingMode::FirstMatch),
+            publish(&[], vec![])
+        );
+
+        // Filters run before any route
+        let plan = Plan::compile(
+            &[rule(
+                "$.event",
+                FilterOperator::Exists,
+                FilterValue::Bool(true),
+            )],
+            &routes,
+        )
+        .unwrap();
+        assert_eq!(
+            outcome(&plan, &event(&json!({})), RoutingMode::FirstMatch).unwrap(),
+            Outcome::Filtered
+        );
+
+        let invalid: Vec<Route> = serde_json::from_value(json!([{
+            "action": "drop",
+            "path": "$.event",
+            "mapping": [{ "value": "a", "topic": "t" }]
+        }]))
+        .unwrap();
+        let err = validate_rules(&[], &invalid).unwrap_err().to_string();
+        assert_eq!(err, "Route with action drop must not select a topic");
+    }
+
+    #[test]
+    fn test_message_key() {
+        let handler_id = Uuid::new_v4();
+        let spec = |key: Value| -> WebhookHandlerSpec {
+            let mut spec = json!({ "topic": "events" });
+            spec.as_object_mut()
+                .unwrap()
+                .extend(key.as_object().unwrap().clone());
+            serde_json::from_value(spec).unwrap()
+        };
+        let plan = |key: Value| Plan::default().with_message_key(&spec(key));
+
+        let body = json!({ "payload": { "object": { "id": "obj-1", "seq": 7 } } });
+        let headers = json!({ "x-request-id": "req-1" });
+        let webhook = WebhookEvent {
+            headers: &headers,
+            ..event(&body)
+        };
+        let key = |key: Value| plan(key).unwrap().message_key(&webhook, handler_id);
+
+        assert_eq!(key(json!({})).unwrap(), Some(handler_id.to_string()));
+        assert_eq!(key(json!({ "nullKey": true })).unwrap(), None);
+        assert_eq!(
+            key(json!({ "keyPath": "$.payload.object.id" })).unwrap(),
+            Some("obj-1".to_string())
+        );
+        assert_eq!(
+            key(json!({ "keyPath": "$request.headers['X-Request-Id']" })).unwrap(),
+            Some("req-1".to_string())
+        );
+        assert_eq!(
+            key(json!({ "keyTemplate": "{$.payload.object.id}-{$.payload.object.seq}" })).unwrap(),
+            Some("obj-1-7".to_string())
+        );
+
+        // Fallbacks when the path selects nothing, or an object
+        assert_eq!(
+            key(json!({ "keyPath": "$.payload.missing" })).unwrap(),
+            Some(handler_id.to_string())
+        );
+        assert_eq!(
+            key(json!({ "keyPath": "$.payload.object", "keyFallback": "null" })).unwrap(),
+            None
+        );
+        let err = key(json!({ "keyTemplate": "{$.payload.missing}", "keyFallback": "reject" }))
+            .unwrap_err();
+        assert_eq!(err.policy, MissingPolicy::Reject);
+        assert_eq!(
+            err.to_string(),
+            "Message key path matched no string, number or bool: $.payload.missing"
+        );
+
+        let invalid = |key: Value| plan(key).unwrap_err().to_string();
+        assert_eq!(
+            invalid(json!({ "keyPath": "$.a", "keyTemplate": "{$.b}" })),
+            "keyPath and keyTemplate are mutually exclusive"
+        );
+        assert!(
+            invalid(json!({ "keyPath": "$.a", "nullKey": true })).starts_with("nullKey must not")
+        );
+        assert_eq!(
+            invalid(json!({ "keyFallback": "null" })),
+            "keyFallback requires keyPath or keyTemplate"
+        );
+        assert!(invalid(json!({ "keyPath": "$[" })).starts_with("Invalid JSONPath"));
+        assert!(invalid(json!({ "keyTemplate": "{$.a" })).starts_with("Unclosed placeholder"));
+    }
+}
@@ -1,13 +1,27 @@
 use axum::{
-    extract::Extension,
-    http::{HeaderMap, StatusCode},
+    extract::{Extension, Path},
+    http::{HeaderMap, Method, StatusCode, Uri},
     Json,
 };
-use kube::{api::PostParams, Api, Client};
-use serde::{Deserialize, Serialize};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use kube::{
+    api::{DeleteParams, ListParams, PostParams},
+    Api, Client,
+};
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
-use crate::crd::{WebhookHandler, WebhookHandlerSpec, WebhookHandlerStatus, Filter, Route};
+use crate::controller::parse_uuid_from_name;
+use crate::crd::{
+    ChallengeMode, CloudEventsConfig, Filter, KeyFallback, MessageFormat, PartialFailurePolicy,
+    PayloadSchemaConfig, Route, RoutingMode, SecretKeyRef, SerializerConfig, SignatureScheme,
+    SigningKey, TransformConfig, WebhookHandler, WebhookHandlerSpec, WebhookHandlerStatus,
+};
+use crate::secrets::{
+    remove_signing_keys, signing_secret_name, store_signing_key, SIGNATURE_KEY_FIELD,
+};
 use crate::signature::verify_signature;
 use crate::state::AppState;
 
@@ -16,12 +30,134 @@ pub struct ConfigRequest {
     topic: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     signature_key: Option<String>,
+    #[serde(default)]
+    signature_scheme: SignatureScheme,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    challenge_mode: Option<ChallengeMode>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    verify_token: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     filters: Option<Vec<Filter>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     routes: Option<Vec<Route>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_letter_topic: Option<String>,
+    #[serde(default)]
+    routing_mode: RoutingMode,
+    #[serde(default)]
+    include_default_topic: bool,
+    #[serde(default)]
+    partial_failure: PartialFailurePolicy,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    key_template: Option<String>,
+    #[serde(default)]
+    key_fallback: KeyFallback,
+    #[serde(default)]
+    null_key: bool,
+    #[serde(default)]
+    message_format: MessageFormat,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cloud_events: Option<CloudEventsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    serializer: Option<SerializerConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_schema: Option<PayloadSchemaConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transform: Option<TransformConfig>,
+}
+
+/// Body of `PATCH /config/:id`, a JSON Merge Patch (RFC 7386): only the
+/// fields present are changed, and `null` removes a field or resets it to
+/// its default
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PatchRequest {
+    topic: PatchField<String>,
+    signature_key: PatchField<String>,
+    signature_scheme: PatchField<SignatureScheme>,
+    challenge_mode: PatchField<ChallengeMode>,
+    verify_token: PatchField<String>,
+    filters: PatchField<Vec<Filter>>,
+    routes: PatchField<Vec<Route>>,
+    dead_letter_topic: PatchField<String>,
+    routing_mode: PatchField<RoutingMode>,
+    include_default_topic: PatchField<bool>,
+    partial_failure: PatchField<PartialFailurePolicy>,
+    key_path: PatchField<String>,
+    key_template: PatchField<String>,
+    key_fallback: PatchField<KeyFallback>,
+    null_key: PatchField<bool>,
+    message_format: PatchField<MessageFormat>,
+    cloud_events: PatchField<CloudEventsConfig>,
+    serializer: PatchField<SerializerConfig>,
+    payload_schema: PatchField<PayloadSchemaConfig>,
+    transform: PatchField<TransformConfig>,
+}
+
+/// A field of a merge patch: left out, `null`, or a new value
+#[derive(Debug, Default)]
+enum PatchField<T> {
+    #[default]
+    Absent,
+    Null,
+    Value(T),
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for PatchField<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::deserialize(deserializer)?.map_or(PatchField::Null, PatchField::Value))
+    }
+}
+
+impl<T> PatchField<T> {
+    fn value(&self) -> Option<&T> {
+        match self {
+            PatchField::Value(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Applies the field to an optional spec field; `null` removes it
+    fn merge(self, target: &mut Option<T>) {
+        match self {
+            PatchField::Absent => {}
+            PatchField::Null => *target = None,
+            PatchField::Value(value) => *target = Some(value),
+        }
+    }
+}
+
+impl<T: Default> PatchField<T> {
+    /// Applies the field to a spec field with a default; `null` resets it
+    fn merge_or_default(self, target: &mut T) {
+        match self {
+            PatchField::Absent => {}
+            PatchField::Null => *target = T::default(),
+            PatchField::Value(value) => *target = value,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct HandlerResponse {
+    handler_id: Uuid,
+    webhook_url: String,
+    /// Stored spec with inline secrets redacted
+    spec: WebhookHandlerSpec,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<WebhookHandlerStatus>,
+}
+
+#[derive(Serialize)]
+pub struct HandlerListResponse {
+    handlers: Vec<HandlerResponse>,
 }
 
+/// Placeholder returned in place of inline secrets
+const REDACTED: &str = "<redacted>";
+
 #[derive(Serialize)]
 pub struct ConfigResponse {
     handler_id: Uuid,
@@ -35,10 +171,624 @@ pub struct ErrorResponse {
 
 pub async fn create_handler(
     Extension(state): Extension<AppState>,
+    method: Method,
+    uri: Uri,
     headers: HeaderMap,
     body: String,
 ) -> Result<Json<ConfigResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Extract and verify signature
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, &body)?;
+
+    // Parse request body
+    let req: ConfigRequest = parse_body(&body)?;
+
+    // Generate UUID for handler
+    let handler_id = Uuid::new_v4();
+    let handler_name = format!("handler-{}", handler_id);
+
+    tracing::info!(
+        "Creating webhook handler: {} for topic: {}",
+        handler_id,
+        req.topic
+    );
+
+    let client = kube_client().await?;
+
+    let api: Api<WebhookHandler> = Api::namespaced(client.clone(), &state.namespace);
+
+    // Create WebhookHandler resource
+    let webhook_url = format!("{}/handler/{}", state.external_url, handler_id);
+
+    let handler = WebhookHandler {
+        metadata: kube::api::ObjectMeta {
+            name: Some(handler_name.clone()),
+            namespace: Some(state.namespace.clone()),
+            ..Default::default()
+        },
+        spec: spec_from_request(&req, owned_key_ref(&handler_name, SIGNATURE_KEY_FIELD)),
+        // Status is written by the controller once the handler is reconciled
+        status: None,
+    };
+
+    let created = api
+        .create(&PostParams::default(), &handler)
+        .await
+        .map_err(|e| {
+            tracing::error!("Failed to create WebhookHandler CRD: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create handler: {}", e),
+                }),
+            )
+        })?;
+
+    // The Secret is created after the handler so it can be owned by it;
+    // the controller picks it up through its Secret watch
+    if let Some(signature_key) = &req.signature_key {
+        if let Err(e) =
+            store_signing_key(client, &created, SIGNATURE_KEY_FIELD, signature_key).await
+        {
+            tracing::error!(
+                "Failed to create signing Secret for handler {}: {}",
+                handler_id,
+                e
+            );
+            if let Err(e) = api.delete(&handler_name, &DeleteParams::default()).await {
+                tracing::error!("Failed to clean up handler {}: {}", handler_id, e);
+            }
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create handler: {}", e),
+                }),
+            ));
+        }
+    }
+
+    tracing::info!("Successfully created handler: {}", handler_id);
+
+    Ok(Json(ConfigResponse {
+        handler_id,
+        webhook_url,
+    }))
+}
+
+/// `GET /config` - lists all handlers with their secrets redacted
+pub async fn list_handlers(
+    Extension(state): Extension<AppState>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Json<HandlerListResponse>, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, "")?;
+
+    let client = kube_client().await?;
+    let api: Api<WebhookHandler> = Api::namespaced(client, &state.namespace);
+    let list = api
+        .list(&ListParams::default())
+        .await
+        .map_err(|e| internal_error("Failed to list handlers", e))?;
+
+    let handlers = list
+        .items
+        .iter()
+        .filter_map(|handler| handler_response(&state, handler))
+        .collect();
+
+    Ok(Json(HandlerListResponse { handlers }))
+}
+
+/// `GET /config/:id` - returns a handler with its secrets redacted
+pub async fn get_handler(
+    Extension(state): Extension<AppState>,
+    Path(handler_id): Path<Uuid>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<Json<HandlerResponse>, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, "")?;
+
+    let client = kube_client().await?;
+    let api: Api<WebhookHandler> = Api::namespaced(client, &state.namespace);
+    let handler = fetch_handler(&api, handler_id).await?;
+
+    handler_response(&state, &handler)
+        .map(Json)
+        .ok_or_else(handler_not_found)
+}
+
+/// `PUT /config/:id` - replaces a handler's configuration.
+/// Fields left out of the request are removed, including the signing key.
+pub async fn replace_handler(
+    Extension(state): Extension<AppState>,
+    Path(handler_id): Path<Uuid>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<HandlerResponse>, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, &body)?;
+    let req: ConfigRequest = parse_body(&body)?;
+
+    update_handler_spec(
+        &state,
+        handler_id,
+        req.signature_key.as_deref(),
+        |_, key_ref| spec_from_request(&req, key_ref),
+    )
+    .await
+}
+
+/// `PATCH /config/:id` - updates the fields present in the request.
+/// A new `signature_key` replaces all existing keys; use
+/// `POST /config/:id/rotate` to keep the previous keys valid for a while.
+pub async fn patch_handler(
+    Extension(state): Extension<AppState>,
+    Path(handler_id): Path<Uuid>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<HandlerResponse>, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, &body)?;
+    let req: PatchRequest = parse_body(&body)?;
+    check_patch(&req)?;
+
+    let signature_key = req.signature_key.value().cloned();
+    update_handler_spec(
+        &state,
+        handler_id,
+        signature_key.as_deref(),
+        |mut spec, key_ref| {
+            apply_patch(&mut spec, req, key_ref);
+            spec
+        },
+    )
+    .await
+}
+
+/// `DELETE /config/:id` - deletes a handler; its owned signing Secret is
+/// garbage collected by Kubernetes
+pub async fn delete_handler(
+    Extension(state): Extension<AppState>,
+    Path(handler_id): Path<Uuid>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, "")?;
+
+    let client = kube_client().await?;
+    let api: Api<WebhookHandler> = Api::namespaced(client, &state.namespace);
+    let handler_name = format!("handler-{}", handler_id);
+
+    match api.delete(&handler_name, &DeleteParams::default()).await {
+        Ok(_) => {
+            tracing::info!("Deleted handler: {}", handler_id);
+            Ok(StatusCode::NO_CONTENT)
+        }
+        Err(kube::Error::Api(e)) if e.code == 404 => Err(handler_not_found()),
+        Err(e) => Err(internal_error("Failed to delete handler", e)),
+    }
+}
+
+/// Loads a handler, applies `update` to its spec and writes it back.
+/// `update` is given the Secret reference a submitted `signature_key` is
+/// stored under.
+async fn update_handler_spec(
+    state: &AppState,
+    handler_id: Uuid,
+    signature_key: Option<&str>,
+    update: impl FnOnce(WebhookHandlerSpec, SecretKeyRef) -> WebhookHandlerSpec,
+) -> Result<Json<HandlerResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let client = kube_client().await?;
+    let api: Api<WebhookHandler> = Api::namespaced(client.clone(), &state.namespace);
+    let mut handler = fetch_handler(&api, handler_id).await?;
+    let handler_name = format!("handler-{}", handler_id);
+
+    // A submitted key goes into a new field before the spec refers to it,
+    // so the handler verifies with either the old key or the new one, never
+    // a half-applied mix
+    let key_ref = owned_key_ref(&handler_name, &new_key_field(Utc::now()));
+    if let Some(signature_key) = signature_key {
+        store_signing_key(client.clone(), &handler, &key_ref.key, signature_key)
+            .await
+            .map_err(|e| internal_error("Failed to store signing key", e))?;
+    }
+
+    let previous_fields = owned_key_fields(&handler.spec, &handler_name);
+    handler.spec = update(handler.spec, key_ref.clone());
+    let updated = match write_handler(&api, &handler).await {
+        Ok(updated) => updated,
+        Err(e) => {
+            if signature_key.is_some() {
+                discard_signing_keys(client, &state.namespace, &handler_name, &[key_ref.key]).await;
+            }
+            return Err(e);
+        }
+    };
+
+    // Keys the new spec no longer refers to
+    let current_fields = owned_key_fields(&updated.spec, &handler_name);
+    let unused: Vec<String> = previous_fields
+        .into_iter()
+        .filter(|field| !current_fields.contains(field))
+        .collect();
+    discard_signing_keys(client, &state.namespace, &handler_name, &unused).await;
+
+    tracing::info!("Updated handler: {}", handler_id);
+
+    handler_response(state, &updated)
+        .map(Json)
+        .ok_or_else(handler_not_found)
+}
+
+/// Writes back a fetched handler. Replace carries its resourceVersion, so a
+/// concurrent update is reported as a conflict instead of being overwritten.
+async fn write_handler(
+    api: &Api<WebhookHandler>,
+    handler: &WebhookHandler,
+) -> Result<WebhookHandler, (StatusCode, Json<ErrorResponse>)> {
+    let handler_name = handler.metadata.name.as_deref().unwrap_or_default();
+    api.replace(handler_name, &PostParams::default(), handler)
+        .await
+        .map_err(|e| match e {
+            kube::Error::Api(e) if e.code == 409 => (
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "Handler was modified concurrently, retry the request".to_string(),
+                }),
+            ),
+            e => internal_error("Failed to update handler", e),
+        })
+}
+
+/// Removes keys no spec references any more from the handler's owned
+/// Secret. A failure only leaves unused keys behind, so it is logged.
+async fn discard_signing_keys(
+    client: Client,
+    namespace: &str,
+    handler_name: &str,
+    fields: &[String],
+) {
+    let secret_name = signing_secret_name(handler_name);
+    if let Err(e) = remove_signing_keys(client, namespace, &secret_name, fields).await {
+        tracing::warn!(
+            "Failed to remove unused keys from Secret {}: {}",
+            secret_name,
+            e
+        );
+    }
+}
+
+async fn fetch_handler(
+    api: &Api<WebhookHandler>,
+    handler_id: Uuid,
+) -> Result<WebhookHandler, (StatusCode, Json<ErrorResponse>)> {
+    api.get_opt(&format!("handler-{}", handler_id))
+        .await
+        .map_err(|e| internal_error("Failed to load handler", e))?
+        .ok_or_else(handler_not_found)
+}
+
+fn handler_response(state: &AppState, handler: &WebhookHandler) -> Option<HandlerResponse> {
+    let handler_id = parse_uuid_from_name(&handler.metadata.name)?;
+    Some(HandlerResponse {
+        handler_id,
+        webhook_url: format!("{}/handler/{}", state.external_url, handler_id),
+        spec: redacted_spec(&handler.spec),
+        status: handler.status.clone(),
+    })
+}
+
+/// Builds the stored spec for a create or replace request.
+/// Submitted keys are stored in an owned Secret under `key_ref`, never in the CR.
+fn spec_from_request(req: &ConfigRequest, key_ref: SecretKeyRef) -> WebhookHandlerSpec {
+    WebhookHandlerSpec {
+        topic: req.topic.clone(),
+        signature_key: None,
+        signature_key_secret_ref: req.signature_key.as_ref().map(|_| key_ref),
+        signature_keys: None,
+        signature_scheme: req.signature_scheme,
+        challenge_mode: req.challenge_mode,
+        verify_token: req.verify_token.clone(),
+        filters: req.filters.clone(),
+        routes: req.routes.clone(),
+        dead_letter_topic: req.dead_letter_topic.clone(),
+        routing_mode: req.routing_mode,
+        include_default_topic: req.include_default_topic,
+        partial_failure: req.partial_failure,
+        key_path: req.key_path.clone(),
+        key_template: req.key_template.clone(),
+        key_fallback: req.key_fallback,
+        null_key: req.null_key,
+        message_format: req.message_format,
+        cloud_events: req.cloud_events.clone(),
+        serializer: req.serializer.clone(),
+        payload_schema: req.payload_schema.clone(),
+        transform: req.transform.clone(),
+    }
+}
+
+/// Applies a patch that passed `check_patch` to a stored spec; a new
+/// `signature_key` is referred to by `key_ref`
+fn apply_patch(spec: &mut WebhookHandlerSpec, req: PatchRequest, key_ref: SecretKeyRef) {
+    if let PatchField::Value(topic) = req.topic {
+        spec.topic = topic;
+    }
+    match req.signature_key {
+        PatchField::Absent => {}
+        // Removing the key turns signature verification off
+        PatchField::Null => {
+            spec.signature_key = None;
+            spec.signature_key_secret_ref = None;
+            spec.signature_keys = None;
+        }
+        PatchField::Value(_) => {
+            spec.signature_key = None;
+            spec.signature_key_secret_ref = Some(key_ref);
+            spec.signature_keys = None;
+        }
+    }
+    req.signature_scheme
+        .merge_or_default(&mut spec.signature_scheme);
+    req.challenge_mode.merge(&mut spec.challenge_mode);
+    req.verify_token.merge(&mut spec.verify_token);
+    req.filters.merge(&mut spec.filters);
+    req.routes.merge(&mut spec.routes);
+    req.dead_letter_topic.merge(&mut spec.dead_letter_topic);
+    req.routing_mode.merge_or_default(&mut spec.routing_mode);
+    req.include_default_topic
+        .merge_or_default(&mut spec.include_default_topic);
+    req.partial_failure
+        .merge_or_default(&mut spec.partial_failure);
+    req.key_path.merge(&mut spec.key_path);
+    req.key_template.merge(&mut spec.key_template);
+    req.key_fallback.merge_or_default(&mut spec.key_fallback);
+    req.null_key.merge_or_default(&mut spec.null_key);
+    req.message_format
+        .merge_or_default(&mut spec.message_format);
+    req.cloud_events.merge(&mut spec.cloud_events);
+    req.serializer.merge(&mut spec.serializer);
+    req.payload_schema.merge(&mut spec.payload_schema);
+    req.transform.merge(&mut spec.transform);
+}
+
+/// Rejects patches that would remove a required field
+fn check_patch(req: &PatchRequest) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if matches!(req.topic, PatchField::Null) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "topic is required and cannot be removed".to_string(),
+            }),
+        ));
+    }
+    Ok(())
+}
+
+fn owned_key_ref(handler_name: &str, field: &str) -> SecretKeyRef {
+    SecretKeyRef {
+        name: signing_secret_name(handler_name),
+        key: field.to_string(),
+    }
+}
+
+/// A Secret field no earlier key of the handler can have used
+fn new_key_field(now: DateTime<Utc>) -> String {
+    format!("key-{}", now.timestamp_millis())
+}
+
+/// Fields of the handler's owned Secret that its spec refers to
+fn owned_key_fields(spec: &WebhookHandlerSpec, handler_name: &str) -> Vec<String> {
+    let secret_name = signing_secret_name(handler_name);
+    spec.signing_keys()
+        .into_iter()
+        .filter_map(|key| key.secret_ref)
+        .filter(|secret_ref| secret_ref.name == secret_name)
+        .map(|secret_ref| secret_ref.key)
+        .collect()
+}
+
+/// Replaces inline secrets with a placeholder; Secret references are kept
+fn redacted_spec(spec: &WebhookHandlerSpec) -> WebhookHandlerSpec {
+    let redact = |value: &Option<String>| value.as_ref().map(|_| REDACTED.to_string());
+
+    let mut spec = spec.clone();
+    spec.signature_key = redact(&spec.signature_key);
+    spec.verify_token = redact(&spec.verify_token);
+    if let Some(keys) = spec.signature_keys.as_mut() {
+        for key in keys {
+            key.key = redact(&key.key);
+        }
+    }
+    spec
+}
+
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, (StatusCode, Json<ErrorResponse>)> {
+    serde_json::from_str(body).map_err(|e| {
+        tracing::error!("Failed to parse request body: {}", e);
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid request body: {}", e),
+            }),
+        )
+    })
+}
+
+fn handler_not_found() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "Handler not found".to_string(),
+        }),
+    )
+}
+
+/// Request body for `POST /config/:id/rotate`
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RotateRequest {
+    /// How long the previous keys stay valid after the rotation
+    #[serde(default = "default_grace_period_seconds")]
+    grace_period_seconds: u64,
+}
+
+const MAX_GRACE_PERIOD_SECONDS: u64 = 90 * 24 * 60 * 60;
+
+fn default_grace_period_seconds() -> u64 {
+    24 * 60 * 60
+}
+
+#[derive(Serialize)]
+pub struct RotateResponse {
+    handler_id: Uuid,
+    signature_key: String,
+    previous_keys_expire_at: String,
+}
+
+/// Generates a new signing key and schedules expiry of the previous ones,
+/// so senders can switch to the new key without dropping traffic
+pub async fn rotate_key(
+    Extension(state): Extension<AppState>,
+    Path(handler_id): Path<Uuid>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Json<RotateResponse>, (StatusCode, Json<ErrorResponse>)> {
+    verify_api_request(&state.api_signing_key, &method, &uri, &headers, &body)?;
+
+    let req: RotateRequest = if body.trim().is_empty() {
+        RotateRequest {
+            grace_period_seconds: default_grace_period_seconds(),
+        }
+    } else {
+        parse_body(&body)?
+    };
+
+    let handler_name = format!("handler-{}", handler_id);
+    let client = kube_client().await?;
+    let api: Api<WebhookHandler> = Api::namespaced(client.clone(), &state.namespace);
+
+    let mut handler = fetch_handler(&api, handler_id).await?;
+
+    if req.grace_period_seconds > MAX_GRACE_PERIOD_SECONDS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!(
+                    "grace_period_seconds must not exceed {}",
+                    MAX_GRACE_PERIOD_SECONDS
+                ),
+            }),
+        ));
+    }
+
+    let now = chrono::Utc::now();
+    let expire_at = now + chrono::Duration::seconds(req.grace_period_seconds as i64);
+
+    let signature_key = generate_signing_key();
+    let field = new_key_field(now);
+    let secret_name = signing_secret_name(&handler_name);
+
+    // The key is stored before the spec refers to it, so the controller
+    // never loads a reference to a missing key
+    store_signing_key(client.clone(), &handler, &field, &signature_key)
+        .await
+        .map_err(|e| internal_error("Failed to store signing key", e))?;
+
+    let expired = rotate_signing_keys(
+        &mut handler.spec,
+        SecretKeyRef {
+            name: secret_name.clone(),
+            key: field.clone(),
+        },
+        now,
+        expire_at,
+    );
+    if let Err(e) = write_handler(&api, &handler).await {
+        discard_signing_keys(client, &state.namespace, &handler_name, &[field]).await;
+        return Err(e);
+    }
+
+    // Expired keys in the owned Secret are no longer referenced
+    let expired_fields: Vec<String> = expired
+        .into_iter()
+        .filter_map(|key| key.secret_ref)
+        .filter(|secret_ref| secret_ref.name == secret_name)
+        .map(|secret_ref| secret_ref.key)
+        .collect();
+    discard_signing_keys(client, &state.namespace, &handler_name, &expired_fields).await;
+
+    tracing::info!(
+        "Rotated signing key for handler {}; previous keys expire at {}",
+        handler_id,
+        expire_at
+    );
+
+    Ok(Json(RotateResponse {
+        handler_id,
+        signature_key,
+        previous_keys_expire_at: expire_at.to_rfc3339(),
+    }))
+}
+
+/// Puts `new_key` first and caps every other key at `expire_at`.
+/// Keys that had already expired are dropped and returned.
+fn rotate_signing_keys(
+    spec: &mut WebhookHandlerSpec,
+    new_key: SecretKeyRef,
+    now: DateTime<Utc>,
+    expire_at: DateTime<Utc>,
+) -> Vec<SigningKey> {
+    let (expired, current): (Vec<_>, Vec<_>) = spec
+        .signing_keys()
+        .into_iter()
+        .partition(|key| key.not_after.as_ref().is_some_and(|t| t.0 <= now));
+
+    let mut keys = vec![SigningKey {
+        key: None,
+        secret_ref: Some(new_key),
+        not_after: None,
+    }];
+    keys.extend(current.into_iter().map(|mut key| {
+        let not_after = match key.not_after {
+            Some(t) if t.0 < expire_at => t.0,
+            _ => expire_at,
+        };
+        key.not_after = Some(Time(not_after));
+        key
+    }));
+
+    spec.signature_key = None;
+    spec.signature_key_secret_ref = None;
+    spec.signature_keys = Some(keys);
+    expired
+}
+
+fn generate_signing_key() -> String {
+    // whsec_ + base64 is also the format expected by Standard Webhooks
+    format!("whsec_{}", BASE64.encode(rand::random::<[u8; 32]>()))
+}
+
+/// Header selecting the format of config API signatures
+const SIGNATURE_VERSION_HEADER: &str = "x-signature-version";
+
+/// Verifies the API_SIGNING_KEY HMAC on a config API request. With
+/// `X-Signature-Version: 2` the signature covers
+/// `"{X-Timestamp}.{METHOD}.{path}.{body}"`, so a signed request cannot be
+/// replayed against another endpoint or handler. Without the header, the
+/// original `"{X-Timestamp}.{body}"` form is still accepted for
+/// `POST /config`, which existing clients sign that way.
+fn verify_api_request(
+    api_signing_key: &str,
+    method: &Method,
+    uri: &Uri,
+    headers: &HeaderMap,
+    body: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
     let signature = headers
         .get("x-signature")
         .and_then(|v| v.to_str().ok())
@@ -63,8 +813,29 @@ pub async fn create_handler(
             )
         })?;
 
+    let message = match headers.get(SIGNATURE_VERSION_HEADER).map(|v| v.to_str()) {
+        Some(Ok("2")) => format!("{}.{}.{}", method, uri.path(), body),
+        None if method == Method::POST && uri.path() == "/config" => body.to_string(),
+        None => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse {
+                    error: "X-Signature-Version: 2 is required for this endpoint".to_string(),
+                }),
+            ))
+        }
+        Some(_) => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse {
+                    error: "Unsupported X-Signature-Version".to_string(),
+                }),
+            ))
+        }
+    };
+
     // Verify signature
-    let is_valid = verify_signature(&state.api_signing_key, timestamp, &body, signature)
+    let is_valid = verify_signature(api_signing_key, timestamp, message.as_bytes(), signature)
         .map_err(|e| {
             tracing::error!("Signature verification error: {}", e);
             (
@@ -85,25 +856,11 @@ pub async fn create_handler(
         ));
     }
 
-    // Parse request body
-    let req: ConfigRequest = serde_json::from_str(&body).map_err(|e| {
-        tracing::error!("Failed to parse request body: {}", e);
-        (
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: format!("Invalid request body: {}", e),
-            }),
-        )
-    })?;
-
-    // Generate UUID for handler
-    let handler_id = Uuid::new_v4();
-    let handler_name = format!("handler-{}", handler_id);
-
-    tracing::info!("Creating webhook handler: {} for topic: {}", handler_id, req.topic);
+    Ok(())
+}
 
-    // Create Kubernetes client
-    let client = Client::try_default().await.map_err(|e| {
+async fn kube_client() -> Result<Client, (StatusCode, Json<ErrorResponse>)> {
+    Client::try_default().await.map_err(|e| {
         tracing::error!("Failed to create Kubernetes client: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -111,47 +868,249 @@ pub async fn create_handler(
                 error: "Internal server error".to_string(),
             }),
         )
-    })?;
-
-    let api: Api<WebhookHandler> = Api::namespaced(client, &state.namespace);
+    })
+}
 
-    // Create WebhookHandler resource
-    let webhook_url = format!("{}/handler/{}", state.external_url, handler_id);
-    
-    let handler = WebhookHandler {
-        metadata: kube::api::ObjectMeta {
-            name: Some(handler_name.clone()),
-            namespace: Some(state.namespace.clone()),
-            ..Default::default()
-        },
-        spec: WebhookHandlerSpec {
-            topic: req.topic.clone(),
-            signature_key: req.signature_key,
-            filters: req.filters,
-            routes: req.routes,
-        },
-        status: Some(WebhookHandlerStatus {
-            handler_url: Some(webhook_url.clone()),
-            ready: true,
+fn internal_error(context: &str, e: impl std::fmt::Display) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::error!("{}: {}", context, e);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("{}: {}", context, e),
         }),
-    };
+    )
+}
 
-    api.create(&PostParams::default(), &handler)
-        .await
-        .map_err(|e| {
-            tracing::error!("Failed to create WebhookHandler CRD: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to create handler: {}", e),
-                }),
-            )
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hmac::{Hmac, Mac};
+    use serde_json::json;
+    use sha2::Sha256;
 
-    tracing::info!("Successfully created handler: {}", handler_id);
+    fn secret_ref(key: &str) -> SecretKeyRef {
+        SecretKeyRef {
+            name: "handler-x-signing".to_string(),
+            key: key.to_string(),
+        }
+    }
 
-    Ok(Json(ConfigResponse {
-        handler_id,
-        webhook_url,
-    }))
-}
\ No newline at end of file
+    #[test]
+    fn test_rotate_signing_keys() {
+        let now = Utc::now();
+        let hour = chrono::Duration::hours(1);
+        let mut spec: WebhookHandlerSpec = serde_json::from_value(json!({
+            "topic": "events",
+            "signatureKeySecretRef": { "name": "handler-x-signing", "key": "signature-key" },
+            "signatureKeys": [
+                { "secretRef": { "name": "handler-x-signing", "key": "key-1" },
+                  "notAfter": (now - hour).to_rfc3339() },
+                { "secretRef": { "name": "handler-x-signing", "key": "key-2" },
+                  "notAfter": (now + hour).to_rfc3339() }
+            ]
+        }))
+        .unwrap();
+
+        let expired = rotate_signing_keys(&mut spec, secret_ref("key-3"), now, now + hour * 24);
+
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].secret_ref, Some(secret_ref("key-1")));
+        assert!(spec.signature_key_secret_ref.is_none());
+
+        let keys = spec.signature_keys.unwrap();
+        let refs: Vec<_> = keys
+            .iter()
+            .map(|k| k.secret_ref.clone().unwrap().key)
+            .collect();
+        assert_eq!(refs, ["key-3", "key-2", "signature-key"]);
+        assert!(keys[0].not_after.is_none());
+        // An earlier expiry is kept, keys without one get the grace period
+        assert_eq!(
+            keys[1].not_after.as_ref().unwrap().0.timestamp(),
+            (now + hour).timestamp()
+        );
+        assert_eq!(keys[2].not_after.as_ref().unwrap().0, now + hour * 24);
+    }
+
+    #[test]
+    fn test_redacted_spec() {
+        let spec: WebhookHandlerSpec = serde_json::from_value(json!({
+            "topic": "events",
+            "signatureKey": "inline-secret",
+            "verifyToken": "meta-token",
+            "signatureKeys": [
+                { "key": "old-secret" },
+                { "secretRef": { "name": "handler-x-signing", "key": "key-1" } }
+            ]
+        }))
+        .unwrap();
+
+        let redacted = serde_json::to_string(&redacted_spec(&spec)).unwrap();
+        assert!(!redacted.contains("inline-secret"));
+        assert!(!redacted.contains("meta-token"));
+        assert!(!redacted.contains("old-secret"));
+        // References only name the Secret and are safe to return
+        assert!(redacted.contains("handler-x-signing"));
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let mut spec: WebhookHandlerSpec = serde_json::from_value(json!({
+            "topic": "events",
+            "signatureKeys": [{ "secretRef": { "name": "handler-x-signing", "key": "key-1" } }],
+            "routes": [{ "path": "$.event", "mapping": [] }]
+        }))
+        .unwrap();
+        let req: PatchRequest = serde_json::from_value(json!({
+            "topic": "other-events",
+            "signature_key": "new-secret"
+        }))
+        .unwrap();
+
+        apply_patch(&mut spec, req, secret_ref("key-2"));
+
+        assert_eq!(spec.topic, "other-events");
+        assert_eq!(spec.signature_key_secret_ref, Some(secret_ref("key-2")));
+        assert!(spec.signature_keys.is_none());
+        // Fields missing from the patch are untouched
+        assert!(spec.routes.is_some());
+
+        // null removes a field or resets it to its default
+        spec.routing_mode = RoutingMode::FanOut;
+        let req: PatchRequest = serde_json::from_value(json!({
+            "routes": null,
+            "routing_mode": null,
+            "signature_key": null
+        }))
+        .unwrap();
+        assert!(check_patch(&req).is_ok());
+        apply_patch(&mut spec, req, secret_ref("key-3"));
+        assert!(spec.routes.is_none());
+        assert_eq!(spec.routing_mode, RoutingMode::default());
+        assert!(spec.signature_key_secret_ref.is_none());
+        assert!(spec.signing_keys().is_empty());
+        assert_eq!(spec.topic, "other-events");
+
+        let req: PatchRequest = serde_json::from_value(json!({ "topic": null })).unwrap();
+        assert!(check_patch(&req).is_err());
+    }
+
+    #[test]
+    fn test_owned_key_fields() {
+        let spec: WebhookHandlerSpec = serde_json::from_value(json!({
+            "topic": "events",
+            "signatureKeySecretRef": { "name": "handler-x-signing", "key": "signature-key" },
+            "signatureKeys": [
+                { "secretRef": { "name": "handler-x-signing", "key": "key-1" } },
+                { "secretRef": { "name": "shared-keys", "key": "key-2" } },
+                { "key": "inline-secret" }
+            ]
+        }))
+        .unwrap();
+        // Only fields of the owned Secret are ever removed
+        assert_eq!(
+            owned_key_fields(&spec, "handler-x"),
+            ["key-1", "signature-key"]
+        );
+
+        let req: ConfigRequest = serde_json::from_value(json!({ "topic": "events" })).unwrap();
+        let replaced = spec_from_request(&req, secret_ref("key-3"));
+        assert!(owned_key_fields(&replaced, "handler-x").is_empty());
+
+        let req: ConfigRequest =
+            serde_json::from_value(json!({ "topic": "events", "signature_key": "new-secret" }))
+                .unwrap();
+        let replaced = spec_from_request(&req, secret_ref("key-3"));
+        assert_eq!(owned_key_fields(&replaced, "handler-x"), ["key-3"]);
+
+        let now = Utc::now();
+        assert_ne!(
+            new_key_field(now),
+            new_key_field(now + chrono::Duration::milliseconds(1))
+        );
+    }
+
+    #[test]
+    fn test_verify_api_request() {
+        let key = "api-key";
+        let timestamp = Utc::now().timestamp().to_string();
+        let uri: Uri = "/config/789e4567-e89b-12d3-a456-426614174001"
+            .parse()
+            .unwrap();
+        let sign_legacy = |message: &str| {
+            let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).unwrap();
+            mac.update(format!("{}.{}", timestamp, message).as_bytes());
+            let signature = hex::encode(mac.finalize().into_bytes());
+            let mut headers = HeaderMap::new();
+            headers.insert("x-timestamp", timestamp.parse().unwrap());
+            headers.insert(
+                "x-signature",
+                format!("sha256={}", signature).parse().unwrap(),
+            );
+            headers
+        };
+        let sign = |message: &str| {
+            let mut headers = sign_legacy(message);
+            headers.insert(SIGNATURE_VERSION_HEADER, "2".parse().unwrap());
+            headers
+        };
+
+        let get = sign("GET./config/789e4567-e89b-12d3-a456-426614174001.");
+        assert!(verify_api_request(key, &Method::GET, &uri, &get, "").is_ok());
+        // The same signature does not authorize another method or handler
+        assert!(verify_api_request(key, &Method::DELETE, &uri, &get, "").is_err());
+        let other: Uri = "/config/00000000-0000-0000-0000-000000000000"
+            .parse()
+            .unwrap();
+        assert!(verify_api_request(key, &Method::GET, &other, &get, "").is_err());
+        // Version 2 signatures must cover the method and path
+        assert!(verify_api_request(key, &Method::GET, &uri, &sign(""), "").is_err());
+
+        let body = r#"{"grace_period_seconds":60}"#;
+        let rotate: Uri = "/config/789e4567-e89b-12d3-a456-426614174001/rotate"
+            .parse()
+            .unwrap();
+        let post = sign(&format!(
+            "POST./config/789e4567-e89b-12d3-a456-426614174001/rotate.{}",
+            body
+        ));
+        assert!(verify_api_request(key, &Method::POST, &rotate, &post, body).is_ok());
+        assert!(verify_api_request(key, &Method::PATCH, &uri, &post, body).is_err());
+
+        // Existing clients sign POST /config over the body only
+        let create: Uri = "/config".parse().unwrap();
+        let body = r#"{"topic":"events"}"#;
+        let legacy = sign_legacy(body);
+        assert!(verify_api_request(key, &Method::POST, &create, &legacy, body).is_ok());
+        assert!(verify_api_request(key, &Method::POST, &create, &legacy, "{}").is_err());
+        let v2 = sign(&format!("POST./config.{}", body));
+        assert!(verify_api_request(key, &Method::POST, &create, &v2, body).is_ok());
+        // A legacy signature is checked as version 2 once the header is set
+        let mut upgraded = legacy.clone();
+        upgraded.insert(SIGNATURE_VERSION_HEADER, "2".parse().unwrap());
+        assert!(verify_api_request(key, &Method::POST, &create, &upgraded, body).is_err());
+
+        // No other endpoint accepts the legacy form
+        assert!(verify_api_request(key, &Method::GET, &create, &sign_legacy(""), "").is_err());
+        assert!(verify_api_request(key, &Method::POST, &rotate, &sign_legacy(body), body).is_err());
+        let mut unknown = v2.clone();
+        unknown.insert(SIGNATURE_VERSION_HEADER, "3".parse().unwrap());
+        assert!(verify_api_request(key, &Method::POST, &create, &unknown, body).is_err());
+    }
+
+    #[test]
+    fn test_rotate_request() {
+        let req: RotateRequest = serde_json::from_value(json!({})).unwrap();
+        assert_eq!(req.grace_period_seconds, default_grace_period_seconds());
+        // A misspelled field must not silently fall back to the default
+        assert!(serde_json::from_value::<RotateRequest>(json!({ "grace_period": 60 })).is_err());
+    }
+
+    #[test]
+    fn test_generate_signing_key() {
+        let key = generate_signing_key();
+        let encoded = key.strip_prefix("whsec_").unwrap();
+        assert_eq!(BASE64.decode(encoded).unwrap().len(), 32);
+        assert_ne!(key, generate_signing_key());
+    }
+}
@@ -26,9 +26,7 @@ pub async fn health() -> Json<HealthResponse> {
     })
 }
 
-pub async fn ready(
-    Extension(state): Extension<AppState>,
-) -> (StatusCode, Json<HealthResponse>) {
+pub async fn ready(Extension(state): Extension<AppState>) -> (StatusCode, Json<HealthResponse>) {
     // Check multiple health indicators
     let kafka_status: String;
     let k8s_status: String;
@@ -62,7 +60,7 @@ pub async fn ready(
 
     // 3. Check that handlers are loaded
     let handlers_count = state.handlers.read().await.len();
-    
+
     // If we have no handlers loaded but Kafka and K8s are OK, still consider ready
     // (it's valid to have zero handlers configured)
 
@@ -92,7 +90,8 @@ async fn check_kafka_connection(state: &AppState) -> Result<bool, String> {
         state.kafka_producer.send(
             "__health_check__", // Use a special topic or check metadata
             None,
-            r#"{"type":"health_check"}"#,
+            br#"{"type":"health_check"}"#,
+            &[],
         ),
     )
     .await;
@@ -140,4 +139,4 @@ mod tests {
         let response = health().await;
         assert_eq!(response.0.status, "healthy");
     }
-}
\ No newline at end of file
+}
@@ -1,3 +1,3 @@
 pub mod config;
 pub mod health;
-pub mod webhook;
\ No newline at end of file
+pub mod webhook;
@@ -1,20 +1,45 @@
 use axum::{
-    extract::{Extension, Path},
-    http::{HeaderMap, StatusCode},
+    body::Bytes,
+    extract::{Extension, Path, Query},
+    http::{HeaderMap, Method, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
-use serde::{Serialize};
+use serde::Serialize;
 use serde_json::json;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::filter::{route_to_topic, should_process_event};
-use crate::signature::verify_signature;
+use crate::challenge::{body_challenge, query_challenge, ChallengeResponse};
+use crate::cloudevents::CloudEvent;
+use crate::crd::{
+    InvalidPayloadPolicy, MessageFormat, MissingPolicy, PartialFailurePolicy, RoutingMode,
+};
+use crate::filter::{MissingPath, Outcome, WebhookEvent};
+use crate::kafka::RecordHeader;
+use crate::serializer::{EncodeError, Serializer};
+use crate::signature::verify_webhook_keys;
 use crate::state::AppState;
+use crate::transform::Transform;
 
 #[derive(Serialize)]
 pub struct WebhookResponse {
     success: bool,
     message: String,
+    /// Topics the event was published to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    topics: Vec<String>,
+    /// Fan-out topics the event could not be published to
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    failed: Vec<FailedTopic>,
+}
+
+#[derive(Serialize)]
+pub struct FailedTopic {
+    topic: String,
+    error: String,
 }
 
 #[derive(Serialize)]
@@ -22,6 +47,7 @@ pub struct ErrorResponse {
     error: String,
 }
 
+/// The JSON envelope published with `messageFormat: envelope`
 #[derive(Serialize)]
 pub struct KafkaMessage {
     headers: serde_json::Value,
@@ -29,12 +55,106 @@ pub struct KafkaMessage {
     received_at: String,
 }
 
+/// A `KafkaMessage` as published, with the reason when it is dead-lettered
+#[derive(Serialize)]
+struct Envelope<'a> {
+    #[serde(flatten)]
+    message: &'a KafkaMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_letter_reason: Option<&'a str>,
+}
+
+/// The original request, published as the record value and record headers
+pub struct RawMessage {
+    body: Bytes,
+    headers: Vec<RecordHeader>,
+    /// The routes that picked the topics, as `route:<number>`, and
+    /// `default` if the default topic is published to; comma-separated
+    route: String,
+}
+
+/// What is published for a webhook, in the handler's `messageFormat`
+enum Message {
+    Envelope(KafkaMessage),
+    Raw(RawMessage),
+    CloudEvent(CloudEvent),
+}
+
+/// A message ready to publish, built once per request
+struct Outgoing {
+    message: Message,
+    /// Why the event is sent to the dead-letter topic
+    dead_letter_reason: Option<String>,
+    /// Schema Registry encoding of the record value; not applied to dead-letter records
+    serializer: Option<Arc<Serializer>>,
+    /// False when the body failed `payloadSchema` and was passed on anyway
+    schema_valid: bool,
+}
+
+/// A serialized Kafka record
+struct Record {
+    value: Vec<u8>,
+    headers: Vec<RecordHeader>,
+}
+
+/// Record headers set by the operator in the `raw` format; request headers
+/// with these names are not forwarded
+const HANDLER_ID_HEADER: &str = "webhook-handler-id";
+const RECEIVED_AT_HEADER: &str = "webhook-received-at";
+const ROUTE_HEADER: &str = "webhook-route";
+const DEAD_LETTER_REASON_HEADER: &str = "webhook-dead-letter-reason";
+
+/// Record header marking events published despite failing `payloadSchema`;
+/// never forwarded from the request either
+const SCHEMA_VALID_HEADER: &str = "schema-valid";
+
+/// Answers GET endpoint-validation handshakes (e.g. Meta's `hub.challenge`)
+pub async fn handle_challenge(
+    Extension(state): Extension<AppState>,
+    Path(uuid): Path<Uuid>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    tracing::debug!("Received validation request for handler: {}", uuid);
+
+    let handlers = state.handlers.read().await;
+    let handler_config = handlers.get(&uuid).ok_or_else(|| {
+        tracing::warn!("Handler not found: {}", uuid);
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Handler not found".to_string(),
+            }),
+        )
+    })?;
+
+    let challenge_mode = handler_config.challenge_mode;
+    let verify_token = handler_config.verify_token.clone();
+    drop(handlers); // Release lock
+
+    let response = match challenge_mode {
+        Some(mode) => query_challenge(mode, &query, verify_token.as_deref())
+            .map_err(|e| challenge_rejected(uuid, e))?,
+        None => None,
+    };
+
+    response.map(challenge_response).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Not a validation request".to_string(),
+            }),
+        )
+    })
+}
+
 pub async fn handle_webhook(
     Extension(state): Extension<AppState>,
     Path(uuid): Path<Uuid>,
+    Query(query): Query<HashMap<String, String>>,
+    method: Method,
     headers: HeaderMap,
-    body: String,
-) -> Result<Json<WebhookResponse>, (StatusCode, Json<ErrorResponse>)> {
+    body: Bytes,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
     tracing::debug!("Received webhook for handler: {}", uuid);
 
     // Look up handler configuration
@@ -50,47 +170,41 @@ pub async fn handle_webhook(
     })?;
 
     let default_topic = handler_config.topic.clone();
-    let signature_key = handler_config.signature_key.clone();
-    let filters = handler_config.filters.clone();
-    let routes = handler_config.routes.clone();
+    let signature_keys = handler_config.signature_keys.clone();
+    let signature_scheme = handler_config.signature_scheme;
+    let challenge_mode = handler_config.challenge_mode;
+    let verify_token = handler_config.verify_token.clone();
+    let plan = handler_config.plan.clone();
+    let output = handler_config.output.clone();
+    let dead_letter_topic = handler_config.dead_letter_topic.clone();
+    let routing_mode = handler_config.routing_mode;
+    let include_default_topic = handler_config.include_default_topic;
+    let partial_failure = handler_config.partial_failure;
     drop(handlers); // Release lock
 
-    // Verify signature if configured
-    if let Some(key) = signature_key {
-        let signature = headers
-            .get("x-signature")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| {
-                (
-                    StatusCode::UNAUTHORIZED,
-                    Json(ErrorResponse {
-                        error: "Missing X-Signature header".to_string(),
-                    }),
-                )
-            })?;
+    // Answer unsigned validation handshakes (e.g. Microsoft Graph's validationToken)
+    if let Some(mode) = challenge_mode {
+        if let Some(response) = query_challenge(mode, &query, verify_token.as_deref())
+            .map_err(|e| challenge_rejected(uuid, e))?
+        {
+            tracing::info!("Answered validation request for handler: {}", uuid);
+            return Ok(challenge_response(response));
+        }
+    }
 
-        let timestamp = headers
-            .get("x-timestamp")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| {
+    // Verify signature if configured
+    if !signature_keys.is_empty() {
+        let is_valid = verify_webhook_keys(signature_scheme, &signature_keys, &headers, &body)
+            .map_err(|e| {
+                tracing::warn!("Signature verification error for handler {}: {}", uuid, e);
                 (
                     StatusCode::UNAUTHORIZED,
                     Json(ErrorResponse {
-                        error: "Missing X-Timestamp header".to_string(),
+                        error: e.to_string(),
                     }),
                 )
             })?;
 
-        let is_valid = verify_signature(&key, timestamp, &body, signature).map_err(|e| {
-            tracing::error!("Signature verification error for handler {}: {}", uuid, e);
-            (
-                StatusCode::UNAUTHORIZED,
-                Json(ErrorResponse {
-                    error: "Invalid signature".to_string(),
-                }),
-            )
-        })?;
-
         if !is_valid {
             tracing::warn!("Invalid signature for handler: {}", uuid);
             return Err((
@@ -103,86 +217,542 @@ pub async fn handle_webhook(
     }
 
     // Parse body as JSON (or store as string if not valid JSON)
-    let body_json: serde_json::Value = serde_json::from_str(&body)
-        .unwrap_or_else(|_| json!({ "raw": body }));
-
-    // Apply filters if configured
-    if let Some(filter_rules) = filters {
-        match should_process_event(&body_json, &filter_rules) {
-            Ok(should_process) => {
-                if !should_process {
-                    tracing::info!("Event filtered out for handler: {}", uuid);
-                    return Ok(Json(WebhookResponse {
-                        success: true,
-                        message: "Event filtered, not sent to Kafka".to_string(),
-                    }));
+    let body_json: serde_json::Value = serde_json::from_slice(&body)
+        .unwrap_or_else(|_| json!({ "raw": String::from_utf8_lossy(&body) }));
+
+    // Answer signed validation handshakes (Zoom, Slack) without publishing them
+    if let Some(mode) = challenge_mode {
+        // Zoom's handshake is answered with the newest active key
+        let now = chrono::Utc::now();
+        let primary_key = signature_keys.iter().find(|key| key.is_active(now));
+        if let Some(response) =
+            body_challenge(mode, &body_json, primary_key.map(|key| key.secret.as_str()))
+                .map_err(|e| challenge_rejected(uuid, e))?
+        {
+            tracing::info!("Answered validation request for handler: {}", uuid);
+            return Ok(challenge_response(response));
+        }
+    }
+
+    let received_at = chrono::Utc::now();
+    let headers_json = headers_to_json(&headers);
+    let query_json = serde_json::to_value(&query).unwrap_or_default();
+    let method_json = json!(method.as_str());
+    let event = WebhookEvent {
+        body: &body_json,
+        headers: &headers_json,
+        query: &query_json,
+        method: &method_json,
+        received_at,
+    };
+    let cloud_event = output
+        .cloud_events()
+        .map(|cloud_events| cloud_events.event(&event, &body, uuid));
+
+    // Validate the body against the handler's payload schema before filtering
+    let schema_error = output.payload_schema().and_then(|schema| {
+        let error = schema.validate(&body_json).err()?;
+        Some((schema.on_invalid(), error))
+    });
+    let schema_valid = schema_error.is_none();
+    // Builds the message from the body to publish: the request body, or the
+    // transformed body and its JSON
+    let outgoing = |body_json: serde_json::Value, transformed: Option<Bytes>, route: &str| {
+        let message = match (output.format(), cloud_event) {
+            (_, Some(mut cloud_event)) => {
+                if let Some(data) = transformed {
+                    cloud_event.set_json_data(data);
                 }
+                Message::CloudEvent(cloud_event)
             }
+            (MessageFormat::Raw, None) => Message::Raw(RawMessage {
+                body: transformed.unwrap_or_else(|| body.clone()),
+                headers: raw_headers(&headers, uuid, &received_at.to_rfc3339()),
+                route: route.to_string(),
+            }),
+            _ => Message::Envelope(KafkaMessage {
+                headers: headers_json.clone(),
+                body: body_json,
+                received_at: received_at.to_rfc3339(),
+            }),
+        };
+        Outgoing {
+            message,
+            dead_letter_reason: None,
+            serializer: output.serializer().cloned(),
+            schema_valid,
+        }
+    };
+
+    match schema_error {
+        Some((InvalidPayloadPolicy::Reject, error)) => {
+            tracing::warn!("Rejected webhook for handler {}: {}", uuid, error);
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorResponse { error }),
+            ));
+        }
+        Some((InvalidPayloadPolicy::DeadLetter, error)) => {
+            let message = outgoing(body_json, None, "default");
+            return match dead_letter_topic.as_deref() {
+                Some(topic) => dead_letter(&state, uuid, topic, message, error).await,
+                None => Err(no_dead_letter_topic(uuid, &error)),
+            };
+        }
+        Some((InvalidPayloadPolicy::Pass, error)) => {
+            tracing::info!("Passing invalid payload for handler {}: {}", uuid, error);
+        }
+        None => {}
+    }
+
+    // Apply filters, then the transform, then routes, in order
+    let filtered = plan.should_process_event(&event).map(|passed| !passed);
+    let transformed = match (&filtered, output.transform()) {
+        (Ok(false), Some(transform)) => Some(apply_transform(uuid, transform, &body_json)?),
+        _ => None,
+    };
+    let route_event = WebhookEvent {
+        body: route_body(output.transform(), transformed.as_ref(), &body_json),
+        headers: &headers_json,
+        query: &query_json,
+        method: &method_json,
+        received_at,
+    };
+    let outcome = match filtered {
+        Ok(true) => Ok(Outcome::Filtered),
+        Ok(false) => plan.route(&route_event, routing_mode),
+        Err(missing) => Err(missing),
+    };
+
+    let (mut target_topics, routes): (Vec<String>, _) = match outcome {
+        Ok(Outcome::Publish { topics, routes }) => {
+            (topics.into_iter().map(Cow::into_owned).collect(), routes)
+        }
+        Ok(Outcome::Filtered) => {
+            tracing::info!("Event filtered out for handler: {}", uuid);
+            return Ok(Json(WebhookResponse {
+                success: true,
+                message: "Event filtered, not sent to Kafka".to_string(),
+                topics: Vec::new(),
+                failed: Vec::new(),
+            })
+            .into_response());
+        }
+        Ok(Outcome::Dropped) => {
+            tracing::info!("Event dropped by route for handler: {}", uuid);
+            return Ok(Json(WebhookResponse {
+                success: true,
+                message: "Event dropped by route, not sent to Kafka".to_string(),
+                topics: Vec::new(),
+                failed: Vec::new(),
+            })
+            .into_response());
+        }
+        Ok(Outcome::DeadLetter(reason)) => {
+            let message = outgoing(body_json, None, "default");
+            return match dead_letter_topic.as_deref() {
+                Some(topic) => dead_letter(&state, uuid, topic, message, reason).await,
+                None => Err(no_dead_letter_topic(uuid, &reason)),
+            };
+        }
+        Err(missing) => {
+            let message = outgoing(body_json, None, "default");
+            return missing_path(&state, uuid, missing, message, dead_letter_topic.as_deref())
+                .await;
+        }
+    };
+    let key = match plan.message_key(&event, uuid) {
+        Ok(key) => key,
+        Err(missing) => {
+            let message = outgoing(body_json, None, "default");
+            return missing_path(&state, uuid, missing, message, dead_letter_topic.as_deref())
+                .await;
+        }
+    };
+    // No route matched, use default (or add it to the fan-out)
+    let mut route: Vec<String> = routes
+        .iter()
+        .map(|route| format!("route:{}", route))
+        .collect();
+    if target_topics.is_empty()
+        || (routing_mode == RoutingMode::FanOut
+            && include_default_topic
+            && !target_topics.contains(&default_topic))
+    {
+        target_topics.push(default_topic);
+        route.push("default".to_string());
+    }
+
+    // A transformed body replaces the request body in every format
+    let transformed_json = transformed
+        .as_ref()
+        .map(|body| Bytes::from(body.to_string()));
+    let kafka_message = outgoing(
+        transformed.unwrap_or(body_json),
+        transformed_json,
+        &route.join(","),
+    );
+
+    let target_topic = match target_topics.as_slice() {
+        [target_topic] => target_topic.clone(),
+        _ => {
+            let dead_letter_topic = dead_letter_topic.as_deref();
+            return fan_out(
+                &state,
+                uuid,
+                target_topics,
+                key.as_deref(),
+                kafka_message,
+                partial_failure,
+                dead_letter_topic,
+            )
+            .await;
+        }
+    };
+
+    publish(&state, uuid, &target_topic, key.as_deref(), &kafka_message).await?;
+
+    tracing::info!(
+        "Successfully processed webhook for handler: {} -> topic: {}",
+        uuid,
+        target_topic
+    );
+
+    Ok(Json(WebhookResponse {
+        success: true,
+        message: format!("Webhook sent to topic: {}", target_topic),
+        topics: vec![target_topic],
+        failed: Vec::new(),
+    })
+    .into_response())
+}
+
+/// Reshapes a body that passed the filters. A failing `patch` operation
+/// rejects the webhook with a 422.
+fn apply_transform(
+    uuid: Uuid,
+    transform: &Transform,
+    body: &serde_json::Value,
+) -> Result<serde_json::Value, (StatusCode, Json<ErrorResponse>)> {
+    transform.apply(body).map_err(|e| {
+        tracing::warn!("Rejected webhook for handler {}: {}", uuid, e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })
+}
+
+/// The body routes match: the transformed body with `routeOnOutput`, else
+/// the request body
+fn route_body<'a>(
+    transform: Option<&Transform>,
+    transformed: Option<&'a serde_json::Value>,
+    body: &'a serde_json::Value,
+) -> &'a serde_json::Value {
+    match transformed {
+        Some(transformed) if transform.is_some_and(Transform::route_on_output) => transformed,
+        _ => body,
+    }
+}
+
+/// Applies the `onMissing` policy of a filter or route whose path selected nothing
+/// or whose expression failed
+async fn missing_path(
+    state: &AppState,
+    uuid: Uuid,
+    missing: MissingPath,
+    kafka_message: Outgoing,
+    dead_letter_topic: Option<&str>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    match (missing.policy, dead_letter_topic) {
+        (MissingPolicy::Reject, _) => {
+            tracing::warn!("Rejected webhook for handler {}: {}", uuid, missing);
+            Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                Json(ErrorResponse {
+                    error: missing.to_string(),
+                }),
+            ))
+        }
+        (MissingPolicy::DeadLetter, Some(topic)) => {
+            dead_letter(state, uuid, topic, kafka_message, missing.to_string()).await
+        }
+        _ => Err(no_dead_letter_topic(uuid, &missing.to_string())),
+    }
+}
+
+/// Sends the event to the dead-letter topic, keyed by handler UUID and recording why
+async fn dead_letter(
+    state: &AppState,
+    uuid: Uuid,
+    topic: &str,
+    mut kafka_message: Outgoing,
+    reason: String,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    tracing::info!(
+        "Sending webhook for handler {} to dead-letter topic {}: {}",
+        uuid,
+        topic,
+        reason
+    );
+    kafka_message.dead_letter_reason = Some(reason);
+    publish(state, uuid, topic, Some(&uuid.to_string()), &kafka_message).await?;
+
+    Ok(Json(WebhookResponse {
+        success: true,
+        message: format!("Event sent to dead-letter topic: {}", topic),
+        topics: vec![topic.to_string()],
+        failed: Vec::new(),
+    })
+    .into_response())
+}
+
+fn no_dead_letter_topic(uuid: Uuid, reason: &str) -> (StatusCode, Json<ErrorResponse>) {
+    // Dead-lettering without a topic is rejected when the handler is loaded
+    tracing::error!("No dead-letter topic for handler {}: {}", uuid, reason);
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: "No dead-letter topic configured".to_string(),
+        }),
+    )
+}
+
+/// Publishes to every fan-out topic at once. If only some topics fail, the
+/// handler's `partialFailure` policy decides the response; if all fail, the
+/// webhook fails so the provider retries it.
+async fn fan_out(
+    state: &AppState,
+    uuid: Uuid,
+    topics: Vec<String>,
+    key: Option<&str>,
+    mut kafka_message: Outgoing,
+    partial_failure: PartialFailurePolicy,
+    dead_letter_topic: Option<&str>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let record = serialize(&kafka_message)?;
+    // Encode for every topic before sending, so a value the schema rejects publishes nothing
+    let mut values = Vec::with_capacity(topics.len());
+    for topic in &topics {
+        values.push(encode(state, uuid, topic, &kafka_message, record.value.clone()).await?);
+    }
+    let results = futures::future::join_all(topics.iter().zip(&values).map(|(topic, value)| {
+        state
+            .kafka_producer
+            .send(topic, key, value, &record.headers)
+    }))
+    .await;
+
+    let mut delivered = Vec::new();
+    let mut failed = Vec::new();
+    for (topic, result) in topics.into_iter().zip(results) {
+        match result {
+            Ok(()) => delivered.push(topic),
             Err(e) => {
-                tracing::error!("Filter evaluation error for handler {}: {}", uuid, e);
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(ErrorResponse {
-                        error: format!("Filter error: {}", e),
-                    }),
+                tracing::error!(
+                    "Failed to send to Kafka topic {} for handler {}: {}",
+                    topic,
+                    uuid,
+                    e
+                );
+                failed.push(FailedTopic {
+                    topic,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    let message = format!("Webhook sent to topics: {}", delivered.join(", "));
+    let response = |status: StatusCode, success: bool, message: String, delivered, failed| {
+        (
+            status,
+            Json(WebhookResponse {
+                success,
+                message,
+                topics: delivered,
+                failed,
+            }),
+        )
+            .into_response()
+    };
+
+    if failed.is_empty() {
+        tracing::info!(
+            "Successfully processed webhook for handler: {} -> topics: {}",
+            uuid,
+            delivered.join(", ")
+        );
+        return Ok(response(StatusCode::OK, true, message, delivered, failed));
+    }
+    if delivered.is_empty() {
+        return Ok(response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            false,
+            "Failed to send to Kafka".to_string(),
+            delivered,
+            failed,
+        ));
+    }
+
+    match (partial_failure, dead_letter_topic) {
+        (PartialFailurePolicy::Accept, _) => {
+            Ok(response(StatusCode::OK, true, message, delivered, failed))
+        }
+        (PartialFailurePolicy::DeadLetter, Some(dead_letter_topic)) => {
+            for failure in &failed {
+                kafka_message.dead_letter_reason = Some(format!(
+                    "Failed to send to topic {}: {}",
+                    failure.topic, failure.error
                 ));
+                publish(
+                    state,
+                    uuid,
+                    dead_letter_topic,
+                    Some(&uuid.to_string()),
+                    &kafka_message,
+                )
+                .await?;
             }
+            let message = format!(
+                "{}; failed topics sent to dead-letter topic: {}",
+                message, dead_letter_topic
+            );
+            Ok(response(StatusCode::OK, true, message, delivered, failed))
         }
+        // partialFailure: dead_letter without a topic is rejected when the handler is loaded
+        _ => Ok(response(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            false,
+            format!("Failed to send to some topics; {}", message),
+            delivered,
+            failed,
+        )),
     }
+}
 
-    // Determine target topic using routing rules
-    let target_topic = if let Some(route_rules) = routes {
-        match route_to_topic(&body_json, &route_rules) {
-            Ok(Some(routed_topic)) => routed_topic,
-            Ok(None) => default_topic, // No route matched, use default
-            Err(e) => {
-                tracing::error!("Routing error for handler {}: {}", uuid, e);
-                return Err((
+/// The record value and headers for a message in the handler's `messageFormat`
+fn serialize(kafka_message: &Outgoing) -> Result<Record, (StatusCode, Json<ErrorResponse>)> {
+    let dead_letter_reason = kafka_message.dead_letter_reason.as_deref();
+    let mut record = match &kafka_message.message {
+        Message::Envelope(message) => {
+            let envelope = Envelope {
+                message,
+                dead_letter_reason,
+            };
+            let value = serde_json::to_vec(&envelope).map_err(|e| {
+                tracing::error!("Failed to serialize Kafka message: {}", e);
+                (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     Json(ErrorResponse {
-                        error: format!("Routing error: {}", e),
+                        error: "Failed to process message".to_string(),
                     }),
-                ));
+                )
+            })?;
+            Record {
+                value,
+                headers: Vec::new(),
+            }
+        }
+        Message::Raw(raw) => {
+            let mut headers = raw.headers.clone();
+            match dead_letter_reason {
+                Some(reason) => {
+                    headers.push((ROUTE_HEADER.to_string(), b"dead_letter".to_vec()));
+                    headers.push((
+                        DEAD_LETTER_REASON_HEADER.to_string(),
+                        reason.as_bytes().to_vec(),
+                    ));
+                }
+                None => headers.push((ROUTE_HEADER.to_string(), raw.route.as_bytes().to_vec())),
             }
+            Record {
+                value: raw.body.to_vec(),
+                headers,
+            }
+        }
+        Message::CloudEvent(cloud_event) => {
+            let (value, headers) = cloud_event.record(dead_letter_reason);
+            Record { value, headers }
         }
-    } else {
-        default_topic
     };
+    if !kafka_message.schema_valid {
+        record
+            .headers
+            .push((SCHEMA_VALID_HEADER.to_string(), b"false".to_vec()));
+    }
+    Ok(record)
+}
 
-
-    // Convert headers to JSON
-    let headers_json: serde_json::Value = headers
-        .iter()
-        .map(|(k, v)| {
-            (
-                k.as_str().to_string(),
-                serde_json::Value::String(v.to_str().unwrap_or("").to_string()),
-            )
-        })
-        .collect::<serde_json::Map<String, serde_json::Value>>()
-        .into();
-
-    // Create Kafka message
-    let kafka_message = KafkaMessage {
-        headers: headers_json,
-        body: body_json,
-        received_at: chrono::Utc::now().to_rfc3339(),
+/// Encodes the record value with the handler's `serializer`, if any. Values
+/// the schema rejects get a 422; registry failures a 500 so the provider retries.
+async fn encode(
+    state: &AppState,
+    uuid: Uuid,
+    topic: &str,
+    kafka_message: &Outgoing,
+    value: Vec<u8>,
+) -> Result<Vec<u8>, (StatusCode, Json<ErrorResponse>)> {
+    let serializer = match &kafka_message.serializer {
+        Some(serializer) if kafka_message.dead_letter_reason.is_none() => serializer,
+        _ => return Ok(value),
     };
-
-    let kafka_payload = serde_json::to_string(&kafka_message).map_err(|e| {
-        tracing::error!("Failed to serialize Kafka message: {}", e);
-        (
+    let Some(registry) = &state.schema_registry else {
+        tracing::error!(
+            "Handler {} has a serializer but SCHEMA_REGISTRY_URL is not set",
+            uuid
+        );
+        return Err((
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "Failed to process message".to_string(),
+                error: "Schema Registry is not configured".to_string(),
             }),
-        )
-    })?;
+        ));
+    };
+
+    serializer
+        .encode(registry, topic, &value)
+        .await
+        .map_err(|e| match e {
+            EncodeError::Value(_) => {
+                tracing::warn!("Rejected webhook for handler {}: {}", uuid, e);
+                (
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    Json(ErrorResponse {
+                        error: e.to_string(),
+                    }),
+                )
+            }
+            EncodeError::Registry(_) => {
+                tracing::error!("Schema Registry error for handler {}: {}", uuid, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: "Failed to encode message".to_string(),
+                    }),
+                )
+            }
+        })
+}
+
+/// Serializes the message and sends it to Kafka with the given key
+async fn publish(
+    state: &AppState,
+    uuid: Uuid,
+    topic: &str,
+    key: Option<&str>,
+    kafka_message: &Outgoing,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let record = serialize(kafka_message)?;
+    let value = encode(state, uuid, topic, kafka_message, record.value).await?;
 
     // Send to Kafka
     state
         .kafka_producer
-        .send(&target_topic, Some(&uuid.to_string()), &kafka_payload)
+        .send(topic, key, &value, &record.headers)
         .await
         .map_err(|e| {
             tracing::error!("Failed to send to Kafka for handler {}: {}", uuid, e);
@@ -194,14 +764,366 @@ pub async fn handle_webhook(
             )
         })?;
 
-    tracing::info!(
-        "Successfully processed webhook for handler: {} -> topic: {}",
-        uuid,
-        target_topic
-    );
+    Ok(())
+}
 
-    Ok(Json(WebhookResponse {
-        success: true,
-        message: format!("Webhook sent to topic: {}", target_topic),
-    }))
-}
\ No newline at end of file
+/// Converts request headers to a JSON object
+fn headers_to_json(headers: &HeaderMap) -> serde_json::Value {
+    headers
+        .iter()
+        .map(|(k, v)| {
+            (
+                k.as_str().to_string(),
+                serde_json::Value::String(v.to_str().unwrap_or("").to_string()),
+            )
+        })
+        .collect::<serde_json::Map<String, serde_json::Value>>()
+        .into()
+}
+
+/// Request headers plus the operator's own, as record headers for the `raw` format
+fn raw_headers(headers: &HeaderMap, uuid: Uuid, received_at: &str) -> Vec<RecordHeader> {
+    let mut record_headers: Vec<RecordHeader> = headers
+        .iter()
+        .filter(|(name, _)| {
+            ![
+                HANDLER_ID_HEADER,
+                RECEIVED_AT_HEADER,
+                ROUTE_HEADER,
+                DEAD_LETTER_REASON_HEADER,
+                SCHEMA_VALID_HEADER,
+            ]
+            .contains(&name.as_str())
+        })
+        .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+        .collect();
+    record_headers.push((HANDLER_ID_HEADER.to_string(), uuid.to_string().into_bytes()));
+    record_headers.push((
+        RECEIVED_AT_HEADER.to_string(),
+        received_at.as_bytes().to_vec(),
+    ));
+    record_headers
+}
+
+fn challenge_response(response: ChallengeResponse) -> Response {
+    match response {
+        ChallengeResponse::Json(value) => Json(value).into_response(),
+        ChallengeResponse::Text(text) => text.into_response(),
+    }
+}
+
+fn challenge_rejected(uuid: Uuid, e: anyhow::Error) -> (StatusCode, Json<ErrorResponse>) {
+    tracing::warn!("Rejected validation request for handler {}: {}", uuid, e);
+    (
+        StatusCode::FORBIDDEN,
+        Json(ErrorResponse {
+            error: format!("Validation request rejected: {}", e),
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::WebhookHandlerSpec;
+    use crate::filter::Plan;
+    use crate::kafka::KafkaProducer;
+    use crate::output::Output;
+    use crate::state::HandlerConfig;
+    use tokio::sync::RwLock;
+
+    /// State serving one handler compiled from a spec, publishing to an in-memory producer
+    fn serving(spec: serde_json::Value) -> (AppState, Uuid) {
+        let spec: WebhookHandlerSpec = serde_json::from_value(spec).unwrap();
+        let plan = Plan::compile(
+            spec.filters.as_deref().unwrap_or_default(),
+            spec.routes.as_deref().unwrap_or_default(),
+        )
+        .and_then(|plan| plan.with_message_key(&spec))
+        .unwrap();
+        let config = HandlerConfig {
+            topic: spec.topic.clone(),
+            signature_keys: Vec::new(),
+            signature_scheme: spec.signature_scheme,
+            challenge_mode: spec.challenge_mode,
+            verify_token: spec.verify_token.clone(),
+            plan: Arc::new(plan),
+            dead_letter_topic: spec.dead_letter_topic.clone(),
+            routing_mode: spec.routing_mode,
+            include_default_topic: spec.include_default_topic,
+            partial_failure: spec.partial_failure,
+            output: Arc::new(Output::compile(&spec).unwrap()),
+        };
+        let uuid = Uuid::new_v4();
+        let state = AppState {
+            handlers: Arc::new(RwLock::new(HashMap::from([(uuid, config)]))),
+            kafka_producer: Arc::new(KafkaProducer::in_memory()),
+            schema_registry: None,
+            api_signing_key: String::new(),
+            external_url: String::new(),
+            namespace: "default".to_string(),
+        };
+        (state, uuid)
+    }
+
+    async fn post(
+        state: &AppState,
+        uuid: Uuid,
+        body: serde_json::Value,
+    ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+        let body = Bytes::from(body.to_string());
+        handle_webhook(
+            Extension(state.clone()),
+            Path(uuid),
+            Query(HashMap::new()),
+            Method::POST,
+            HeaderMap::new(),
+            body,
+        )
+        .await
+    }
+
+    fn outgoing(message: Message) -> Outgoing {
+        Outgoing {
+            message,
+            dead_letter_reason: None,
+            serializer: None,
+            schema_valid: true,
+        }
+    }
+
+    #[test]
+    fn test_serialize_formats() {
+        let envelope = || {
+            Message::Envelope(KafkaMessage {
+                headers: json!({ "x-env": "prod" }),
+                body: json!({ "event": "created" }),
+                received_at: "2026-01-01T00:00:00+00:00".to_string(),
+            })
+        };
+        let record = serialize(&outgoing(envelope())).ok().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&record.value).unwrap();
+        assert_eq!(
+            value,
+            json!({
+                "headers": { "x-env": "prod" },
+                "body": { "event": "created" },
+                "received_at": "2026-01-01T00:00:00+00:00"
+            })
+        );
+        assert!(record.headers.is_empty());
+
+        let mut dead_letter = outgoing(envelope());
+        dead_letter.dead_letter_reason = Some("Path matched no values: $.id".to_string());
+        dead_letter.schema_valid = false;
+        let record = serialize(&dead_letter).ok().unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&record.value).unwrap();
+        assert_eq!(value["dead_letter_reason"], "Path matched no values: $.id");
+        assert_eq!(
+            record.headers,
+            vec![(SCHEMA_VALID_HEADER.to_string(), b"false".to_vec())]
+        );
+
+        let mut raw = outgoing(Message::Raw(RawMessage {
+            body: Bytes::from_static(b"not json"),
+            headers: vec![("x-env".to_string(), b"prod".to_vec())],
+            route: "route:1".to_string(),
+        }));
+        let record = serialize(&raw).ok().unwrap();
+        assert_eq!(record.value, b"not json");
+        assert_eq!(
+            record.headers[1],
+            (ROUTE_HEADER.to_string(), b"route:1".to_vec())
+        );
+
+        raw.dead_letter_reason = Some("rejected".to_string());
+        let record = serialize(&raw).ok().unwrap();
+        assert_eq!(
+            record.headers[1],
+            (ROUTE_HEADER.to_string(), b"dead_letter".to_vec())
+        );
+        assert_eq!(
+            record.headers[2],
+            (DEAD_LETTER_REASON_HEADER.to_string(), b"rejected".to_vec())
+        );
+    }
+
+    fn transform(config: serde_json::Value) -> Transform {
+        Transform::compile(&serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_transform_rejection() {
+        let strict =
+            transform(json!({ "patch": [{ "op": "test", "path": "/event", "value": "deleted" }] }));
+        let (status, Json(response)) =
+            apply_transform(Uuid::nil(), &strict, &json!({ "event": "created" })).unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response.error.starts_with("Transform patch failed"));
+
+        assert_eq!(
+            apply_transform(Uuid::nil(), &strict, &json!({ "event": "deleted" })).ok(),
+            Some(json!({ "event": "deleted" }))
+        );
+    }
+
+    #[test]
+    fn test_route_on_output() {
+        let routes: Vec<crate::crd::Route> = serde_json::from_value(json!([{
+            "path": "$.account",
+            "mapping": [{ "value": "abc", "topic": "acme-events" }]
+        }]))
+        .unwrap();
+        let plan = crate::filter::Plan::compile(&[], &routes).unwrap();
+        let body = json!({ "payload": { "account_id": "abc" } });
+        let config = |route_on_output: bool| {
+            json!({
+                "patch": [{ "op": "move", "from": "/payload/account_id", "path": "/account" }],
+                "routeOnOutput": route_on_output
+            })
+        };
+        let topics = |config: serde_json::Value| {
+            let transform = transform(config);
+            let transformed = apply_transform(Uuid::nil(), &transform, &body)
+                .ok()
+                .unwrap();
+            let headers = json!({});
+            let event = WebhookEvent {
+                body: route_body(Some(&transform), Some(&transformed), &body),
+                headers: &headers,
+                query: &headers,
+                method: &headers,
+                received_at: chrono::Utc::now(),
+            };
+            match plan.route(&event, RoutingMode::FirstMatch).unwrap() {
+                Outcome::Publish { topics, .. } => {
+                    topics.into_iter().map(Cow::into_owned).collect::<Vec<_>>()
+                }
+                other => panic!("Expected topics, got {:?}", other),
+            }
+        };
+
+        // Routes match the transformed body only with routeOnOutput
+        assert_eq!(topics(config(true)), vec!["acme-events".to_string()]);
+        assert!(topics(config(false)).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_payload_schema_policies() {
+        let spec = |on_invalid: &str| {
+            json!({
+                "topic": "zoom.events",
+                "deadLetterTopic": "zoom.dead-letter",
+                "routes": [{ "path": "$.event", "mapping": [{ "value": "created", "topic": "zoom.created" }] }],
+                "payloadSchema": {
+                    "schema": { "type": "object", "required": ["event", "payload"] },
+                    "onInvalid": on_invalid
+                }
+            })
+        };
+        let invalid = json!({ "event": "created" });
+        let valid = json!({ "event": "created", "payload": {} });
+
+        // reject: 422 and nothing is published
+        let (state, uuid) = serving(spec("reject"));
+        let (status, Json(response)) = post(&state, uuid, invalid.clone()).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(response
+            .error
+            .starts_with("Payload does not match schema: "));
+        assert!(state.kafka_producer.sent().is_empty());
+
+        // dead_letter: the event goes to the dead-letter topic instead of its route
+        let (state, uuid) = serving(spec("dead_letter"));
+        let response = post(&state, uuid, invalid.clone()).await.ok().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let sent = state.kafka_producer.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].topic, "zoom.dead-letter");
+        assert_eq!(sent[0].key, Some(uuid.to_string()));
+        let value: serde_json::Value = serde_json::from_slice(&sent[0].value).unwrap();
+        assert_eq!(value["body"], invalid);
+        assert!(value["dead_letter_reason"]
+            .as_str()
+            .unwrap()
+            .contains("\"payload\" is a required property"));
+
+        // pass: the event is routed as usual, marked with a schema-valid header
+        let (state, uuid) = serving(spec("pass"));
+        post(&state, uuid, invalid).await.ok().unwrap();
+        post(&state, uuid, valid).await.ok().unwrap();
+        let sent = state.kafka_producer.sent();
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].topic, "zoom.created");
+        assert_eq!(
+            sent[0].headers,
+            vec![(SCHEMA_VALID_HEADER.to_string(), b"false".to_vec())]
+        );
+        assert_eq!(sent[1].topic, "zoom.created");
+        assert!(sent[1].headers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_raw_route_header() {
+        let spec = |routing_mode: &str| {
+            json!({
+                "topic": "zoom.events",
+                "messageFormat": "raw",
+                "routingMode": routing_mode,
+                "includeDefaultTopic": true,
+                "routes": [
+                    { "path": "$.event", "mapping": [{ "value": "deleted", "topic": "zoom.deleted" }] },
+                    { "path": "$.event", "mapping": [{ "prefix": "meeting.", "topic": "zoom.meetings" }] },
+                    { "path": "$.account", "mapping": [{ "value": "acme", "topic": "acme.events" }] }
+                ]
+            })
+        };
+        let route_header = |record: &crate::kafka::SentRecord| {
+            let (_, value) = record
+                .headers
+                .iter()
+                .find(|(name, _)| name == ROUTE_HEADER)
+                .unwrap();
+            String::from_utf8(value.clone()).unwrap()
+        };
+
+        let (state, uuid) = serving(spec("first_match"));
+        post(
+            &state,
+            uuid,
+            json!({ "event": "meeting.started", "account": "acme" }),
+        )
+        .await
+        .ok()
+        .unwrap();
+        post(&state, uuid, json!({ "event": "recording.completed" }))
+            .await
+            .ok()
+            .unwrap();
+        let sent = state.kafka_producer.sent();
+        assert_eq!(
+            (sent[0].topic.as_str(), route_header(&sent[0]).as_str()),
+            ("zoom.meetings", "route:2")
+        );
+        assert_eq!(
+            (sent[1].topic.as_str(), route_header(&sent[1]).as_str()),
+            ("zoom.events", "default")
+        );
+
+        // Fan-out records name every matched route, and the default topic when included
+        let (state, uuid) = serving(spec("fan_out"));
+        post(
+            &state,
+            uuid,
+            json!({ "event": "meeting.started", "account": "acme" }),
+        )
+        .await
+        .ok()
+        .unwrap();
+        let sent = state.kafka_producer.sent();
+        assert_eq!(sent.len(), 3);
+        assert!(sent
+            .iter()
+            .all(|record| route_header(record) == "route:2,route:3,default"));
+    }
+}
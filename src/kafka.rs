@@ -1,12 +1,35 @@
 use anyhow::{Context, Result};
 use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use std::collections::HashSet;
 use std::time::Duration;
 
 use crate::config::Config;
 
+/// A native Kafka record header
+pub type RecordHeader = (String, Vec<u8>);
+
 pub struct KafkaProducer {
-    producer: FutureProducer,
+    producer: Sink,
+}
+
+/// Where records are sent
+enum Sink {
+    Kafka(FutureProducer),
+    /// Keeps sent records in memory, for tests that drive the webhook handler
+    #[cfg(test)]
+    Memory(std::sync::Mutex<Vec<SentRecord>>),
+}
+
+/// A record kept by an in-memory producer
+#[cfg(test)]
+#[derive(Debug, Clone)]
+pub struct SentRecord {
+    pub topic: String,
+    pub key: Option<String>,
+    pub value: Vec<u8>,
+    pub headers: Vec<RecordHeader>,
 }
 
 impl KafkaProducer {
@@ -22,27 +45,134 @@ impl KafkaProducer {
             .create()
             .context("Failed to create Kafka producer")?;
 
-        Ok(KafkaProducer { producer })
+        Ok(KafkaProducer {
+            producer: Sink::Kafka(producer),
+        })
+    }
+
+    /// A producer that keeps records in memory instead of sending them
+    #[cfg(test)]
+    pub fn in_memory() -> Self {
+        KafkaProducer {
+            producer: Sink::Memory(Default::default()),
+        }
+    }
+
+    /// Records sent so far by an in-memory producer
+    #[cfg(test)]
+    pub fn sent(&self) -> Vec<SentRecord> {
+        match &self.producer {
+            Sink::Memory(sent) => sent.lock().unwrap().clone(),
+            Sink::Kafka(_) => Vec::new(),
+        }
     }
 
     pub async fn send(
         &self,
         topic: &str,
         key: Option<&str>,
-        payload: &str,
+        payload: &[u8],
+        headers: &[RecordHeader],
     ) -> Result<()> {
-        let mut record = FutureRecord::to(topic).payload(payload);
-        
-        if let Some(k) = key {
-            record = record.key(k);
+        match &self.producer {
+            Sink::Kafka(producer) => send_record(producer, topic, key, payload, headers).await,
+            #[cfg(test)]
+            Sink::Memory(sent) => {
+                sent.lock().unwrap().push(SentRecord {
+                    topic: topic.to_string(),
+                    key: key.map(str::to_string),
+                    value: payload.to_vec(),
+                    headers: headers.to_vec(),
+                });
+                Ok(())
+            }
         }
+    }
 
-        self.producer
-            .send(record, Duration::from_secs(5))
-            .await
-            .map_err(|(e, _)| anyhow::anyhow!("Failed to send to Kafka: {}", e))?;
+    /// Lists the topics currently known to the cluster
+    pub async fn list_topics(&self) -> Result<HashSet<String>> {
+        match &self.producer {
+            Sink::Kafka(producer) => fetch_topics(producer.clone()).await,
+            #[cfg(test)]
+            Sink::Memory(_) => Ok(HashSet::new()),
+        }
+    }
+}
 
-        tracing::debug!("Message sent to Kafka topic: {}", topic);
-        Ok(())
+async fn send_record(
+    producer: &FutureProducer,
+    topic: &str,
+    key: Option<&str>,
+    payload: &[u8],
+    headers: &[RecordHeader],
+) -> Result<()> {
+    let mut record = FutureRecord::to(topic).payload(payload);
+
+    if let Some(k) = key {
+        record = record.key(k);
+    }
+    if !headers.is_empty() {
+        record = record.headers(
+            headers
+                .iter()
+                .fold(OwnedHeaders::new(), |owned, (key, value)| {
+                    owned.insert(Header {
+                        key,
+                        value: Some(value),
+                    })
+                }),
+        );
+    }
+
+    producer
+        .send(record, Duration::from_secs(5))
+        .await
+        .map_err(|(e, _)| anyhow::anyhow!("Failed to send to Kafka: {}", e))?;
+
+    tracing::debug!("Message sent to Kafka topic: {}", topic);
+    Ok(())
+}
+
+async fn fetch_topics(producer: FutureProducer) -> Result<HashSet<String>> {
+    // Metadata requests block on the librdkafka client
+    let metadata = tokio::task::spawn_blocking(move || {
+        producer
+            .client()
+            .fetch_metadata(None, Duration::from_secs(5))
+    })
+    .await
+    .context("Metadata request panicked")?
+    .context("Failed to fetch Kafka metadata")?;
+
+    Ok(metadata
+        .topics()
+        .iter()
+        .map(|topic| topic.name().to_string())
+        .collect())
+}
+
+/// Checks a topic name against Kafka's naming rules
+pub fn is_valid_topic_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 249
+        && name != "."
+        && name != ".."
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_topic_name() {
+        assert!(is_valid_topic_name("zoom.account-123.events"));
+        assert!(is_valid_topic_name("tenant_a"));
+        assert!(!is_valid_topic_name(""));
+        assert!(!is_valid_topic_name(".."));
+        assert!(!is_valid_topic_name("has space"));
+        assert!(!is_valid_topic_name(&"a".repeat(250)));
     }
-}
\ No newline at end of file
+}
@@ -1,11 +1,20 @@
+mod cel;
+mod challenge;
+mod cloudevents;
 mod config;
 mod controller;
 mod crd;
 mod filter;
 mod handlers;
 mod kafka;
+mod output;
+mod schema;
+mod secrets;
+mod serializer;
 mod signature;
 mod state;
+mod template;
+mod transform;
 
 use axum::{
     routing::{get, post},
@@ -19,6 +28,7 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use crate::config::Config;
 use crate::controller::watch_handlers;
 use crate::kafka::KafkaProducer;
+use crate::serializer::SchemaRegistry;
 use crate::state::AppState;
 
 #[tokio::main]
@@ -36,37 +46,62 @@ async fn main() -> anyhow::Result<()> {
 
     // Load configuration
     let config = Config::from_env()?;
-    tracing::info!("Configuration loaded: namespace={}, external_url={}", 
-        config.namespace, config.external_url);
+    tracing::info!(
+        "Configuration loaded: namespace={}, external_url={}",
+        config.namespace,
+        config.external_url
+    );
 
     // Initialize Kafka producer
     let kafka_producer = KafkaProducer::new(&config)?;
     tracing::info!("Kafka producer initialized");
 
+    let schema_registry = match &config.schema_registry_url {
+        Some(url) => {
+            let credentials = config
+                .schema_registry_username
+                .clone()
+                .zip(config.schema_registry_password.clone());
+            tracing::info!("Schema Registry configured: {}", url);
+            Some(Arc::new(SchemaRegistry::new(url, credentials)?))
+        }
+        None => None,
+    };
+
     // Initialize shared state
-    let handlers = Arc::new(RwLock::new(std::collections::HashMap::new()));
     let state = AppState {
-        handlers: handlers.clone(),
+        handlers: Arc::new(RwLock::new(std::collections::HashMap::new())),
         kafka_producer: Arc::new(kafka_producer),
+        schema_registry,
         api_signing_key: config.api_signing_key.clone(),
         external_url: config.external_url.clone(),
         namespace: config.namespace.clone(),
     };
 
-    // Start controller to watch WebhookHandler CRDs
+    // Start controller to reconcile WebhookHandler CRDs
     let client = kube::Client::try_default().await?;
-    tokio::spawn(watch_handlers(
-        client.clone(),
-        config.namespace.clone(),
-        handlers.clone(),
-    ));
+    tokio::spawn(watch_handlers(client, state.clone()));
 
     // Build HTTP router
     let app = Router::new()
         .route("/health", get(handlers::health::health))
         .route("/ready", get(handlers::health::ready))
-        .route("/config", post(handlers::config::create_handler))
-        .route("/handler/:uuid", post(handlers::webhook::handle_webhook))
+        .route(
+            "/config",
+            post(handlers::config::create_handler).get(handlers::config::list_handlers),
+        )
+        .route(
+            "/config/:id",
+            get(handlers::config::get_handler)
+                .put(handlers::config::replace_handler)
+                .patch(handlers::config::patch_handler)
+                .delete(handlers::config::delete_handler),
+        )
+        .route("/config/:id/rotate", post(handlers::config::rotate_key))
+        .route(
+            "/handler/:uuid",
+            post(handlers::webhook::handle_webhook).get(handlers::webhook::handle_challenge),
+        )
         .layer(TraceLayer::new_for_http())
         .layer(Extension(state));
 
@@ -78,4 +113,4 @@ async fn main() -> anyhow::Result<()> {
     axum::serve(listener, app).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+
+use crate::cloudevents::CloudEvents;
+use crate::crd::{MessageFormat, WebhookHandlerSpec};
+use crate::schema::PayloadSchema;
+use crate::serializer::Serializer;
+use crate::transform::Transform;
+
+/// What a handler publishes for an accepted webhook: the schema the body is
+/// checked against, the transform that reshapes it and the record format and
+/// encoding. Compiled once when the handler is loaded, next to its `Plan`.
+#[derive(Debug, Default)]
+pub struct Output {
+    format: MessageFormat,
+    payload_schema: Option<PayloadSchema>,
+    transform: Option<Transform>,
+    cloud_events: Option<CloudEvents>,
+    serializer: Option<Arc<Serializer>>,
+}
+
+impl Output {
+    /// Compiles the handler's `messageFormat`, `cloudEvents`, `serializer`,
+    /// inline `payloadSchema` and `transform`
+    pub fn compile(spec: &WebhookHandlerSpec) -> Result<Output> {
+        let cloud_events = compile_cloud_events(spec)?;
+        let serializer = compile_serializer(spec)?;
+        let payload_schema = compile_payload_schema(spec)?;
+        let transform = spec
+            .transform
+            .as_ref()
+            .map(Transform::compile)
+            .transpose()?;
+
+        Ok(Output {
+            format: spec.message_format,
+            payload_schema,
+            transform,
+            cloud_events,
+            serializer,
+        })
+    }
+
+    /// Adds a `payloadSchema` loaded from a ConfigMap by the controller
+    pub fn with_resolved_payload_schema(mut self, payload_schema: Option<PayloadSchema>) -> Output {
+        if payload_schema.is_some() {
+            self.payload_schema = payload_schema;
+        }
+        self
+    }
+
+    pub fn format(&self) -> MessageFormat {
+        self.format
+    }
+
+    /// Checked against the body before filters run
+    pub fn payload_schema(&self) -> Option<&PayloadSchema> {
+        self.payload_schema.as_ref()
+    }
+
+    /// Reshapes the body before it is published, and before it is routed
+    /// with `routeOnOutput`
+    pub fn transform(&self) -> Option<&Transform> {
+        self.transform.as_ref()
+    }
+
+    /// The CloudEvents attributes, set with `messageFormat: cloudevents`
+    pub fn cloud_events(&self) -> Option<&CloudEvents> {
+        self.cloud_events.as_ref()
+    }
+
+    /// The Schema Registry encoding applied to published record values
+    pub fn serializer(&self) -> Option<&Arc<Serializer>> {
+        self.serializer.as_ref()
+    }
+}
+
+/// `cloudEvents` attributes only apply to `messageFormat: cloudevents`, which
+/// uses the defaults when they are left out
+fn compile_cloud_events(spec: &WebhookHandlerSpec) -> Result<Option<CloudEvents>> {
+    match (spec.message_format, &spec.cloud_events) {
+        (MessageFormat::CloudEvents, config) => Ok(Some(CloudEvents::compile(
+            &config.clone().unwrap_or_default(),
+        )?)),
+        (_, Some(_)) => Err(anyhow!("cloudEvents requires messageFormat: cloudevents")),
+        (_, None) => Ok(None),
+    }
+}
+
+/// A `serializer` encodes the envelope or raw body; CloudEvents records are
+/// already structured and cannot be encoded again
+fn compile_serializer(spec: &WebhookHandlerSpec) -> Result<Option<Arc<Serializer>>> {
+    match (&spec.serializer, spec.message_format) {
+        (Some(_), MessageFormat::CloudEvents) => Err(anyhow!(
+            "serializer cannot be combined with messageFormat: cloudevents"
+        )),
+        (Some(config), _)
+            if config.subject.is_none()
+                && spec
+                    .routes
+                    .iter()
+                    .flatten()
+                    .any(|route| route.topic_template.is_some()) =>
+        {
+            Err(anyhow!(
+                "serializer requires a subject when routes use topicTemplate"
+            ))
+        }
+        (config, _) => Ok(config
+            .as_ref()
+            .map(Serializer::compile)
+            .transpose()?
+            .map(Arc::new)),
+    }
+}
+
+/// Compiles an inline `payloadSchema`. A ConfigMap schema is loaded by the
+/// controller and added with `with_resolved_payload_schema`.
+fn compile_payload_schema(spec: &WebhookHandlerSpec) -> Result<Option<PayloadSchema>> {
+    let Some(config) = &spec.payload_schema else {
+        return Ok(None);
+    };
+    match (&config.schema, &config.config_map_ref) {
+        (Some(schema), None) => Ok(Some(PayloadSchema::compile(schema, config.on_invalid)?)),
+        (None, Some(_)) => Ok(None),
+        _ => Err(anyhow!(
+            "payloadSchema requires exactly one of schema and configMapRef"
+        )),
+    }
+}
@@ -0,0 +1,153 @@
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::{Api, Client};
+use serde_json::Value;
+
+use crate::crd::{ConfigMapKeyRef, InvalidPayloadPolicy};
+
+/// Violations listed in a rejection or dead-letter reason; the rest are counted
+const MAX_REPORTED_ERRORS: usize = 3;
+
+/// A handler's `payloadSchema`, compiled when it is loaded
+#[derive(Debug)]
+pub struct PayloadSchema {
+    validator: Box<jsonschema::Validator>,
+    on_invalid: InvalidPayloadPolicy,
+}
+
+/// Result of looking up a ConfigMap schema reference
+pub enum ResolvedSchema {
+    Found(Value),
+    /// The ConfigMap or key does not exist or is not JSON; the message is
+    /// surfaced on the handler's `SchemaResolved` condition
+    Missing(String),
+}
+
+impl PayloadSchema {
+    pub fn compile(schema: &Value, on_invalid: InvalidPayloadPolicy) -> Result<PayloadSchema> {
+        let validator = jsonschema::validator_for(schema)
+            .map_err(|e| anyhow!("Invalid payloadSchema: {}", e))?;
+        Ok(PayloadSchema {
+            validator: Box::new(validator),
+            on_invalid,
+        })
+    }
+
+    pub fn on_invalid(&self) -> InvalidPayloadPolicy {
+        self.on_invalid
+    }
+
+    /// Checks a parsed body, describing the first few violations if it does not match
+    pub fn validate(&self, body: &Value) -> Result<(), String> {
+        let errors: Vec<String> = self
+            .validator
+            .iter_errors(body)
+            .map(|error| {
+                let path = error.instance_path().to_string();
+                format!("{}: {}", if path.is_empty() { "/" } else { &path }, error)
+            })
+            .collect();
+        if errors.is_empty() {
+            return Ok(());
+        }
+
+        let mut message = format!(
+            "Payload does not match schema: {}",
+            errors[..errors.len().min(MAX_REPORTED_ERRORS)].join("; ")
+        );
+        if errors.len() > MAX_REPORTED_ERRORS {
+            message.push_str(&format!(
+                " (and {} more)",
+                errors.len() - MAX_REPORTED_ERRORS
+            ));
+        }
+        Err(message)
+    }
+}
+
+/// Reads a JSON Schema document from a ConfigMap key in the given namespace
+pub async fn resolve_config_map_ref(
+    client: Client,
+    namespace: &str,
+    config_map_ref: &ConfigMapKeyRef,
+) -> Result<ResolvedSchema, kube::Error> {
+    let api: Api<ConfigMap> = Api::namespaced(client, namespace);
+    let Some(config_map) = api.get_opt(&config_map_ref.name).await? else {
+        return Ok(ResolvedSchema::Missing(format!(
+            "ConfigMap {} not found",
+            config_map_ref.name
+        )));
+    };
+
+    let Some(document) = config_map
+        .data
+        .as_ref()
+        .and_then(|data| data.get(&config_map_ref.key))
+    else {
+        return Ok(ResolvedSchema::Missing(format!(
+            "ConfigMap {}: key {} not found",
+            config_map_ref.name, config_map_ref.key
+        )));
+    };
+    Ok(match serde_json::from_str(document) {
+        Ok(schema) => ResolvedSchema::Found(schema),
+        Err(e) => ResolvedSchema::Missing(format!(
+            "ConfigMap {}: key {} is not valid JSON: {}",
+            config_map_ref.name, config_map_ref.key, e
+        )),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_payload_schema() {
+        let schema = PayloadSchema::compile(
+            &json!({
+                "type": "object",
+                "required": ["event", "payload"],
+                "properties": {
+                    "event": { "type": "string" },
+                    "payload": {
+                        "type": "object",
+                        "properties": { "account_id": { "type": "string" } }
+                    }
+                }
+            }),
+            InvalidPayloadPolicy::Pass,
+        )
+        .unwrap();
+        assert_eq!(schema.on_invalid(), InvalidPayloadPolicy::Pass);
+
+        assert!(schema
+            .validate(&json!({ "event": "meeting.started", "payload": { "account_id": "abc" } }))
+            .is_ok());
+
+        let message = schema
+            .validate(&json!({ "event": 1, "payload": { "account_id": 2 } }))
+            .unwrap_err();
+        assert!(message.starts_with("Payload does not match schema: "));
+        assert!(message.contains("/event: 1 is not of type \"string\""));
+        assert!(message.contains("/payload/account_id: 2 is not of type \"string\""));
+
+        let message = schema.validate(&json!([])).unwrap_err();
+        assert!(message.contains("/: [] is not of type \"object\""));
+
+        let many = PayloadSchema::compile(
+            &json!({ "required": ["a", "b", "c", "d", "e"] }),
+            InvalidPayloadPolicy::Reject,
+        )
+        .unwrap();
+        assert!(many
+            .validate(&json!({}))
+            .unwrap_err()
+            .ends_with("(and 2 more)"));
+
+        assert!(
+            PayloadSchema::compile(&json!({ "type": 12 }), InvalidPayloadPolicy::Reject).is_err()
+        );
+    }
+}
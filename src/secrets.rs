@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use k8s_openapi::api::core::v1::Secret;
+use kube::{
+    api::{ObjectMeta, Patch, PatchParams, PostParams},
+    Api, Client, Resource,
+};
+use serde_json::json;
+use std::collections::BTreeMap;
+
+use crate::crd::{SecretKeyRef, WebhookHandler};
+
+/// Key under which `POST /config` stores a submitted signing key
+pub const SIGNATURE_KEY_FIELD: &str = "signature-key";
+
+/// Name of the Secret owned by a handler created through the config API
+pub fn signing_secret_name(handler_name: &str) -> String {
+    format!("{}-signing", handler_name)
+}
+
+/// Result of looking up a secret reference
+pub enum ResolvedSecret {
+    Found(String),
+    /// The Secret or key does not exist; the message is surfaced on the
+    /// handler's `SecretResolved` condition
+    Missing(String),
+}
+
+/// Reads the referenced key from a Secret in the given namespace
+pub async fn resolve_secret_ref(
+    client: Client,
+    namespace: &str,
+    secret_ref: &SecretKeyRef,
+) -> Result<ResolvedSecret, kube::Error> {
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    let Some(secret) = api.get_opt(&secret_ref.name).await? else {
+        return Ok(ResolvedSecret::Missing(format!(
+            "Secret {} not found",
+            secret_ref.name
+        )));
+    };
+
+    Ok(match secret_value(&secret, &secret_ref.key) {
+        Ok(value) => ResolvedSecret::Found(value),
+        Err(e) => ResolvedSecret::Missing(format!("Secret {}: {}", secret_ref.name, e)),
+    })
+}
+
+fn secret_value(secret: &Secret, key: &str) -> Result<String> {
+    let bytes = secret
+        .data
+        .as_ref()
+        .and_then(|data| data.get(key))
+        .map(|value| value.0.clone())
+        .or_else(|| {
+            secret
+                .string_data
+                .as_ref()
+                .and_then(|data| data.get(key))
+                .map(|value| value.clone().into_bytes())
+        })
+        .ok_or_else(|| anyhow!("key {} not found", key))?;
+
+    String::from_utf8(bytes).map_err(|_| anyhow!("key {} is not valid UTF-8", key))
+}
+
+/// Builds the Secret holding a handler's signing keys, owned by the handler
+/// so it is garbage collected when the handler is deleted
+pub fn owned_signing_secret(
+    handler: &WebhookHandler,
+    field: &str,
+    signature_key: &str,
+) -> Result<Secret> {
+    let handler_name = handler
+        .metadata
+        .name
+        .as_deref()
+        .ok_or_else(|| anyhow!("Handler has no name"))?;
+    let owner = handler
+        .controller_owner_ref(&())
+        .ok_or_else(|| anyhow!("Handler has no uid"))?;
+
+    Ok(Secret {
+        metadata: ObjectMeta {
+            name: Some(signing_secret_name(handler_name)),
+            namespace: handler.metadata.namespace.clone(),
+            owner_references: Some(vec![owner]),
+            ..Default::default()
+        },
+        string_data: Some(BTreeMap::from([(
+            field.to_string(),
+            signature_key.to_string(),
+        )])),
+        ..Default::default()
+    })
+}
+
+/// Stores a signing key under `field` in the handler's owned Secret,
+/// creating the Secret if it does not exist yet
+pub async fn store_signing_key(
+    client: Client,
+    handler: &WebhookHandler,
+    field: &str,
+    signature_key: &str,
+) -> Result<()> {
+    let secret = owned_signing_secret(handler, field, signature_key)?;
+    let namespace = secret.metadata.namespace.clone().unwrap_or_default();
+    let name = secret.metadata.name.clone().unwrap_or_default();
+    let api: Api<Secret> = Api::namespaced(client, &namespace);
+
+    if api.get_opt(&name).await?.is_some() {
+        let patch = json!({ "stringData": { field: signature_key } });
+        api.patch(&name, &PatchParams::default(), &Patch::Merge(patch))
+            .await?;
+    } else {
+        api.create(&PostParams::default(), &secret).await?;
+    }
+    Ok(())
+}
+
+/// Removes keys from a Secret; used to drop expired signing keys
+pub async fn remove_signing_keys(
+    client: Client,
+    namespace: &str,
+    name: &str,
+    fields: &[String],
+) -> Result<()> {
+    if fields.is_empty() {
+        return Ok(());
+    }
+    let data: serde_json::Map<String, serde_json::Value> = fields
+        .iter()
+        .map(|field| (field.clone(), serde_json::Value::Null))
+        .collect();
+    let api: Api<Secret> = Api::namespaced(client, namespace);
+    api.patch(
+        name,
+        &PatchParams::default(),
+        &Patch::Merge(json!({ "data": data })),
+    )
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::ByteString;
+
+    #[test]
+    fn test_secret_value() {
+        let secret = Secret {
+            data: Some(BTreeMap::from([(
+                "signature-key".to_string(),
+                ByteString(b"whsec_123".to_vec()),
+            )])),
+            ..Default::default()
+        };
+        assert_eq!(secret_value(&secret, "signature-key").unwrap(), "whsec_123");
+        assert!(secret_value(&secret, "other").is_err());
+    }
+
+    #[test]
+    fn test_owned_signing_secret() {
+        let mut handler: WebhookHandler = serde_json::from_value(serde_json::json!({
+            "apiVersion": "webhooks.example.com/v1",
+            "kind": "WebhookHandler",
+            "metadata": { "name": "handler-abc", "namespace": "default", "uid": "1234" },
+            "spec": { "topic": "events" }
+        }))
+        .unwrap();
+
+        let secret = owned_signing_secret(&handler, SIGNATURE_KEY_FIELD, "whsec_123").unwrap();
+        assert_eq!(secret.metadata.name.as_deref(), Some("handler-abc-signing"));
+        let owner = &secret.metadata.owner_references.unwrap()[0];
+        assert_eq!(owner.uid, "1234");
+        assert_eq!(owner.kind, "WebhookHandler");
+
+        handler.metadata.uid = None;
+        assert!(owned_signing_secret(&handler, SIGNATURE_KEY_FIELD, "whsec_123").is_err());
+    }
+}
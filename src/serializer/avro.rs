@@ -0,0 +1,681 @@
+use anyhow::{anyhow, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A parsed Avro schema. Named types (records, enums and fixed) are kept by
+/// full name, so recursive schemas can refer to themselves.
+#[derive(Debug)]
+pub struct Schema {
+    root: Node,
+    named: HashMap<String, Node>,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Null,
+    Boolean,
+    Int,
+    Long,
+    Float,
+    Double,
+    Bytes,
+    String,
+    Record { fields: Vec<Field> },
+    Enum { symbols: Vec<String> },
+    Array(Box<Node>),
+    Map(Box<Node>),
+    Union(Vec<Node>),
+    Fixed { size: usize },
+    Named(String),
+}
+
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    node: Node,
+    default: Option<Value>,
+}
+
+impl Schema {
+    pub fn parse(source: &str) -> Result<Schema> {
+        let json: Value = serde_json::from_str(source)
+            .map_err(|e| anyhow!("Avro schema is not valid JSON: {}", e))?;
+        let mut named = HashMap::new();
+        let root = parse_node(&json, None, &mut named)?;
+        let schema = Schema { root, named };
+        schema.check_references(&schema.root)?;
+        for node in schema.named.values() {
+            schema.check_references(node)?;
+        }
+        Ok(schema)
+    }
+
+    /// Appends the Avro binary encoding of a JSON value. Unions take the
+    /// first branch the value fits, or Avro's `{"type": value}` form.
+    pub fn encode(&self, value: &Value, out: &mut Vec<u8>) -> Result<()> {
+        self.encode_node(&self.root, value, "$", out)
+    }
+
+    fn resolve<'a>(&'a self, node: &'a Node) -> &'a Node {
+        match node {
+            Node::Named(name) => &self.named[name],
+            node => node,
+        }
+    }
+
+    fn check_references(&self, node: &Node) -> Result<()> {
+        match node {
+            Node::Named(name) if !self.named.contains_key(name) => {
+                Err(anyhow!("Unknown Avro type {:?}", name))
+            }
+            Node::Record { fields } => fields
+                .iter()
+                .try_for_each(|field| self.check_references(&field.node)),
+            Node::Array(items) | Node::Map(items) => self.check_references(items),
+            Node::Union(branches) => branches
+                .iter()
+                .try_for_each(|branch| self.check_references(branch)),
+            _ => Ok(()),
+        }
+    }
+
+    fn encode_node(&self, node: &Node, value: &Value, at: &str, out: &mut Vec<u8>) -> Result<()> {
+        let mismatch = || {
+            anyhow!(
+                "Value at {} does not match Avro type {}",
+                at,
+                self.type_name(node)
+            )
+        };
+        match (self.resolve(node), value) {
+            (Node::Null, Value::Null) => {}
+            (Node::Boolean, Value::Bool(b)) => out.push(*b as u8),
+            (Node::Int, Value::Number(n)) => {
+                let n = n
+                    .as_i64()
+                    .filter(|n| i32::try_from(*n).is_ok())
+                    .ok_or_else(mismatch)?;
+                write_long(n, out);
+            }
+            (Node::Long, Value::Number(n)) => write_long(n.as_i64().ok_or_else(mismatch)?, out),
+            (Node::Float, Value::Number(n)) => {
+                out.extend_from_slice(&(n.as_f64().ok_or_else(mismatch)? as f32).to_le_bytes())
+            }
+            (Node::Double, Value::Number(n)) => {
+                out.extend_from_slice(&n.as_f64().ok_or_else(mismatch)?.to_le_bytes())
+            }
+            (Node::String, Value::String(s)) => write_bytes(s.as_bytes(), out),
+            (Node::Bytes, Value::String(s)) => {
+                write_bytes(&json_bytes(s).ok_or_else(mismatch)?, out)
+            }
+            (Node::Fixed { size }, Value::String(s)) => {
+                let bytes = json_bytes(s)
+                    .filter(|bytes| bytes.len() == *size)
+                    .ok_or_else(mismatch)?;
+                out.extend_from_slice(&bytes);
+            }
+            (Node::Enum { symbols }, Value::String(s)) => {
+                let index = symbols
+                    .iter()
+                    .position(|symbol| symbol == s)
+                    .ok_or_else(mismatch)?;
+                write_long(index as i64, out);
+            }
+            (Node::Record { fields }, Value::Object(object)) => {
+                if let Some(key) = object
+                    .keys()
+                    .find(|key| !fields.iter().any(|field| field.name == **key))
+                {
+                    return Err(anyhow!(
+                        "Unknown field {}.{} for Avro record {}",
+                        at,
+                        key,
+                        self.type_name(node)
+                    ));
+                }
+                for field in fields {
+                    let at = format!("{}.{}", at, field.name);
+                    match object.get(&field.name).or(field.default.as_ref()) {
+                        Some(value) => self.encode_node(&field.node, value, &at, out)?,
+                        None => self
+                            .encode_node(&field.node, &Value::Null, &at, out)
+                            .map_err(|_| anyhow!("Missing field {}", at))?,
+                    }
+                }
+            }
+            (Node::Array(items), Value::Array(values)) => {
+                if !values.is_empty() {
+                    write_long(values.len() as i64, out);
+                    for (i, value) in values.iter().enumerate() {
+                        self.encode_node(items, value, &format!("{}[{}]", at, i), out)?;
+                    }
+                }
+                out.push(0);
+            }
+            (Node::Map(values_node), Value::Object(object)) => {
+                if !object.is_empty() {
+                    write_long(object.len() as i64, out);
+                    for (key, value) in object {
+                        write_bytes(key.as_bytes(), out);
+                        self.encode_node(values_node, value, &format!("{}.{}", at, key), out)?;
+                    }
+                }
+                out.push(0);
+            }
+            (Node::Union(branches), value) => {
+                let (index, branch, value) =
+                    self.union_branch(branches, value).ok_or_else(mismatch)?;
+                write_long(index as i64, out);
+                self.encode_node(branch, value, at, out)?;
+            }
+            _ => return Err(mismatch()),
+        }
+        Ok(())
+    }
+
+    fn union_branch<'a>(
+        &self,
+        branches: &'a [Node],
+        value: &'a Value,
+    ) -> Option<(usize, &'a Node, &'a Value)> {
+        // Avro's JSON encoding wraps non-null union values as {"<type>": value}
+        if let Some((name, inner)) = value
+            .as_object()
+            .filter(|o| o.len() == 1)
+            .and_then(|o| o.iter().next())
+        {
+            if let Some(index) = branches
+                .iter()
+                .position(|branch| self.type_name(branch) == *name)
+            {
+                return Some((index, &branches[index], inner));
+            }
+        }
+        branches
+            .iter()
+            .enumerate()
+            .find(|(_, branch)| self.fits(branch, value))
+            .map(|(index, branch)| (index, branch, value))
+    }
+
+    /// Whether a value could be encoded as the node, checked shallowly to pick a union branch
+    fn fits(&self, node: &Node, value: &Value) -> bool {
+        match (self.resolve(node), value) {
+            (Node::Null, Value::Null) | (Node::Boolean, Value::Bool(_)) => true,
+            (Node::Int, Value::Number(n)) => n.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+            (Node::Long, Value::Number(n)) => n.is_i64(),
+            (Node::Float | Node::Double, Value::Number(_)) => true,
+            (Node::String | Node::Bytes, Value::String(_)) => true,
+            (Node::Enum { symbols }, Value::String(s)) => symbols.contains(s),
+            (Node::Fixed { size }, Value::String(s)) => {
+                json_bytes(s).is_some_and(|bytes| bytes.len() == *size)
+            }
+            (Node::Array(_), Value::Array(_)) | (Node::Map(_), Value::Object(_)) => true,
+            (Node::Record { fields }, Value::Object(object)) => object
+                .keys()
+                .all(|key| fields.iter().any(|field| field.name == *key)),
+            _ => false,
+        }
+    }
+
+    fn type_name(&self, node: &Node) -> String {
+        match node {
+            Node::Null => "null".to_string(),
+            Node::Boolean => "boolean".to_string(),
+            Node::Int => "int".to_string(),
+            Node::Long => "long".to_string(),
+            Node::Float => "float".to_string(),
+            Node::Double => "double".to_string(),
+            Node::Bytes => "bytes".to_string(),
+            Node::String => "string".to_string(),
+            Node::Array(_) => "array".to_string(),
+            Node::Map(_) => "map".to_string(),
+            Node::Union(_) => "union".to_string(),
+            Node::Named(name) => name.clone(),
+            Node::Record { .. } | Node::Enum { .. } | Node::Fixed { .. } => {
+                "named type".to_string()
+            }
+        }
+    }
+}
+
+fn parse_node(
+    json: &Value,
+    namespace: Option<&str>,
+    named: &mut HashMap<String, Node>,
+) -> Result<Node> {
+    match json {
+        Value::String(name) => {
+            Ok(primitive(name).unwrap_or_else(|| Node::Named(full_name(name, namespace))))
+        }
+        Value::Array(branches) => Ok(Node::Union(
+            branches
+                .iter()
+                .map(|branch| parse_node(branch, namespace, named))
+                .collect::<Result<_>>()?,
+        )),
+        Value::Object(object) => parse_complex(object, namespace, named),
+        _ => Err(anyhow!("Invalid Avro schema: {}", json)),
+    }
+}
+
+fn parse_complex(
+    object: &Map<String, Value>,
+    namespace: Option<&str>,
+    named: &mut HashMap<String, Node>,
+) -> Result<Node> {
+    let kind = object
+        .get("type")
+        .ok_or_else(|| anyhow!("Avro schema object has no type"))?;
+    let Some(kind) = kind.as_str() else {
+        // e.g. {"type": {"type": "array", ...}}
+        return parse_node(kind, namespace, named);
+    };
+
+    let name = || -> Result<(String, Option<String>)> {
+        let name = object
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("Avro {} has no name", kind))?;
+        let namespace = object
+            .get("namespace")
+            .and_then(Value::as_str)
+            .or(namespace);
+        let full_name = full_name(name, namespace);
+        let namespace = full_name
+            .rsplit_once('.')
+            .map(|(namespace, _)| namespace.to_string());
+        Ok((full_name, namespace))
+    };
+
+    let node = match kind {
+        "record" | "error" => {
+            let (full_name, namespace) = name()?;
+            // Registered first, so fields can refer to the record itself
+            named.insert(full_name.clone(), Node::Record { fields: Vec::new() });
+            let fields = object
+                .get("fields")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("Avro record {} has no fields", full_name))?
+                .iter()
+                .map(|field| {
+                    Ok(Field {
+                        name: field
+                            .get("name")
+                            .and_then(Value::as_str)
+                            .ok_or_else(|| {
+                                anyhow!("Field of Avro record {} has no name", full_name)
+                            })?
+                            .to_string(),
+                        node: parse_node(
+                            field.get("type").ok_or_else(|| {
+                                anyhow!("Field of Avro record {} has no type", full_name)
+                            })?,
+                            namespace.as_deref(),
+                            named,
+                        )?,
+                        default: field.get("default").cloned(),
+                    })
+                })
+                .collect::<Result<_>>()?;
+            named.insert(full_name.clone(), Node::Record { fields });
+            Node::Named(full_name)
+        }
+        "enum" => {
+            let (full_name, _) = name()?;
+            let symbols = object
+                .get("symbols")
+                .and_then(Value::as_array)
+                .ok_or_else(|| anyhow!("Avro enum {} has no symbols", full_name))?
+                .iter()
+                .map(|symbol| symbol.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or_else(|| {
+                    anyhow!("Avro enum {} has a symbol that is not a string", full_name)
+                })?;
+            named.insert(full_name.clone(), Node::Enum { symbols });
+            Node::Named(full_name)
+        }
+        "fixed" => {
+            let (full_name, _) = name()?;
+            let size = object
+                .get("size")
+                .and_then(Value::as_u64)
+                .ok_or_else(|| anyhow!("Avro fixed {} has no size", full_name))?;
+            named.insert(
+                full_name.clone(),
+                Node::Fixed {
+                    size: size as usize,
+                },
+            );
+            Node::Named(full_name)
+        }
+        "array" => Node::Array(Box::new(parse_node(
+            object
+                .get("items")
+                .ok_or_else(|| anyhow!("Avro array has no items"))?,
+            namespace,
+            named,
+        )?)),
+        "map" => Node::Map(Box::new(parse_node(
+            object
+                .get("values")
+                .ok_or_else(|| anyhow!("Avro map has no values"))?,
+            namespace,
+            named,
+        )?)),
+        // Primitives, possibly with a logicalType, are encoded as the underlying type
+        name => primitive(name).ok_or_else(|| anyhow!("Unknown Avro type {:?}", name))?,
+    };
+    Ok(node)
+}
+
+fn primitive(name: &str) -> Option<Node> {
+    Some(match name {
+        "null" => Node::Null,
+        "boolean" => Node::Boolean,
+        "int" => Node::Int,
+        "long" => Node::Long,
+        "float" => Node::Float,
+        "double" => Node::Double,
+        "bytes" => Node::Bytes,
+        "string" => Node::String,
+        _ => return None,
+    })
+}
+
+fn full_name(name: &str, namespace: Option<&str>) -> String {
+    match namespace {
+        Some(namespace) if !name.contains('.') && !namespace.is_empty() => {
+            format!("{}.{}", namespace, name)
+        }
+        _ => name.to_string(),
+    }
+}
+
+/// Avro's JSON form of bytes: each code point 0-255 is one byte
+fn json_bytes(s: &str) -> Option<Vec<u8>> {
+    s.chars().map(|c| u8::try_from(u32::from(c)).ok()).collect()
+}
+
+/// Zig-zag variable-length encoding of ints and longs
+fn write_long(n: i64, out: &mut Vec<u8>) {
+    let mut n = ((n << 1) ^ (n >> 63)) as u64;
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    write_long(bytes.len() as i64, out);
+    out.extend_from_slice(bytes);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn encode(schema: &Schema, value: Value) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        schema.encode(&value, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_avro_encode() {
+        let schema = Schema::parse(
+            &json!({
+                "type": "record",
+                "name": "Payment",
+                "namespace": "com.example",
+                "fields": [
+                    { "name": "id", "type": "string" },
+                    { "name": "amount", "type": "long" },
+                    { "name": "status", "type": { "type": "enum", "name": "Status", "symbols": ["OK", "FAILED"] } },
+                    { "name": "note", "type": ["null", "string"], "default": null },
+                    { "name": "tags", "type": { "type": "array", "items": "string" } },
+                    { "name": "next", "type": ["null", "Payment"], "default": null }
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+
+        let encoded = encode(
+            &schema,
+            json!({ "id": "p1", "amount": -3, "status": "FAILED", "tags": ["a"] }),
+        )
+        .unwrap();
+        assert_eq!(encoded, vec![4, b'p', b'1', 5, 2, 0, 2, 2, b'a', 0, 0]);
+
+        let nested = json!({
+            "id": "p1", "amount": 64, "status": "OK", "note": { "string": "hi" }, "tags": [],
+            "next": { "id": "p2", "amount": 0, "status": "OK", "tags": [] }
+        });
+        assert_eq!(
+            encode(&schema, nested).unwrap(),
+            vec![
+                4, b'p', b'1', 0x80, 0x01, 0, 2, 4, b'h', b'i', 0, 2, 4, b'p', b'2', 0, 0, 0, 0, 0
+            ]
+        );
+
+        let err = encode(
+            &schema,
+            json!({ "id": "p1", "amount": 1, "status": "LOST", "tags": [] }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Value at $.status does not match Avro type com.example.Status"
+        );
+        let err = encode(&schema, json!({ "amount": 1 })).unwrap_err();
+        assert_eq!(err.to_string(), "Missing field $.id");
+        let err = encode(
+            &schema,
+            json!({ "id": "p1", "amount": 1, "status": "OK", "tags": [], "amout": 2 }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown field $.amout for Avro record com.example.Payment"
+        );
+        let err = encode(
+            &schema,
+            json!({
+                "id": "p1", "amount": 1, "status": "OK", "tags": [],
+                "next": { "com.example.Payment": { "id": "p2", "amount": 0, "status": "OK", "tags": [], "extra": true } }
+            }),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown field $.next.extra for Avro record com.example.Payment"
+        );
+
+        assert!(Schema::parse("{").is_err());
+        assert!(Schema::parse(
+            r#"{"type": "record", "name": "R", "fields": [{"name": "a", "type": "Nope"}]}"#
+        )
+        .is_err());
+    }
+
+    /// Decodes a value back to Avro's JSON encoding, with non-null union
+    /// values wrapped as `{"<type>": value}`, to check encoding by round trip
+    fn decode(schema: &Schema, node: &Node, bytes: &mut &[u8]) -> Value {
+        match schema.resolve(node) {
+            Node::Null => Value::Null,
+            Node::Boolean => json!(take(bytes, 1)[0] != 0),
+            Node::Int => json!(read_long(bytes) as i32),
+            Node::Long => json!(read_long(bytes)),
+            Node::Float => json!(f32::from_le_bytes(take(bytes, 4).try_into().unwrap())),
+            Node::Double => json!(f64::from_le_bytes(take(bytes, 8).try_into().unwrap())),
+            Node::String => {
+                let len = read_long(bytes) as usize;
+                json!(std::str::from_utf8(take(bytes, len)).unwrap())
+            }
+            Node::Bytes => {
+                let len = read_long(bytes) as usize;
+                json!(take(bytes, len)
+                    .iter()
+                    .map(|b| char::from(*b))
+                    .collect::<String>())
+            }
+            Node::Fixed { size } => json!(take(bytes, *size)
+                .iter()
+                .map(|b| char::from(*b))
+                .collect::<String>()),
+            Node::Enum { symbols } => json!(symbols[read_long(bytes) as usize]),
+            Node::Record { fields } => Value::Object(
+                fields
+                    .iter()
+                    .map(|field| (field.name.clone(), decode(schema, &field.node, bytes)))
+                    .collect(),
+            ),
+            Node::Array(items) => {
+                let mut values = Vec::new();
+                while let count @ 1.. = read_long(bytes) {
+                    for _ in 0..count {
+                        values.push(decode(schema, items, bytes));
+                    }
+                }
+                Value::Array(values)
+            }
+            Node::Map(values_node) => {
+                let mut object = Map::new();
+                while let count @ 1.. = read_long(bytes) {
+                    for _ in 0..count {
+                        let key = decode(schema, &Node::String, bytes)
+                            .as_str()
+                            .unwrap()
+                            .to_string();
+                        object.insert(key, decode(schema, values_node, bytes));
+                    }
+                }
+                Value::Object(object)
+            }
+            Node::Union(branches) => match &branches[read_long(bytes) as usize] {
+                Node::Null => Value::Null,
+                branch => json!({ schema.type_name(branch): decode(schema, branch, bytes) }),
+            },
+            Node::Named(_) => unreachable!(),
+        }
+    }
+
+    fn read_long(bytes: &mut &[u8]) -> i64 {
+        let mut n = 0u64;
+        for shift in (0..).step_by(7) {
+            let byte = take(bytes, 1)[0];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        (n >> 1) as i64 ^ -((n & 1) as i64)
+    }
+
+    fn take<'a>(bytes: &mut &'a [u8], len: usize) -> &'a [u8] {
+        let (value, rest) = bytes.split_at(len);
+        *bytes = rest;
+        value
+    }
+
+    #[test]
+    fn test_avro_round_trip() {
+        let schema = Schema::parse(
+            &json!({
+                "type": "record",
+                "name": "Event",
+                "namespace": "test",
+                "fields": [
+                    { "name": "small", "type": "int" },
+                    { "name": "big", "type": "long" },
+                    { "name": "scale", "type": "float" },
+                    { "name": "ratio", "type": "double" },
+                    { "name": "flag", "type": "boolean" },
+                    { "name": "raw", "type": "bytes" },
+                    { "name": "hash", "type": { "type": "fixed", "name": "Hash", "size": 2 } },
+                    { "name": "level", "type": { "type": "enum", "name": "Level", "symbols": ["LOW", "HIGH"] } },
+                    {
+                        "name": "tree",
+                        "type": {
+                            "type": "record",
+                            "name": "Node",
+                            "fields": [
+                                { "name": "name", "type": "string" },
+                                { "name": "children", "type": { "type": "array", "items": "Node" } }
+                            ]
+                        }
+                    },
+                    { "name": "scores", "type": { "type": "map", "values": "long" } },
+                    { "name": "labels", "type": { "type": "array", "items": "string" } },
+                    { "name": "choice", "type": ["null", "long", "string", "Level", "Node"], "default": null }
+                ]
+            })
+            .to_string(),
+        )
+        .unwrap();
+        let round_trip = |value: Value, expected: Value| {
+            let encoded = encode(&schema, value).unwrap();
+            let mut bytes = encoded.as_slice();
+            assert_eq!(decode(&schema, &schema.root, &mut bytes), expected);
+            assert!(bytes.is_empty());
+        };
+        let event = |choice: Value| {
+            json!({
+                "small": -2147483648,
+                "big": i64::MIN,
+                "scale": 1.5,
+                "ratio": -0.25,
+                "flag": true,
+                "raw": "\u{0}\u{ff}",
+                "hash": "ab",
+                "level": "HIGH",
+                "tree": {
+                    "name": "root",
+                    "children": [
+                        { "name": "a", "children": [{ "name": "a1", "children": [] }] },
+                        { "name": "b", "children": [] }
+                    ]
+                },
+                "scores": { "x": -1, "y": 300 },
+                "labels": ["", "z"],
+                "choice": choice
+            })
+        };
+
+        // Union values in Avro's wrapped form, including named types
+        for choice in [
+            json!(null),
+            json!({ "long": -5 }),
+            json!({ "string": "x" }),
+            json!({ "test.Level": "LOW" }),
+            json!({ "test.Node": { "name": "n", "children": [] } }),
+        ] {
+            round_trip(event(choice.clone()), event(choice));
+        }
+
+        // Bare union values take the first branch they fit
+        round_trip(event(json!(-5)), event(json!({ "long": -5 })));
+        round_trip(event(json!("HIGH")), event(json!({ "string": "HIGH" })));
+        round_trip(
+            event(json!({ "name": "n", "children": [] })),
+            event(json!({ "test.Node": { "name": "n", "children": [] } })),
+        );
+
+        // A left-out field with a default uses it
+        let mut without_choice = event(json!(null));
+        without_choice.as_object_mut().unwrap().remove("choice");
+        round_trip(without_choice, event(json!(null)));
+
+        assert!(encode(&schema, event(json!({ "test.Hash": "ab" }))).is_err());
+        let mut wrong = event(json!(null));
+        wrong["small"] = json!(2147483648u32);
+        assert!(encode(&schema, wrong.clone()).is_err());
+        wrong["small"] = json!(1);
+        wrong["hash"] = json!("abc");
+        assert!(encode(&schema, wrong).is_err());
+    }
+}
@@ -0,0 +1,379 @@
+mod avro;
+mod protobuf;
+mod registry;
+
+pub use registry::SchemaRegistry;
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::crd::{SchemaFormat, SerializerConfig};
+
+/// First byte of the Confluent wire format, before the 4-byte schema ID
+const MAGIC_BYTE: u8 = 0;
+
+/// A handler's `serializer`, compiled when it is loaded. Schema IDs are
+/// resolved against the registry on first use for each subject and cached
+/// until the handler is reloaded. Subjects come from the configured subject
+/// or the handler's fixed topics, so the cache stays bounded.
+#[derive(Debug)]
+pub struct Serializer {
+    format: SchemaFormat,
+    subject: Option<String>,
+    /// Protobuf message to encode, instead of the schema's first
+    message: Option<String>,
+    /// The configured schema and its compiled encoder
+    schema: Option<(String, Arc<Encoder>)>,
+    resolved: RwLock<HashMap<String, (u32, Arc<Encoder>)>>,
+    /// Held while a subject is resolved, so concurrent first records register
+    /// or fetch its schema once
+    resolving: tokio::sync::Mutex<()>,
+}
+
+/// A schema compiled to encode JSON values
+#[derive(Debug)]
+enum Encoder {
+    Avro(avro::Schema),
+    Protobuf(protobuf::Schema),
+    JsonSchema(Box<jsonschema::Validator>),
+}
+
+/// Why a record value could not be encoded
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The registry could not be reached or refused the request
+    Registry(anyhow::Error),
+    /// The value does not match the schema
+    Value(anyhow::Error),
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::Registry(e) => write!(f, "{:#}", e),
+            EncodeError::Value(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Serializer {
+    pub fn compile(config: &SerializerConfig) -> Result<Serializer> {
+        if config.subject.as_deref() == Some("") {
+            return Err(anyhow!("serializer subject must not be empty"));
+        }
+        if config.message.is_some() && config.format != SchemaFormat::Protobuf {
+            return Err(anyhow!("serializer message requires format: protobuf"));
+        }
+        let message = config.message.as_deref();
+        let schema = match &config.schema {
+            Some(schema) => {
+                let encoder = Encoder::compile(config.format, schema, message)
+                    .context("Invalid serializer schema")?;
+                Some((schema.clone(), Arc::new(encoder)))
+            }
+            None => None,
+        };
+
+        Ok(Serializer {
+            format: config.format,
+            subject: config.subject.clone(),
+            message: config.message.clone(),
+            schema,
+            resolved: RwLock::new(HashMap::new()),
+            resolving: tokio::sync::Mutex::new(()),
+        })
+    }
+
+    /// Encodes a JSON record value for a topic as magic byte, big-endian
+    /// schema ID and the encoded value
+    pub async fn encode(
+        &self,
+        registry: &SchemaRegistry,
+        topic: &str,
+        value: &[u8],
+    ) -> Result<Vec<u8>, EncodeError> {
+        let value: Value = serde_json::from_slice(value)
+            .map_err(|e| EncodeError::Value(anyhow!("Record value is not JSON: {}", e)))?;
+        let (id, encoder) = self
+            .resolve(registry, topic)
+            .await
+            .map_err(EncodeError::Registry)?;
+
+        let mut out = vec![MAGIC_BYTE];
+        out.extend_from_slice(&id.to_be_bytes());
+        encoder
+            .encode(&value, &mut out)
+            .map_err(EncodeError::Value)?;
+        Ok(out)
+    }
+
+    /// The schema ID and encoder for a topic's subject: the configured schema,
+    /// registered under the subject, or else the subject's latest version
+    async fn resolve(&self, registry: &SchemaRegistry, topic: &str) -> Result<(u32, Arc<Encoder>)> {
+        let subject = self
+            .subject
+            .clone()
+            .unwrap_or_else(|| format!("{}-value", topic));
+        if let Some(resolved) = self.cached(&subject) {
+            return Ok(resolved);
+        }
+        let _resolving = self.resolving.lock().await;
+        if let Some(resolved) = self.cached(&subject) {
+            return Ok(resolved);
+        }
+
+        let resolved = match &self.schema {
+            Some((schema, encoder)) => (
+                registry.register(&subject, self.format, schema).await?,
+                encoder.clone(),
+            ),
+            None => {
+                let latest = registry.latest(&subject, self.format).await?;
+                let encoder =
+                    Encoder::compile(self.format, &latest.schema, self.message.as_deref())
+                        .with_context(|| {
+                            format!("Invalid schema {} for subject {}", latest.id, subject)
+                        })?;
+                (latest.id, Arc::new(encoder))
+            }
+        };
+        self.resolved
+            .write()
+            .unwrap()
+            .insert(subject, resolved.clone());
+        Ok(resolved)
+    }
+
+    fn cached(&self, subject: &str) -> Option<(u32, Arc<Encoder>)> {
+        self.resolved.read().unwrap().get(subject).cloned()
+    }
+}
+
+impl Encoder {
+    fn compile(format: SchemaFormat, schema: &str, message: Option<&str>) -> Result<Encoder> {
+        Ok(match format {
+            SchemaFormat::Avro => Encoder::Avro(avro::Schema::parse(schema)?),
+            SchemaFormat::Protobuf => {
+                let mut schema = protobuf::Schema::parse(schema)?;
+                if let Some(message) = message {
+                    schema.select(message)?;
+                }
+                Encoder::Protobuf(schema)
+            }
+            SchemaFormat::JsonSchema => {
+                let schema: Value =
+                    serde_json::from_str(schema).context("JSON Schema is not valid JSON")?;
+                let validator = jsonschema::validator_for(&schema).map_err(|e| anyhow!("{}", e))?;
+                Encoder::JsonSchema(Box::new(validator))
+            }
+        })
+    }
+
+    fn encode(&self, value: &Value, out: &mut Vec<u8>) -> Result<()> {
+        match self {
+            Encoder::Avro(schema) => schema.encode(value, out),
+            Encoder::Protobuf(schema) => {
+                schema.write_message_indexes(out);
+                schema.encode(value, out)
+            }
+            Encoder::JsonSchema(validator) => {
+                if let Some(error) = validator.iter_errors(value).next() {
+                    return Err(anyhow!(
+                        "Value at {} does not match JSON Schema: {}",
+                        error.instance_path(),
+                        error
+                    ));
+                }
+                serde_json::to_writer(out, value)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::Matcher;
+    use serde_json::json;
+
+    const AVRO: &str =
+        r#"{"type": "record", "name": "Event", "fields": [{"name": "id", "type": "long"}]}"#;
+
+    fn serializer(config: Value) -> Serializer {
+        Serializer::compile(&serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_serializer_with_registry() {
+        let mut server = mockito::Server::new_async().await;
+        let registry = SchemaRegistry::new(
+            &server.url(),
+            Some(("user".to_string(), "secret".to_string())),
+        )
+        .unwrap();
+
+        // An inline schema is registered once under the topic's subject
+        let register = server
+            .mock("POST", "/subjects/orders-value/versions")
+            .match_header("authorization", "Basic dXNlcjpzZWNyZXQ=")
+            .match_body(Matcher::Json(json!({ "schema": AVRO })))
+            .with_body(r#"{"id": 7}"#)
+            .expect(1)
+            .create_async()
+            .await;
+        let avro = serializer(json!({ "format": "avro", "schema": AVRO }));
+        for _ in 0..2 {
+            assert_eq!(
+                avro.encode(&registry, "orders", br#"{"id": 1}"#)
+                    .await
+                    .unwrap(),
+                vec![0, 0, 0, 0, 7, 2]
+            );
+        }
+        register.assert_async().await;
+        assert!(matches!(
+            avro.encode(&registry, "orders", br#"{"id": "one"}"#).await,
+            Err(EncodeError::Value(_))
+        ));
+
+        // Without a schema, the subject's latest version is used
+        server
+            .mock("GET", "/subjects/shared%2Fevents/versions/latest")
+            .with_body(
+                json!({
+                    "subject": "shared/events",
+                    "version": 3,
+                    "id": 300,
+                    "schemaType": "JSON",
+                    "schema": r#"{"type": "object", "required": ["id"]}"#
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let json_schema =
+            serializer(json!({ "format": "json_schema", "subject": "shared/events" }));
+        assert_eq!(
+            json_schema
+                .encode(&registry, "orders", br#"{"id":1}"#)
+                .await
+                .unwrap(),
+            [&[0, 0, 0, 1, 44][..], br#"{"id":1}"#].concat()
+        );
+        let error = json_schema
+            .encode(&registry, "orders", b"{}")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EncodeError::Value(_)));
+        assert!(error.to_string().contains("does not match JSON Schema"));
+
+        // A latest schema of another type is refused
+        let protobuf = serializer(json!({ "format": "protobuf", "subject": "shared/events" }));
+        let error = protobuf
+            .encode(&registry, "orders", b"{}")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EncodeError::Registry(_)));
+        assert!(error
+            .to_string()
+            .contains("holds a JSON schema, not PROTOBUF"));
+
+        // Registry errors carry its message
+        server
+            .mock("POST", "/subjects/payments-value/versions")
+            .match_body(Matcher::PartialJson(json!({ "schemaType": "PROTOBUF" })))
+            .with_status(409)
+            .with_body(
+                r#"{"error_code": 409, "message": "Schema being registered is incompatible"}"#,
+            )
+            .create_async()
+            .await;
+        let protobuf = serializer(
+            json!({ "format": "protobuf", "schema": "message Payment { int64 id = 1; }" }),
+        );
+        let error = protobuf
+            .encode(&registry, "payments", b"{}")
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EncodeError::Registry(_)));
+        assert!(error
+            .to_string()
+            .contains("409: Schema being registered is incompatible"));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_first_records_resolve_once() {
+        let mut server = mockito::Server::new_async().await;
+        let registry = SchemaRegistry::new(&server.url(), None).unwrap();
+        let register = server
+            .mock("POST", "/subjects/orders-value/versions")
+            .with_body(r#"{"id": 7}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let avro = serializer(json!({ "format": "avro", "schema": AVRO }));
+        let value = br#"{"id": 1}"#;
+        let (first, second) = tokio::join!(
+            avro.encode(&registry, "orders", value),
+            avro.encode(&registry, "orders", value)
+        );
+        assert_eq!(first.unwrap(), vec![0, 0, 0, 0, 7, 2]);
+        assert_eq!(second.unwrap(), vec![0, 0, 0, 0, 7, 2]);
+        register.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_protobuf_wire_format() {
+        let mut server = mockito::Server::new_async().await;
+        let registry = SchemaRegistry::new(&format!("{}/registry/", server.url()), None).unwrap();
+        server
+            .mock("POST", "/registry/subjects/payments-value/versions")
+            .with_body(r#"{"id": 1}"#)
+            .create_async()
+            .await;
+
+        let protobuf = serializer(
+            json!({ "format": "protobuf", "schema": "message Payment { int64 id = 1; }" }),
+        );
+        assert_eq!(
+            protobuf
+                .encode(&registry, "payments", br#"{"id": "150"}"#)
+                .await
+                .unwrap(),
+            vec![0, 0, 0, 0, 1, 0, 0x08, 0x96, 0x01]
+        );
+        assert!(matches!(
+            protobuf.encode(&registry, "payments", b"not json").await,
+            Err(EncodeError::Value(_))
+        ));
+
+        // A selected message is written with its message indexes
+        let protobuf = serializer(json!({
+            "format": "protobuf",
+            "schema": "message Refund { int64 id = 1; } message Payment { int64 id = 1; }",
+            "message": "Payment"
+        }));
+        assert_eq!(
+            protobuf
+                .encode(&registry, "payments", br#"{"id": "150"}"#)
+                .await
+                .unwrap(),
+            vec![0, 0, 0, 0, 1, 2, 2, 0x08, 0x96, 0x01]
+        );
+
+        let compile = |config: Value| Serializer::compile(&serde_json::from_value(config).unwrap());
+        assert!(compile(json!({ "format": "json_schema", "schema": "{\"type\": 12}" })).is_err());
+        assert!(compile(json!({ "format": "avro", "schema": AVRO, "message": "Event" })).is_err());
+        assert!(compile(json!({
+            "format": "protobuf",
+            "schema": "message Payment { int64 id = 1; }",
+            "message": "Refund"
+        }))
+        .is_err());
+    }
+}
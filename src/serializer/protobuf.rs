@@ -0,0 +1,1237 @@
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// The messages and enums of a `.proto` file, enough to encode JSON in the
+/// proto3 JSON mapping. Supports messages, nested types, enums, `repeated`,
+/// `map<K, V>` and `oneof`; imports, extensions and groups are not supported.
+#[derive(Debug)]
+pub struct Schema {
+    /// Full name of the message to encode; the first in the file, as with
+    /// Confluent serializers, unless another one is selected
+    root: String,
+    messages: HashMap<String, Message>,
+    enums: HashMap<String, Vec<(String, i32)>>,
+    /// Confluent message indexes: the position of each message among the
+    /// top-level messages, then among the nested messages of each parent
+    indexes: HashMap<String, Vec<i64>>,
+}
+
+#[derive(Debug, Default)]
+struct Message {
+    fields: Vec<Field>,
+}
+
+#[derive(Debug)]
+struct Field {
+    name: String,
+    json_name: String,
+    number: u32,
+    kind: Kind,
+    repeated: bool,
+}
+
+#[derive(Debug, Clone)]
+enum Kind {
+    Scalar(Scalar),
+    Message(String),
+    Enum(String),
+    Map(Scalar, Box<Kind>),
+    /// A type name not yet resolved against the file's messages and enums
+    Unresolved {
+        name: String,
+        scope: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scalar {
+    Double,
+    Float,
+    Int32,
+    Int64,
+    Uint32,
+    Uint64,
+    Sint32,
+    Sint64,
+    Fixed32,
+    Fixed64,
+    Sfixed32,
+    Sfixed64,
+    Bool,
+    String,
+    Bytes,
+}
+
+const VARINT: u32 = 0;
+const FIXED64: u32 = 1;
+const LEN: u32 = 2;
+const FIXED32: u32 = 5;
+
+impl Schema {
+    pub fn parse(source: &str) -> Result<Schema> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            schema: Schema {
+                root: String::new(),
+                messages: HashMap::new(),
+                enums: HashMap::new(),
+                indexes: HashMap::new(),
+            },
+            first_message: None,
+        };
+        parser.parse_file()?;
+        let mut schema = parser.schema;
+        schema.root = parser
+            .first_message
+            .ok_or_else(|| anyhow!("Protobuf schema has no message"))?;
+        schema.resolve()?;
+        Ok(schema)
+    }
+
+    /// Encodes the given message instead of the first one, by full name
+    /// (e.g. `shop.Order`)
+    pub fn select(&mut self, message: &str) -> Result<()> {
+        let message = message.strip_prefix('.').unwrap_or(message);
+        if !self.messages.contains_key(message) {
+            return Err(anyhow!("Protobuf schema has no message {:?}", message));
+        }
+        self.root = message.to_string();
+        Ok(())
+    }
+
+    /// Appends the Confluent message indexes of the encoded message: their
+    /// count and the indexes as zig-zag varints, or a single 0 for the first
+    /// message in the file
+    pub fn write_message_indexes(&self, out: &mut Vec<u8>) {
+        let indexes = &self.indexes[&self.root];
+        if indexes.as_slice() == [0] {
+            out.push(0);
+            return;
+        }
+        write_varint(zigzag(indexes.len() as i64), out);
+        for index in indexes {
+            write_varint(zigzag(*index), out);
+        }
+    }
+
+    /// Appends the binary encoding of a JSON object as the selected message
+    pub fn encode(&self, value: &Value, out: &mut Vec<u8>) -> Result<()> {
+        self.encode_message(&self.root, value, "$", out)
+    }
+
+    fn resolve(&mut self) -> Result<()> {
+        let names: Vec<String> = self.messages.keys().cloned().collect();
+        for name in names {
+            let mut fields = std::mem::take(&mut self.messages.get_mut(&name).unwrap().fields);
+            for field in &mut fields {
+                field.kind = self.resolve_kind(&field.kind)?;
+            }
+            self.messages.get_mut(&name).unwrap().fields = fields;
+        }
+        Ok(())
+    }
+
+    fn resolve_kind(&self, kind: &Kind) -> Result<Kind> {
+        match kind {
+            Kind::Unresolved { name, scope } => {
+                // Look in the enclosing scopes from the innermost out
+                let candidates: Vec<String> = match name.strip_prefix('.') {
+                    Some(absolute) => vec![absolute.to_string()],
+                    None => {
+                        let mut scope = scope.as_str();
+                        let mut candidates = Vec::new();
+                        loop {
+                            candidates.push(if scope.is_empty() {
+                                name.clone()
+                            } else {
+                                format!("{}.{}", scope, name)
+                            });
+                            if scope.is_empty() {
+                                break;
+                            }
+                            scope = scope.rsplit_once('.').map_or("", |(outer, _)| outer);
+                        }
+                        candidates
+                    }
+                };
+                candidates
+                    .into_iter()
+                    .find_map(|candidate| {
+                        if self.messages.contains_key(&candidate) {
+                            Some(Kind::Message(candidate))
+                        } else if self.enums.contains_key(&candidate) {
+                            Some(Kind::Enum(candidate))
+                        } else {
+                            None
+                        }
+                    })
+                    .ok_or_else(|| anyhow!("Unknown protobuf type {:?}", name))
+            }
+            Kind::Map(key, value) => Ok(Kind::Map(*key, Box::new(self.resolve_kind(value)?))),
+            kind => Ok(kind.clone()),
+        }
+    }
+
+    fn encode_message(&self, name: &str, value: &Value, at: &str, out: &mut Vec<u8>) -> Result<()> {
+        let object = value
+            .as_object()
+            .ok_or_else(|| anyhow!("Value at {} does not match protobuf message {}", at, name))?;
+        let message = &self.messages[name];
+
+        for key in object.keys() {
+            if !message
+                .fields
+                .iter()
+                .any(|field| field.json_name == *key || field.name == *key)
+            {
+                return Err(anyhow!(
+                    "Unknown field {}.{} for protobuf message {}",
+                    at,
+                    key,
+                    name
+                ));
+            }
+        }
+
+        for field in &message.fields {
+            let Some(value) = object
+                .get(&field.json_name)
+                .or_else(|| object.get(&field.name))
+            else {
+                continue;
+            };
+            let at = format!("{}.{}", at, field.name);
+            match (&field.kind, value) {
+                (_, Value::Null) => {}
+                (Kind::Map(key_type, value_kind), Value::Object(entries)) => {
+                    for (key, value) in entries {
+                        let mut entry = Vec::new();
+                        let key = map_key(*key_type, key)
+                            .ok_or_else(|| anyhow!("Invalid map key {:?} at {}", key, at))?;
+                        self.encode_field(1, &Kind::Scalar(*key_type), &key, &at, &mut entry)?;
+                        self.encode_field(
+                            2,
+                            value_kind,
+                            value,
+                            &format!("{}.{}", at, key),
+                            &mut entry,
+                        )?;
+                        write_tag(field.number, LEN, out);
+                        write_varint(entry.len() as u64, out);
+                        out.extend_from_slice(&entry);
+                    }
+                }
+                (Kind::Scalar(scalar), Value::Array(values))
+                    if field.repeated && packable(*scalar) =>
+                {
+                    let mut packed = Vec::new();
+                    for (i, value) in values.iter().enumerate() {
+                        encode_scalar(*scalar, value, &format!("{}[{}]", at, i), &mut packed)?;
+                    }
+                    write_tag(field.number, LEN, out);
+                    write_varint(packed.len() as u64, out);
+                    out.extend_from_slice(&packed);
+                }
+                (kind, Value::Array(values)) if field.repeated => {
+                    for (i, value) in values.iter().enumerate() {
+                        self.encode_field(
+                            field.number,
+                            kind,
+                            value,
+                            &format!("{}[{}]", at, i),
+                            out,
+                        )?;
+                    }
+                }
+                (kind, value) if !field.repeated => {
+                    self.encode_field(field.number, kind, value, &at, out)?
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Value at {} must be {}",
+                        at,
+                        if field.repeated {
+                            "an array"
+                        } else {
+                            "an object"
+                        }
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn encode_field(
+        &self,
+        number: u32,
+        kind: &Kind,
+        value: &Value,
+        at: &str,
+        out: &mut Vec<u8>,
+    ) -> Result<()> {
+        match kind {
+            Kind::Scalar(Scalar::String | Scalar::Bytes) | Kind::Message(_) => {
+                let mut encoded = Vec::new();
+                match kind {
+                    Kind::Message(name) => self.encode_message(name, value, at, &mut encoded)?,
+                    Kind::Scalar(scalar) => encode_scalar(*scalar, value, at, &mut encoded)?,
+                    _ => unreachable!(),
+                }
+                write_tag(number, LEN, out);
+                write_varint(encoded.len() as u64, out);
+                out.extend_from_slice(&encoded);
+            }
+            Kind::Scalar(scalar) => {
+                write_tag(number, wire_type(*scalar), out);
+                encode_scalar(*scalar, value, at, out)?;
+            }
+            Kind::Enum(name) => {
+                let values = &self.enums[name];
+                let symbol = match value {
+                    Value::String(s) => values
+                        .iter()
+                        .find(|(value, _)| value == s)
+                        .map(|(_, symbol)| *symbol),
+                    Value::Number(n) => n.as_i64().and_then(|n| i32::try_from(n).ok()),
+                    _ => None,
+                }
+                .ok_or_else(|| anyhow!("Value at {} is not a {} value", at, name))?;
+                write_tag(number, VARINT, out);
+                write_varint(symbol as i64 as u64, out);
+            }
+            Kind::Map(..) | Kind::Unresolved { .. } => {
+                return Err(anyhow!("Value at {} cannot be encoded", at))
+            }
+        }
+        Ok(())
+    }
+}
+
+fn packable(scalar: Scalar) -> bool {
+    !matches!(scalar, Scalar::String | Scalar::Bytes)
+}
+
+fn wire_type(scalar: Scalar) -> u32 {
+    match scalar {
+        Scalar::Double | Scalar::Fixed64 | Scalar::Sfixed64 => FIXED64,
+        Scalar::Float | Scalar::Fixed32 | Scalar::Sfixed32 => FIXED32,
+        Scalar::String | Scalar::Bytes => LEN,
+        _ => VARINT,
+    }
+}
+
+/// Encodes a scalar without its tag, following the proto3 JSON mapping:
+/// 64-bit integers may be strings and bytes are base64. Integers outside the
+/// field type's range do not match it.
+fn encode_scalar(scalar: Scalar, value: &Value, at: &str, out: &mut Vec<u8>) -> Result<()> {
+    let mismatch = || anyhow!("Value at {} does not match protobuf type {:?}", at, scalar);
+    let int = || match value {
+        Value::Number(n) => n
+            .as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from)),
+        Value::String(s) => s.parse::<i128>().ok(),
+        _ => None,
+    };
+    let float = || match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.parse::<f64>().ok(),
+        _ => None,
+    };
+
+    match scalar {
+        Scalar::Double => out.extend_from_slice(&float().ok_or_else(mismatch)?.to_le_bytes()),
+        Scalar::Float => {
+            out.extend_from_slice(&(float().ok_or_else(mismatch)? as f32).to_le_bytes())
+        }
+        Scalar::Int32 => {
+            let n: i32 = in_range(int()).ok_or_else(mismatch)?;
+            write_varint(i64::from(n) as u64, out);
+        }
+        Scalar::Int64 => {
+            let n: i64 = in_range(int()).ok_or_else(mismatch)?;
+            write_varint(n as u64, out);
+        }
+        Scalar::Uint32 => {
+            let n: u32 = in_range(int()).ok_or_else(mismatch)?;
+            write_varint(u64::from(n), out);
+        }
+        Scalar::Uint64 => write_varint(in_range(int()).ok_or_else(mismatch)?, out),
+        Scalar::Sint32 => {
+            let n: i32 = in_range(int()).ok_or_else(mismatch)?;
+            write_varint(zigzag(i64::from(n)), out);
+        }
+        Scalar::Sint64 => write_varint(zigzag(in_range(int()).ok_or_else(mismatch)?), out),
+        Scalar::Fixed32 => {
+            let n: u32 = in_range(int()).ok_or_else(mismatch)?;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Scalar::Sfixed32 => {
+            let n: i32 = in_range(int()).ok_or_else(mismatch)?;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Scalar::Fixed64 => {
+            let n: u64 = in_range(int()).ok_or_else(mismatch)?;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Scalar::Sfixed64 => {
+            let n: i64 = in_range(int()).ok_or_else(mismatch)?;
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Scalar::Bool => out.push(value.as_bool().ok_or_else(mismatch)? as u8),
+        Scalar::String => out.extend_from_slice(value.as_str().ok_or_else(mismatch)?.as_bytes()),
+        Scalar::Bytes => {
+            let bytes = value
+                .as_str()
+                .and_then(|s| BASE64.decode(s).ok())
+                .ok_or_else(mismatch)?;
+            out.extend_from_slice(&bytes);
+        }
+    }
+    Ok(())
+}
+
+/// An integer as the field's type, if it fits
+fn in_range<T: TryFrom<i128>>(n: Option<i128>) -> Option<T> {
+    n.and_then(|n| T::try_from(n).ok())
+}
+
+/// JSON object keys as the map's key type
+fn map_key(key_type: Scalar, key: &str) -> Option<Value> {
+    match key_type {
+        Scalar::String => Some(Value::String(key.to_string())),
+        Scalar::Bool => key.parse::<bool>().ok().map(Value::Bool),
+        _ => Some(Value::String(key.to_string())),
+    }
+}
+
+fn write_tag(number: u32, wire_type: u32, out: &mut Vec<u8>) {
+    write_varint(u64::from(number << 3 | wire_type), out);
+}
+
+fn write_varint(mut n: u64, out: &mut Vec<u8>) {
+    while n >= 0x80 {
+        out.push((n as u8) | 0x80);
+        n >>= 7;
+    }
+    out.push(n as u8);
+}
+
+fn zigzag(n: i64) -> u64 {
+    ((n << 1) ^ (n >> 63)) as u64
+}
+
+fn scalar(name: &str) -> Option<Scalar> {
+    Some(match name {
+        "double" => Scalar::Double,
+        "float" => Scalar::Float,
+        "int32" => Scalar::Int32,
+        "int64" => Scalar::Int64,
+        "uint32" => Scalar::Uint32,
+        "uint64" => Scalar::Uint64,
+        "sint32" => Scalar::Sint32,
+        "sint64" => Scalar::Sint64,
+        "fixed32" => Scalar::Fixed32,
+        "fixed64" => Scalar::Fixed64,
+        "sfixed32" => Scalar::Sfixed32,
+        "sfixed64" => Scalar::Sfixed64,
+        "bool" => Scalar::Bool,
+        "string" => Scalar::String,
+        "bytes" => Scalar::Bytes,
+        _ => return None,
+    })
+}
+
+/// lowerCamelCase, as protoc derives JSON names
+fn json_name(name: &str) -> String {
+    let mut json_name = String::new();
+    let mut upper = false;
+    for c in name.chars() {
+        if c == '_' {
+            upper = true;
+        } else if upper {
+            json_name.extend(c.to_uppercase());
+            upper = false;
+        } else {
+            json_name.push(c);
+        }
+    }
+    json_name
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Float,
+    Str(String),
+    Symbol(char),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i + 1 < chars.len() && !(chars[i] == '*' && chars[i + 1] == '/') {
+                    i += 1;
+                }
+                i += 2;
+            }
+            '"' | '\'' => {
+                i += 1;
+                let start = i;
+                while i < chars.len() && chars[i] != c {
+                    i += if chars[i] == '\\' { 2 } else { 1 };
+                }
+                if i >= chars.len() {
+                    return Err(anyhow!("Unterminated string in protobuf schema"));
+                }
+                tokens.push(Token::Str(chars[start..i].iter().collect()));
+                i += 1;
+            }
+            c if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                tokens.push(Token::Ident(chars[start..i].iter().collect()));
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let start = i;
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                let value = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16).ok(),
+                    None => text.parse::<i64>().ok(),
+                };
+                // Float literals only appear in options, which are skipped
+                tokens.push(value.map_or(Token::Float, Token::Int));
+            }
+            _ => {
+                tokens.push(Token::Symbol(c));
+                i += 1;
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    schema: Schema,
+    first_message: Option<String>,
+}
+
+/// Where a message is declared, to number it for its message indexes
+struct Position<'a> {
+    /// Indexes of the enclosing message; empty at the top level
+    parent: &'a [i64],
+    /// Messages already declared in the same scope
+    count: &'a mut i64,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| anyhow!("Unexpected end of protobuf schema"))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            token => Err(anyhow!(
+                "Expected a name in protobuf schema, found {:?}",
+                token
+            )),
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            token => Err(anyhow!(
+                "Expected '{}' in protobuf schema, found {:?}",
+                symbol,
+                token
+            )),
+        }
+    }
+
+    /// Skips to the end of a statement, including any `{ ... }` body
+    fn skip_statement(&mut self) -> Result<()> {
+        let mut depth = 0;
+        loop {
+            match self.next()? {
+                Token::Symbol(';') if depth == 0 => return Ok(()),
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn parse_file(&mut self) -> Result<()> {
+        let mut package = String::new();
+        let mut messages = 0;
+        while let Some(token) = self.peek().cloned() {
+            match token {
+                Token::Ident(keyword) if keyword == "package" => {
+                    self.pos += 1;
+                    package = self.ident()?;
+                    self.expect(';')?;
+                }
+                Token::Ident(keyword) if keyword == "import" => {
+                    return Err(anyhow!("Protobuf imports are not supported"));
+                }
+                Token::Ident(keyword) if keyword == "message" => {
+                    self.pos += 1;
+                    let position = Position {
+                        parent: &[],
+                        count: &mut messages,
+                    };
+                    let name = self.parse_message(&package, position)?;
+                    self.first_message.get_or_insert(name);
+                }
+                Token::Ident(keyword) if keyword == "enum" => {
+                    self.pos += 1;
+                    self.parse_enum(&package)?;
+                }
+                Token::Symbol(';') => self.pos += 1,
+                // syntax, option, service and extend
+                _ => self.skip_statement()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_message(&mut self, scope: &str, position: Position) -> Result<String> {
+        let name = qualify(scope, &self.ident()?);
+        let indexes = [position.parent, &[*position.count]].concat();
+        *position.count += 1;
+        self.schema
+            .messages
+            .insert(name.clone(), Message::default());
+        self.schema.indexes.insert(name.clone(), indexes.clone());
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        self.parse_message_body(&name, &indexes, &mut 0, &mut fields)?;
+        self.schema.messages.get_mut(&name).unwrap().fields = fields;
+        Ok(name)
+    }
+
+    fn parse_message_body(
+        &mut self,
+        name: &str,
+        indexes: &[i64],
+        nested: &mut i64,
+        fields: &mut Vec<Field>,
+    ) -> Result<()> {
+        loop {
+            let token = self.next()?;
+            match &token {
+                Token::Symbol('}') => return Ok(()),
+                Token::Symbol(';') => {}
+                Token::Ident(keyword) => match keyword.as_str() {
+                    "message" => {
+                        let position = Position {
+                            parent: indexes,
+                            count: nested,
+                        };
+                        self.parse_message(name, position)?;
+                    }
+                    "enum" => self.parse_enum(name)?,
+                    "oneof" => {
+                        self.ident()?;
+                        self.expect('{')?;
+                        self.parse_message_body(name, indexes, nested, fields)?;
+                    }
+                    "option" | "reserved" | "extensions" | "extend" => self.skip_statement()?,
+                    "map" => {
+                        self.expect('<')?;
+                        let key = self.ident()?;
+                        let key = scalar(&key)
+                            .ok_or_else(|| anyhow!("Invalid protobuf map key type {:?}", key))?;
+                        self.expect(',')?;
+                        let value = self.type_name(name)?;
+                        self.expect('>')?;
+                        fields.push(self.parse_field(Kind::Map(key, Box::new(value)), false)?);
+                    }
+                    "repeated" => {
+                        let kind = self.type_name(name)?;
+                        fields.push(self.parse_field(kind, true)?);
+                    }
+                    "optional" | "required" => {
+                        let kind = self.type_name(name)?;
+                        fields.push(self.parse_field(kind, false)?);
+                    }
+                    _ => {
+                        self.pos -= 1;
+                        let kind = self.type_name(name)?;
+                        fields.push(self.parse_field(kind, false)?);
+                    }
+                },
+                token => {
+                    return Err(anyhow!(
+                        "Unexpected {:?} in protobuf message {}",
+                        token,
+                        name
+                    ))
+                }
+            }
+        }
+    }
+
+    fn type_name(&mut self, scope: &str) -> Result<Kind> {
+        let name = self.ident()?;
+        Ok(match scalar(&name) {
+            Some(scalar) => Kind::Scalar(scalar),
+            None => Kind::Unresolved {
+                name,
+                scope: scope.to_string(),
+            },
+        })
+    }
+
+    fn parse_field(&mut self, kind: Kind, repeated: bool) -> Result<Field> {
+        let name = self.ident()?;
+        self.expect('=')?;
+        let number = match self.next()? {
+            Token::Int(n) if (1..=536_870_911).contains(&n) => n as u32,
+            token => {
+                return Err(anyhow!(
+                    "Invalid field number {:?} for protobuf field {}",
+                    token,
+                    name
+                ))
+            }
+        };
+        // Field options such as [json_name = "..."] or [packed = false]
+        let mut json_name_option = None;
+        if self.peek() == Some(&Token::Symbol('[')) {
+            let mut previous = Vec::new();
+            loop {
+                let token = self.next()?;
+                match (&token, previous.as_slice()) {
+                    (Token::Symbol(']'), _) => break,
+                    (Token::Str(value), [.., Token::Ident(option), Token::Symbol('=')])
+                        if option == "json_name" =>
+                    {
+                        json_name_option = Some(value.clone());
+                    }
+                    _ => {}
+                }
+                previous.push(token);
+            }
+        }
+        self.expect(';')?;
+        Ok(Field {
+            json_name: json_name_option.unwrap_or_else(|| json_name(&name)),
+            name,
+            number,
+            kind,
+            repeated,
+        })
+    }
+
+    fn parse_enum(&mut self, scope: &str) -> Result<()> {
+        let name = qualify(scope, &self.ident()?);
+        self.expect('{')?;
+        let mut values = Vec::new();
+        loop {
+            match self.next()? {
+                Token::Symbol('}') => break,
+                Token::Symbol(';') => {}
+                Token::Ident(keyword) if keyword == "option" || keyword == "reserved" => {
+                    self.skip_statement()?
+                }
+                Token::Ident(value) => {
+                    self.expect('=')?;
+                    let number = match self.next()? {
+                        Token::Int(n) => i32::try_from(n)
+                            .map_err(|_| anyhow!("Enum value {} is out of range", value))?,
+                        token => {
+                            return Err(anyhow!(
+                                "Invalid number {:?} for enum value {}",
+                                token,
+                                value
+                            ))
+                        }
+                    };
+                    if self.peek() == Some(&Token::Symbol('[')) {
+                        while self.next()? != Token::Symbol(']') {}
+                    }
+                    self.expect(';')?;
+                    values.push((value, number));
+                }
+                token => return Err(anyhow!("Unexpected {:?} in protobuf enum {}", token, name)),
+            }
+        }
+        self.schema.enums.insert(name, values);
+        Ok(())
+    }
+}
+
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const ORDER: &str = r#"
+        syntax = "proto3";
+        package shop;
+
+        // Orders are the first message, so they are the one encoded
+        message Order {
+            string id = 1;
+            int64 total_cents = 2;
+            Status status = 3;
+            repeated int32 quantities = 4;
+            map<string, string> labels = 5;
+            Customer customer = 6;
+
+            enum Status {
+                PENDING = 0;
+                PAID = 1;
+            }
+        }
+
+        message Customer {
+            string email = 1 [json_name = "mail"];
+        }
+    "#;
+
+    fn encode(schema: &Schema, value: Value) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        schema.encode(&value, &mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_protobuf_encode() {
+        let schema = Schema::parse(ORDER).unwrap();
+
+        let order = json!({
+            "id": "o1",
+            "totalCents": "300",
+            "status": "PAID",
+            "quantities": [1, 2],
+            "labels": { "a": "b" },
+            "customer": { "mail": "x@y" }
+        });
+        assert_eq!(
+            encode(&schema, order).unwrap(),
+            vec![
+                0x0a, 2, b'o', b'1', 0x10, 0xac, 0x02, 0x18, 1, 0x22, 2, 1, 2, 0x2a, 6, 0x0a, 1,
+                b'a', 0x12, 1, b'b', 0x32, 5, 0x0a, 3, b'x', b'@', b'y'
+            ]
+        );
+
+        // Original field names are accepted too, and nulls are left out
+        assert_eq!(
+            encode(
+                &schema,
+                json!({ "total_cents": 1, "status": 0, "customer": null })
+            )
+            .unwrap(),
+            vec![0x10, 1, 0x18, 0]
+        );
+
+        assert!(encode(&schema, json!({ "id": 5 })).is_err());
+        assert!(encode(&schema, json!({ "status": "REFUNDED" })).is_err());
+        assert!(encode(&schema, json!({ "unknown": 1 })).is_err());
+        assert!(encode(&schema, json!([])).is_err());
+
+        assert!(Schema::parse("syntax = \"proto3\";").is_err());
+        assert!(Schema::parse("import \"other.proto\"; message A { string a = 1; }").is_err());
+        assert!(Schema::parse("message A { Missing a = 1; }").is_err());
+    }
+
+    const EVENT: &str = r#"
+        syntax = "proto3";
+        package test;
+
+        message Event {
+            int32 small = 1;
+            int64 big = 2;
+            sint32 zig = 3;
+            sint64 zig64 = 4;
+            sfixed32 sf32 = 5;
+            sfixed64 sf64 = 6;
+            fixed32 f32 = 7;
+            uint64 u64 = 8;
+            double ratio = 9;
+            float scale = 10;
+            bool flag = 11;
+            bytes blob = 12;
+            Level level = 13;
+            repeated Level levels = 14;
+            repeated sint64 deltas = 15;
+            repeated string tags = 16;
+            repeated Item items = 17;
+            map<int32, Item> by_id = 18;
+            map<string, int64> counts = 19;
+            uint32 u32 = 22;
+            fixed64 f64 = 23;
+            oneof target {
+                string user = 20;
+                Item item = 21;
+            }
+
+            message Item {
+                string name = 1;
+                Item child = 2;
+            }
+
+            enum Level {
+                LEVEL_UNSPECIFIED = 0;
+                LOW = -1;
+                HIGH = 1;
+            }
+        }
+    "#;
+
+    /// Decodes a message back to the proto3 JSON mapping, to check encoding
+    /// by round trip
+    fn decode(schema: &Schema, name: &str, mut bytes: &[u8]) -> Value {
+        let message = &schema.messages[name];
+        let mut object = serde_json::Map::new();
+        while !bytes.is_empty() {
+            let tag = read_varint(&mut bytes);
+            let field = message
+                .fields
+                .iter()
+                .find(|field| u64::from(field.number) == tag >> 3)
+                .unwrap();
+            let key = field.json_name.clone();
+            match &field.kind {
+                Kind::Scalar(scalar) if field.repeated && packable(*scalar) => {
+                    assert_eq!(tag & 7, u64::from(LEN));
+                    let mut packed = take(&mut bytes);
+                    let values = object.entry(key).or_insert_with(|| json!([]));
+                    while !packed.is_empty() {
+                        values
+                            .as_array_mut()
+                            .unwrap()
+                            .push(decode_scalar(*scalar, &mut packed));
+                    }
+                }
+                Kind::Map(key_type, value_kind) => {
+                    let mut entry = take(&mut bytes);
+                    let (mut entry_key, mut entry_value) = (None, None);
+                    while !entry.is_empty() {
+                        match read_varint(&mut entry) >> 3 {
+                            1 => entry_key = Some(decode_scalar(*key_type, &mut entry)),
+                            _ => entry_value = Some(decode_value(schema, value_kind, &mut entry)),
+                        }
+                    }
+                    let entry_key = match entry_key.unwrap() {
+                        Value::String(s) => s,
+                        key => key.to_string(),
+                    };
+                    let entries = object.entry(key).or_insert_with(|| json!({}));
+                    entries
+                        .as_object_mut()
+                        .unwrap()
+                        .insert(entry_key, entry_value.unwrap());
+                }
+                kind if field.repeated => {
+                    let value = decode_value(schema, kind, &mut bytes);
+                    let values = object.entry(key).or_insert_with(|| json!([]));
+                    values.as_array_mut().unwrap().push(value);
+                }
+                kind => {
+                    object.insert(key, decode_value(schema, kind, &mut bytes));
+                }
+            }
+        }
+        Value::Object(object)
+    }
+
+    fn decode_value(schema: &Schema, kind: &Kind, bytes: &mut &[u8]) -> Value {
+        match kind {
+            Kind::Message(name) => decode(schema, name, take(bytes)),
+            Kind::Enum(name) => {
+                let number = read_varint(bytes) as i32;
+                let values = &schema.enums[name];
+                let (symbol, _) = values.iter().find(|(_, value)| *value == number).unwrap();
+                json!(symbol)
+            }
+            Kind::Scalar(scalar) => decode_scalar(*scalar, bytes),
+            Kind::Map(..) | Kind::Unresolved { .. } => unreachable!(),
+        }
+    }
+
+    /// 64-bit integers decode to strings, as in the proto3 JSON mapping
+    fn decode_scalar(scalar: Scalar, bytes: &mut &[u8]) -> Value {
+        match scalar {
+            Scalar::Double => json!(f64::from_le_bytes(take_fixed(bytes))),
+            Scalar::Float => json!(f32::from_le_bytes(take_fixed(bytes))),
+            Scalar::Int32 => json!(read_varint(bytes) as i32),
+            Scalar::Int64 => json!((read_varint(bytes) as i64).to_string()),
+            Scalar::Uint32 => json!(read_varint(bytes) as u32),
+            Scalar::Uint64 => json!(read_varint(bytes).to_string()),
+            Scalar::Sint32 => json!(unzigzag(read_varint(bytes)) as i32),
+            Scalar::Sint64 => json!(unzigzag(read_varint(bytes)).to_string()),
+            Scalar::Fixed32 => json!(u32::from_le_bytes(take_fixed(bytes))),
+            Scalar::Fixed64 => json!(u64::from_le_bytes(take_fixed(bytes)).to_string()),
+            Scalar::Sfixed32 => json!(i32::from_le_bytes(take_fixed(bytes))),
+            Scalar::Sfixed64 => json!(i64::from_le_bytes(take_fixed(bytes)).to_string()),
+            Scalar::Bool => json!(read_varint(bytes) != 0),
+            Scalar::String => json!(std::str::from_utf8(take(bytes)).unwrap()),
+            Scalar::Bytes => json!(BASE64.encode(take(bytes))),
+        }
+    }
+
+    fn read_varint(bytes: &mut &[u8]) -> u64 {
+        let mut n = 0;
+        for shift in (0..).step_by(7) {
+            let byte = bytes[0];
+            *bytes = &bytes[1..];
+            n |= u64::from(byte & 0x7f) << shift;
+            if byte < 0x80 {
+                break;
+            }
+        }
+        n
+    }
+
+    fn unzigzag(n: u64) -> i64 {
+        (n >> 1) as i64 ^ -((n & 1) as i64)
+    }
+
+    fn take<'a>(bytes: &mut &'a [u8]) -> &'a [u8] {
+        let len = read_varint(bytes) as usize;
+        let (value, rest) = bytes.split_at(len);
+        *bytes = rest;
+        value
+    }
+
+    fn take_fixed<const N: usize>(bytes: &mut &[u8]) -> [u8; N] {
+        let (value, rest) = bytes.split_at(N);
+        *bytes = rest;
+        value.try_into().unwrap()
+    }
+
+    #[test]
+    fn test_protobuf_round_trip() {
+        let schema = Schema::parse(EVENT).unwrap();
+        let round_trip = |value: Value| {
+            let encoded = encode(&schema, value.clone()).unwrap();
+            assert_eq!(decode(&schema, "test.Event", &encoded), value);
+        };
+
+        // Negative numbers in every integer encoding
+        round_trip(json!({
+            "small": -1,
+            "big": "-9007199254740993",
+            "zig": -64,
+            "zig64": "-9223372036854775808",
+            "sf32": -2147483648,
+            "sf64": "-5",
+            "f32": 4294967295u32,
+            "u64": "18446744073709551615",
+            "ratio": -0.25,
+            "scale": 1.5,
+            "flag": true,
+            "blob": "AAEC/w=="
+        }));
+
+        // Enums by name, including negative values, packed and unpacked
+        // repeated fields, repeated and recursive nested messages and maps
+        round_trip(json!({
+            "level": "LOW",
+            "levels": ["HIGH", "LOW", "LEVEL_UNSPECIFIED"],
+            "deltas": ["1", "-1", "-300"],
+            "tags": ["a", "", "c"],
+            "items": [{ "name": "x", "child": { "name": "y", "child": { "name": "z" } } }, {}],
+            "byId": { "-7": { "name": "neg" }, "7": { "name": "pos" } },
+            "counts": { "a": "-1", "b": "2" }
+        }));
+
+        // Either branch of a oneof
+        round_trip(json!({ "user": "u1" }));
+        round_trip(json!({ "item": { "name": "i1" } }));
+
+        // Negative int32 values and enums are 10-byte varints; sint32 uses
+        // zig-zag and sfixed32 four bytes
+        assert_eq!(
+            encode(&schema, json!({ "small": -1 })).unwrap(),
+            [&[0x08][..], &[0xff; 9], &[0x01]].concat()
+        );
+        assert_eq!(
+            encode(&schema, json!({ "level": -1 })).unwrap(),
+            [&[0x68][..], &[0xff; 9], &[0x01]].concat()
+        );
+        assert_eq!(
+            encode(&schema, json!({ "zig": -2 })).unwrap(),
+            vec![0x18, 3]
+        );
+        assert_eq!(
+            encode(&schema, json!({ "sf32": -2 })).unwrap(),
+            vec![0x2d, 0xfe, 0xff, 0xff, 0xff]
+        );
+
+        assert!(encode(&schema, json!({ "small": 2147483648u32 })).is_err());
+        assert!(encode(&schema, json!({ "byId": { "x": {} } })).is_err());
+        assert!(encode(&schema, json!({ "items": { "name": "x" } })).is_err());
+        assert!(encode(&schema, json!({ "blob": "not base64!" })).is_err());
+    }
+
+    #[test]
+    fn test_protobuf_integer_ranges() {
+        let schema = Schema::parse(EVENT).unwrap();
+        let fits = |field: &str, n: Value| {
+            let encoded = encode(&schema, json!({ field: n.clone() }));
+            match encoded {
+                Ok(encoded) => {
+                    // Decoded 64-bit values are strings, as in the proto3 JSON mapping
+                    let decoded = decode(&schema, "test.Event", &encoded)[field].clone();
+                    assert_eq!(
+                        decoded.to_string().trim_matches('"'),
+                        n.to_string().trim_matches('"')
+                    );
+                    true
+                }
+                Err(e) => {
+                    assert!(
+                        e.to_string().contains("does not match protobuf type"),
+                        "{}",
+                        e
+                    );
+                    false
+                }
+            }
+        };
+
+        // Each type's bounds fit and the values past them do not
+        let bounds: [(&str, i128, i128); 10] = [
+            ("small", i32::MIN.into(), i32::MAX.into()),
+            ("zig", i32::MIN.into(), i32::MAX.into()),
+            ("sf32", i32::MIN.into(), i32::MAX.into()),
+            ("u32", 0, u32::MAX.into()),
+            ("f32", 0, u32::MAX.into()),
+            ("big", i64::MIN.into(), i64::MAX.into()),
+            ("zig64", i64::MIN.into(), i64::MAX.into()),
+            ("sf64", i64::MIN.into(), i64::MAX.into()),
+            ("u64", 0, u64::MAX.into()),
+            ("f64", 0, u64::MAX.into()),
+        ];
+        for (field, min, max) in bounds {
+            assert!(fits(field, json!(min.to_string())), "{} {}", field, min);
+            assert!(fits(field, json!(max.to_string())), "{} {}", field, max);
+            assert!(
+                !fits(field, json!((min - 1).to_string())),
+                "{} {}",
+                field,
+                min - 1
+            );
+            assert!(
+                !fits(field, json!((max + 1).to_string())),
+                "{} {}",
+                field,
+                max + 1
+            );
+        }
+
+        // JSON numbers are checked the same way as strings
+        assert!(fits("small", json!(-1)));
+        assert!(!fits("small", json!(2147483648u32)));
+        assert!(!fits("f32", json!(4294967297u64)));
+        assert!(!fits("big", json!(9223372036854775808u64)));
+        assert!(fits("u64", json!(18446744073709551615u64)));
+
+        // Negative values never fit unsigned types
+        for field in ["u32", "f32", "u64", "f64"] {
+            assert!(!fits(field, json!(-1)), "{}", field);
+            assert!(!fits(field, json!("-1")), "{}", field);
+        }
+    }
+
+    const NESTED: &str = r#"
+        package shop;
+
+        message A {
+            message B {}
+            message C {
+                message D {}
+            }
+        }
+
+        message E {}
+    "#;
+
+    fn message_indexes(schema: &Schema) -> Vec<u8> {
+        let mut out = Vec::new();
+        schema.write_message_indexes(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_protobuf_message_indexes() {
+        let mut schema = Schema::parse(NESTED).unwrap();
+        assert_eq!(schema.indexes["shop.A"], vec![0]);
+        assert_eq!(schema.indexes["shop.A.B"], vec![0, 0]);
+        assert_eq!(schema.indexes["shop.A.C"], vec![0, 1]);
+        assert_eq!(schema.indexes["shop.A.C.D"], vec![0, 1, 0]);
+        assert_eq!(schema.indexes["shop.E"], vec![1]);
+
+        // The first message is written as a single 0
+        assert_eq!(message_indexes(&schema), vec![0]);
+
+        // Others as the zig-zag count followed by the zig-zag indexes
+        schema.select("shop.E").unwrap();
+        assert_eq!(message_indexes(&schema), vec![2, 2]);
+        schema.select(".shop.A.C.D").unwrap();
+        assert_eq!(message_indexes(&schema), vec![6, 0, 2, 0]);
+        schema.select("shop.A.B").unwrap();
+        assert_eq!(message_indexes(&schema), vec![4, 0, 0]);
+
+        assert!(schema.select("shop.Missing").is_err());
+        assert!(schema.select("A").is_err());
+        assert_eq!(message_indexes(&schema), vec![4, 0, 0]);
+    }
+}
@@ -0,0 +1,132 @@
+use anyhow::{anyhow, Context, Result};
+use reqwest::{header::ACCEPT, Client, RequestBuilder, Url};
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use crate::crd::SchemaFormat;
+
+const CONTENT_TYPE: &str = "application/vnd.schemaregistry.v1+json";
+
+/// A client for a Confluent-compatible Schema Registry
+#[derive(Debug)]
+pub struct SchemaRegistry {
+    client: Client,
+    url: Url,
+    /// Basic auth username and password
+    credentials: Option<(String, String)>,
+}
+
+/// A schema as stored in the registry
+#[derive(Debug)]
+pub struct RegisteredSchema {
+    pub id: u32,
+    pub schema: String,
+}
+
+impl SchemaRegistry {
+    pub fn new(url: &str, credentials: Option<(String, String)>) -> Result<Self> {
+        let url = Url::parse(url).context("Invalid SCHEMA_REGISTRY_URL")?;
+        if url.cannot_be_a_base() {
+            return Err(anyhow!("Invalid SCHEMA_REGISTRY_URL: {}", url));
+        }
+        let client = Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()
+            .context("Failed to create Schema Registry client")?;
+
+        Ok(SchemaRegistry {
+            client,
+            url,
+            credentials,
+        })
+    }
+
+    /// Registers a schema under a subject and returns its ID. A schema that
+    /// is already registered keeps its existing ID.
+    pub async fn register(&self, subject: &str, format: SchemaFormat, schema: &str) -> Result<u32> {
+        let mut body = json!({ "schema": schema });
+        if format != SchemaFormat::Avro {
+            body["schemaType"] = json!(format.as_str());
+        }
+        let response = self
+            .send(
+                self.client
+                    .post(self.endpoint(subject, &["versions"]))
+                    .json(&body),
+            )
+            .await
+            .with_context(|| format!("Failed to register schema under subject {}", subject))?;
+        schema_id(&response)
+    }
+
+    /// The latest version of a subject's schema, which must be in the given format
+    pub async fn latest(&self, subject: &str, format: SchemaFormat) -> Result<RegisteredSchema> {
+        let response = self
+            .send(
+                self.client
+                    .get(self.endpoint(subject, &["versions", "latest"])),
+            )
+            .await
+            .with_context(|| format!("Failed to look up subject {}", subject))?;
+
+        // The registry leaves out schemaType for Avro
+        let schema_type = response["schemaType"].as_str().unwrap_or("AVRO");
+        if schema_type != format.as_str() {
+            return Err(anyhow!(
+                "Subject {} holds a {} schema, not {}",
+                subject,
+                schema_type,
+                format.as_str()
+            ));
+        }
+        Ok(RegisteredSchema {
+            id: schema_id(&response)?,
+            schema: response["schema"]
+                .as_str()
+                .ok_or_else(|| anyhow!("Schema Registry response has no schema"))?
+                .to_string(),
+        })
+    }
+
+    /// `<url>/subjects/<subject>/<segments>`, with the subject percent-encoded
+    fn endpoint(&self, subject: &str, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("checked in SchemaRegistry::new")
+            .pop_if_empty()
+            .push("subjects")
+            .push(subject)
+            .extend(segments);
+        url
+    }
+
+    async fn send(&self, mut request: RequestBuilder) -> Result<Value> {
+        request = request.header(ACCEPT, CONTENT_TYPE);
+        if let Some((username, password)) = &self.credentials {
+            request = request.basic_auth(username, Some(password));
+        }
+
+        let response = request.send().await?;
+        let status = response.status();
+        let body: Value = response.json().await.unwrap_or(Value::Null);
+        if !status.is_success() {
+            let message = body["message"]
+                .as_str()
+                .or(status.canonical_reason())
+                .unwrap_or_default();
+            return Err(anyhow!(
+                "Schema Registry returned {}: {}",
+                status.as_u16(),
+                message
+            ));
+        }
+        Ok(body)
+    }
+}
+
+fn schema_id(response: &Value) -> Result<u32> {
+    response["id"]
+        .as_u64()
+        .and_then(|id| u32::try_from(id).ok())
+        .ok_or_else(|| anyhow!("Schema Registry response has no schema ID"))
+}
@@ -1,68 +1,293 @@
+use anyhow::{anyhow, Result};
+use axum::http::HeaderMap;
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
 use hmac::{Hmac, Mac};
 use sha2::Sha256;
-use anyhow::{anyhow, Result};
+
+use crate::crd::SignatureScheme;
 
 type HmacSha256 = Hmac<Sha256>;
 
+/// Maximum accepted clock skew for timestamped schemes (prevents replay attacks)
+const TIMESTAMP_TOLERANCE_SECS: i64 = 300;
+
+/// A resolved signing key, accepted until `not_after`
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerificationKey {
+    pub secret: String,
+    pub not_after: Option<DateTime<Utc>>,
+}
+
+impl VerificationKey {
+    pub fn is_active(&self, now: DateTime<Utc>) -> bool {
+        self.not_after.is_none_or(|not_after| now < not_after)
+    }
+}
+
+/// Verifies a webhook request against every currently valid key, so that
+/// senders can switch secrets without dropping traffic during a rotation.
+/// A key that cannot be checked counts as no match; its error is returned
+/// only when no other key verifies the request.
+pub fn verify_webhook_keys(
+    scheme: SignatureScheme,
+    keys: &[VerificationKey],
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<bool> {
+    let now = Utc::now();
+    let mut active = keys.iter().filter(|key| key.is_active(now)).peekable();
+    if active.peek().is_none() {
+        return Err(anyhow!("No active signature key"));
+    }
+
+    let mut error = None;
+    for key in active {
+        match verify_webhook(scheme, &key.secret, headers, body) {
+            Ok(true) => return Ok(true),
+            Ok(false) => {}
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+    error.map_or(Ok(false), Err)
+}
+
+/// Checks that a secret can be used with the scheme, so that unusable keys
+/// are reported when the handler is loaded rather than on every request
+pub fn check_secret(scheme: SignatureScheme, secret: &str) -> Result<()> {
+    if scheme == SignatureScheme::StandardWebhooks {
+        standard_webhooks_key(secret)?;
+    }
+    Ok(())
+}
+
 pub fn verify_signature(
     secret: &str,
     timestamp: &str,
-    body: &str,
+    body: &[u8],
     signature: &str,
 ) -> Result<bool> {
     // Check timestamp freshness (prevent replay attacks)
-    let ts: i64 = timestamp.parse()
+    if !timestamp_is_fresh(timestamp)? {
+        return Ok(false);
+    }
+
+    // Compute expected signature
+    let message = [format!("{}.", timestamp).as_bytes(), body].concat();
+    let provided = signature.strip_prefix("sha256=").unwrap_or(signature);
+
+    verify_hex(secret.as_bytes(), &message, provided)
+}
+
+/// Verifies a webhook request using the handler's signature scheme.
+/// Returns an error when the scheme's headers are missing or malformed,
+/// and `Ok(false)` when the signature does not match.
+pub fn verify_webhook(
+    scheme: SignatureScheme,
+    secret: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<bool> {
+    match scheme {
+        SignatureScheme::Default => {
+            let signature = header(headers, "X-Signature")?;
+            let timestamp = header(headers, "X-Timestamp")?;
+            verify_signature(secret, timestamp, body, signature)
+        }
+        SignatureScheme::Stripe => {
+            verify_stripe(secret, header(headers, "Stripe-Signature")?, body)
+        }
+        SignatureScheme::Github => {
+            let signature = header(headers, "X-Hub-Signature-256")?;
+            let provided = signature
+                .strip_prefix("sha256=")
+                .ok_or_else(|| anyhow!("Malformed X-Hub-Signature-256 header"))?;
+            verify_hex(secret.as_bytes(), body, provided)
+        }
+        SignatureScheme::Zoom => verify_v0(
+            secret,
+            header(headers, "X-Zm-Request-Timestamp")?,
+            header(headers, "X-Zm-Signature")?,
+            body,
+        ),
+        SignatureScheme::Slack => verify_v0(
+            secret,
+            header(headers, "X-Slack-Request-Timestamp")?,
+            header(headers, "X-Slack-Signature")?,
+            body,
+        ),
+        SignatureScheme::Shopify => {
+            let provided = BASE64
+                .decode(header(headers, "X-Shopify-Hmac-Sha256")?)
+                .map_err(|_| anyhow!("Malformed X-Shopify-Hmac-Sha256 header"))?;
+            verify_bytes(secret.as_bytes(), body, &provided)
+        }
+        SignatureScheme::StandardWebhooks => verify_standard_webhooks(secret, headers, body),
+    }
+}
+
+/// Stripe: `Stripe-Signature: t=<ts>,v1=<hex>[,v1=<hex>...]` over `"{t}.{body}"`
+fn verify_stripe(secret: &str, signature_header: &str, body: &[u8]) -> Result<bool> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for part in signature_header.split(',') {
+        match part.trim().split_once('=') {
+            Some(("t", value)) => timestamp = Some(value),
+            Some(("v1", value)) => signatures.push(value),
+            _ => {}
+        }
+    }
+
+    let timestamp = timestamp.ok_or_else(|| anyhow!("Stripe-Signature header has no timestamp"))?;
+    if signatures.is_empty() {
+        return Err(anyhow!("Stripe-Signature header has no v1 signature"));
+    }
+    if !timestamp_is_fresh(timestamp)? {
+        return Ok(false);
+    }
+
+    let message = [format!("{}.", timestamp).as_bytes(), body].concat();
+    for signature in signatures {
+        if verify_hex(secret.as_bytes(), &message, signature)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Zoom and Slack: `v0=<hex>` over `"v0:{timestamp}:{body}"`
+fn verify_v0(secret: &str, timestamp: &str, signature: &str, body: &[u8]) -> Result<bool> {
+    let provided = signature
+        .strip_prefix("v0=")
+        .ok_or_else(|| anyhow!("Signature is not a v0 signature"))?;
+    if !timestamp_is_fresh(timestamp)? {
+        return Ok(false);
+    }
+
+    let message = [format!("v0:{}:", timestamp).as_bytes(), body].concat();
+    verify_hex(secret.as_bytes(), &message, provided)
+}
+
+/// Standard Webhooks (and Svix): `webhook-signature: v1,<base64> ...` over
+/// `"{id}.{timestamp}.{body}"`, keyed with the base64 part of a `whsec_` secret
+fn verify_standard_webhooks(secret: &str, headers: &HeaderMap, body: &[u8]) -> Result<bool> {
+    let id = header(headers, "Webhook-Id").or_else(|_| header(headers, "Svix-Id"))?;
+    let timestamp =
+        header(headers, "Webhook-Timestamp").or_else(|_| header(headers, "Svix-Timestamp"))?;
+    let signatures =
+        header(headers, "Webhook-Signature").or_else(|_| header(headers, "Svix-Signature"))?;
+
+    let key = standard_webhooks_key(secret)?;
+
+    if !timestamp_is_fresh(timestamp)? {
+        return Ok(false);
+    }
+
+    let message = [format!("{}.{}.", id, timestamp).as_bytes(), body].concat();
+    for signature in signatures.split_whitespace() {
+        let Some(encoded) = signature.strip_prefix("v1,") else {
+            continue;
+        };
+        let Ok(provided) = BASE64.decode(encoded) else {
+            continue;
+        };
+        if verify_bytes(&key, &message, &provided)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn standard_webhooks_key(secret: &str) -> Result<Vec<u8>> {
+    BASE64
+        .decode(secret.strip_prefix("whsec_").unwrap_or(secret))
+        .map_err(|_| anyhow!("Standard Webhooks secret is not valid base64"))
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Result<&'a str> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| anyhow!("Missing {} header", name))
+}
+
+fn timestamp_is_fresh(timestamp: &str) -> Result<bool> {
+    let ts: i64 = timestamp
+        .parse()
         .map_err(|_| anyhow!("Invalid timestamp"))?;
     let now = chrono::Utc::now().timestamp();
-    if (now - ts).abs() > 300 { // 5 minutes tolerance
-        tracing::warn!("Signature timestamp too old or in future: {} vs {}", ts, now);
+    if (now - ts).abs() > TIMESTAMP_TOLERANCE_SECS {
+        tracing::warn!(
+            "Signature timestamp too old or in future: {} vs {}",
+            ts,
+            now
+        );
         return Ok(false);
     }
+    Ok(true)
+}
 
-    // Compute expected signature
-    let message = format!("{}.{}", timestamp, body);
-    let mut mac = HmacSha256::new_from_slice(secret.as_bytes())
-        .map_err(|e| anyhow!("Invalid key: {}", e))?;
-    mac.update(message.as_bytes());
-    let expected = hex::encode(mac.finalize().into_bytes());
+fn verify_hex(key: &[u8], message: &[u8], provided: &str) -> Result<bool> {
+    match hex::decode(provided) {
+        Ok(provided) => verify_bytes(key, message, &provided),
+        Err(_) => Ok(false),
+    }
+}
 
-    // Compare (constant time via hex crate)
-    let provided = signature.strip_prefix("sha256=").unwrap_or(signature);
-    
-    Ok(expected == provided)
+/// Constant-time comparison of the HMAC-SHA256 of `message` against `provided`
+fn verify_bytes(key: &[u8], message: &[u8], provided: &[u8]) -> Result<bool> {
+    let mut mac = HmacSha256::new_from_slice(key).map_err(|e| anyhow!("Invalid key: {}", e))?;
+    mac.update(message);
+    Ok(mac.verify_slice(provided).is_ok())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn hmac(key: &[u8], message: &str) -> Vec<u8> {
+        let mut mac = HmacSha256::new_from_slice(key).unwrap();
+        mac.update(message.as_bytes());
+        mac.finalize().into_bytes().to_vec()
+    }
+
+    fn headers(pairs: &[(&'static str, String)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
     #[test]
     fn test_verify_signature() {
         let secret = "test_secret";
         let timestamp = chrono::Utc::now().timestamp().to_string();
         let body = r#"{"test":"data"}"#;
-        
+
         // Generate signature
         let message = format!("{}.{}", timestamp, body);
         let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
         mac.update(message.as_bytes());
         let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
-        
+
         // Verify
-        assert!(verify_signature(secret, &timestamp, body, &signature).unwrap());
+        assert!(verify_signature(secret, &timestamp, body.as_bytes(), &signature).unwrap());
     }
 
     #[test]
     fn test_verify_signature_fails_wrong_secret() {
         let timestamp = chrono::Utc::now().timestamp().to_string();
         let body = r#"{"test":"data"}"#;
-        
+
         let message = format!("{}.{}", timestamp, body);
         let mut mac = HmacSha256::new_from_slice("secret1".as_bytes()).unwrap();
         mac.update(message.as_bytes());
         let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
-        
-        assert!(!verify_signature("secret2", &timestamp, body, &signature).unwrap());
+
+        assert!(!verify_signature("secret2", &timestamp, body.as_bytes(), &signature).unwrap());
     }
 
     #[test]
@@ -70,12 +295,200 @@ mod tests {
         let secret = "test_secret";
         let timestamp = (chrono::Utc::now().timestamp() - 400).to_string(); // 400 seconds ago
         let body = r#"{"test":"data"}"#;
-        
+
         let message = format!("{}.{}", timestamp, body);
         let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
         mac.update(message.as_bytes());
         let signature = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
-        
-        assert!(!verify_signature(secret, &timestamp, body, &signature).unwrap());
+
+        assert!(!verify_signature(secret, &timestamp, body.as_bytes(), &signature).unwrap());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_verify_webhook_default_missing_header() {
+        let err = verify_webhook(SignatureScheme::Default, "secret", &HeaderMap::new(), b"{}")
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Missing X-Signature header");
+    }
+
+    #[test]
+    fn test_verify_webhook_keys_accepts_any_active_key() {
+        let body = r#"{"x":1}"#;
+        let ts = chrono::Utc::now().timestamp().to_string();
+        let sign = |secret: &str| {
+            format!(
+                "sha256={}",
+                hex::encode(hmac(secret.as_bytes(), &format!("{}.{}", ts, body)))
+            )
+        };
+        let key = |secret: &str, not_after: Option<DateTime<Utc>>| VerificationKey {
+            secret: secret.to_string(),
+            not_after,
+        };
+        let hour = chrono::Duration::hours(1);
+        let keys = vec![
+            key("new", None),
+            key("old", Some(Utc::now() + hour)),
+            key("expired", Some(Utc::now() - hour)),
+        ];
+
+        for (secret, expected) in [("new", true), ("old", true), ("expired", false)] {
+            let request = headers(&[("x-timestamp", ts.clone()), ("x-signature", sign(secret))]);
+            assert_eq!(
+                verify_webhook_keys(SignatureScheme::Default, &keys, &request, body.as_bytes())
+                    .unwrap(),
+                expected,
+                "{}",
+                secret
+            );
+        }
+
+        let request = headers(&[
+            ("x-timestamp", ts.clone()),
+            ("x-signature", sign("expired")),
+        ]);
+        let err = verify_webhook_keys(
+            SignatureScheme::Default,
+            &keys[2..],
+            &request,
+            body.as_bytes(),
+        )
+        .unwrap_err();
+        assert_eq!(err.to_string(), "No active signature key");
+    }
+
+    #[test]
+    fn test_verify_webhook_stripe() {
+        let secret = "whsec_stripe";
+        let body = r#"{"type":"charge.succeeded"}"#;
+        let ts = chrono::Utc::now().timestamp();
+        let signature = hex::encode(hmac(secret.as_bytes(), &format!("{}.{}", ts, body)));
+
+        // Stripe sends one v1 entry per active secret
+        let header_value = format!("t={},v1={},v1={}", ts, "00".repeat(32), signature);
+        let request = headers(&[("stripe-signature", header_value)]);
+        assert!(
+            verify_webhook(SignatureScheme::Stripe, secret, &request, body.as_bytes()).unwrap()
+        );
+        assert!(
+            !verify_webhook(SignatureScheme::Stripe, "other", &request, body.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_webhook_github() {
+        let secret = "gh_secret";
+        let body = r#"{"action":"opened"}"#;
+        let signature = format!("sha256={}", hex::encode(hmac(secret.as_bytes(), body)));
+
+        let request = headers(&[("x-hub-signature-256", signature)]);
+        assert!(
+            verify_webhook(SignatureScheme::Github, secret, &request, body.as_bytes()).unwrap()
+        );
+        assert!(!verify_webhook(SignatureScheme::Github, secret, &request, b"{}").unwrap());
+    }
+
+    #[test]
+    fn test_verify_webhook_zoom_and_slack() {
+        let secret = "v0_secret";
+        let body = r#"{"event":"meeting.started"}"#;
+        let ts = chrono::Utc::now().timestamp().to_string();
+        let signature = format!(
+            "v0={}",
+            hex::encode(hmac(secret.as_bytes(), &format!("v0:{}:{}", ts, body)))
+        );
+
+        let zoom = headers(&[
+            ("x-zm-request-timestamp", ts.clone()),
+            ("x-zm-signature", signature.clone()),
+        ]);
+        assert!(verify_webhook(SignatureScheme::Zoom, secret, &zoom, body.as_bytes()).unwrap());
+
+        let slack = headers(&[
+            ("x-slack-request-timestamp", ts),
+            ("x-slack-signature", signature),
+        ]);
+        assert!(verify_webhook(SignatureScheme::Slack, secret, &slack, body.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn test_verify_webhook_shopify() {
+        let secret = "shpss_secret";
+        let body = r#"{"id":820982911946154508}"#;
+        let signature = BASE64.encode(hmac(secret.as_bytes(), body));
+
+        let request = headers(&[("x-shopify-hmac-sha256", signature)]);
+        assert!(
+            verify_webhook(SignatureScheme::Shopify, secret, &request, body.as_bytes()).unwrap()
+        );
+        assert!(
+            !verify_webhook(SignatureScheme::Shopify, "other", &request, body.as_bytes()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_verify_webhook_standard_webhooks() {
+        let key = b"standard-webhooks-key";
+        let secret = format!("whsec_{}", BASE64.encode(key));
+        let body = r#"{"type":"user.created"}"#;
+        let ts = chrono::Utc::now().timestamp().to_string();
+        let signature = BASE64.encode(hmac(key, &format!("msg_1.{}.{}", ts, body)));
+
+        let request = headers(&[
+            ("webhook-id", "msg_1".to_string()),
+            ("webhook-timestamp", ts.clone()),
+            ("webhook-signature", format!("v1,bm9wZQ== v1,{}", signature)),
+        ]);
+        assert!(verify_webhook(
+            SignatureScheme::StandardWebhooks,
+            &secret,
+            &request,
+            body.as_bytes()
+        )
+        .unwrap());
+
+        // Svix sends the same signature under svix-* headers
+        let svix = headers(&[
+            ("svix-id", "msg_1".to_string()),
+            ("svix-timestamp", ts),
+            ("svix-signature", format!("v1,{}", signature)),
+        ]);
+        assert!(verify_webhook(
+            SignatureScheme::StandardWebhooks,
+            &secret,
+            &svix,
+            body.as_bytes()
+        )
+        .unwrap());
+
+        // A key that is not base64 does not hide the keys after it
+        let broken = VerificationKey {
+            secret: "whsec_not base64!".to_string(),
+            not_after: None,
+        };
+        let valid = VerificationKey {
+            secret: secret.clone(),
+            not_after: None,
+        };
+        let scheme = SignatureScheme::StandardWebhooks;
+        assert!(
+            verify_webhook_keys(scheme, &[broken.clone(), valid], &request, body.as_bytes())
+                .unwrap()
+        );
+        let err = verify_webhook_keys(
+            scheme,
+            std::slice::from_ref(&broken),
+            &request,
+            body.as_bytes(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Standard Webhooks secret is not valid base64"
+        );
+
+        assert!(check_secret(scheme, &secret).is_ok());
+        assert!(check_secret(scheme, &broken.secret).is_err());
+        assert!(check_secret(SignatureScheme::Default, &broken.secret).is_ok());
+    }
+}
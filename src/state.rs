@@ -3,13 +3,19 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 use uuid::Uuid;
 
+use crate::crd::{ChallengeMode, PartialFailurePolicy, RoutingMode, SignatureScheme};
+use crate::filter::Plan;
 use crate::kafka::KafkaProducer;
-use crate::crd::{Filter, Route};
+use crate::output::Output;
+use crate::serializer::SchemaRegistry;
+use crate::signature::VerificationKey;
 
 #[derive(Clone)]
 pub struct AppState {
     pub handlers: Arc<RwLock<HashMap<Uuid, HandlerConfig>>>,
     pub kafka_producer: Arc<KafkaProducer>,
+    /// Set when `SCHEMA_REGISTRY_URL` is configured
+    pub schema_registry: Option<Arc<SchemaRegistry>>,
     pub api_signing_key: String,
     pub external_url: String,
     pub namespace: String,
@@ -18,7 +24,17 @@ pub struct AppState {
 #[derive(Clone, Debug)]
 pub struct HandlerConfig {
     pub topic: String,
-    pub signature_key: Option<String>,
-    pub filters: Option<Vec<Filter>>,
-    pub routes: Option<Vec<Route>>,
-}
\ No newline at end of file
+    /// Signing keys, newest first; empty when verification is disabled
+    pub signature_keys: Vec<VerificationKey>,
+    pub signature_scheme: SignatureScheme,
+    pub challenge_mode: Option<ChallengeMode>,
+    pub verify_token: Option<String>,
+    /// Filters and routes, compiled when the handler was loaded
+    pub plan: Arc<Plan>,
+    pub dead_letter_topic: Option<String>,
+    pub routing_mode: RoutingMode,
+    pub include_default_topic: bool,
+    pub partial_failure: PartialFailurePolicy,
+    /// Payload schema, transform and record format, compiled when the handler was loaded
+    pub output: Arc<Output>,
+}
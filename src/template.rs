@@ -0,0 +1,241 @@
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::filter::{parse_request_path, RequestPath, WebhookEvent};
+
+/// A string with `{path}` placeholders filled from the request, e.g.
+/// `zoom.{$.payload.account_id}.events`. Placeholders take the same paths as
+/// filters, including `$request.headers[...]` and `$request.query...`;
+/// `{{` and `}}` stand for literal braces.
+#[derive(Debug)]
+pub struct Template {
+    source: String,
+    parts: Vec<Part>,
+}
+
+#[derive(Debug)]
+enum Part {
+    Literal(String),
+    Placeholder { source: String, path: RequestPath },
+}
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Template> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = source;
+
+        while let Some(start) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..start]);
+            rest = &rest[start..];
+            // `{{` and `}}` are literal braces
+            if let Some(after) = rest.strip_prefix("{{").or_else(|| rest.strip_prefix("}}")) {
+                literal.push_str(&rest[..1]);
+                rest = after;
+                continue;
+            }
+            if rest.starts_with('}') {
+                return Err(anyhow!("Unmatched '}}' in template {:?}", source));
+            }
+
+            let end = rest
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed placeholder in template {:?}", source))?;
+            let path = rest[1..end].trim();
+            if path.is_empty() {
+                return Err(anyhow!("Empty placeholder in template {:?}", source));
+            }
+            if !literal.is_empty() {
+                parts.push(Part::Literal(std::mem::take(&mut literal)));
+            }
+            parts.push(Part::Placeholder {
+                source: path.to_string(),
+                path: parse_request_path(path)?,
+            });
+            rest = &rest[end + 1..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+
+        Ok(Template {
+            source: source.to_string(),
+            parts,
+        })
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// The fixed text between placeholders
+    pub fn literals(&self) -> impl Iterator<Item = &str> {
+        self.parts.iter().filter_map(|part| match part {
+            Part::Literal(literal) => Some(literal.as_str()),
+            Part::Placeholder { .. } => None,
+        })
+    }
+
+    /// Fills in the placeholders, passing each value through `escape`.
+    /// A placeholder uses the first value its path selects; if that is not a
+    /// string, number or bool, the placeholder's path is returned as missing.
+    pub fn render(
+        &self,
+        event: &WebhookEvent,
+        escape: impl Fn(&str) -> String,
+    ) -> Result<String, &str> {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => rendered.push_str(literal),
+                Part::Placeholder { source, path } => {
+                    let value = match path.query(event).first() {
+                        Some(Value::String(s)) => escape(s),
+                        Some(Value::Number(n)) => escape(&n.to_string()),
+                        Some(Value::Bool(b)) => escape(&b.to_string()),
+                        _ => return Err(source),
+                    };
+                    rendered.push_str(&value);
+                }
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use serde_json::json;
+
+    #[test]
+    fn test_template_render() {
+        let body = json!({ "payload": { "account_id": "acc 1", "count": 3 } });
+        let headers = json!({ "x-tenant": "t1" });
+        let empty = json!({});
+        let event = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &empty,
+            method: &empty,
+            received_at: Utc::now(),
+        };
+
+        let template = Template::parse(
+            "zoom.{$.payload.account_id}.{ $request.headers['x-tenant'] }-{$.payload.count}",
+        )
+        .unwrap();
+        assert_eq!(
+            template.render(&event, str::to_string),
+            Ok("zoom.acc 1.t1-3".to_string())
+        );
+        assert_eq!(
+            template.render(&event, |s| s.replace(' ', "_")),
+            Ok("zoom.acc_1.t1-3".to_string())
+        );
+        assert_eq!(
+            template.literals().collect::<Vec<_>>(),
+            vec!["zoom.", ".", "-"]
+        );
+
+        let missing = Template::parse("zoom.{$.payload.region}").unwrap();
+        assert_eq!(
+            missing.render(&event, str::to_string),
+            Err("$.payload.region")
+        );
+        let object = Template::parse("{$.payload}").unwrap();
+        assert_eq!(object.render(&event, str::to_string), Err("$.payload"));
+
+        assert!(Template::parse("zoom.{$.a").is_err());
+        assert!(Template::parse("zoom.{}").is_err());
+        assert!(Template::parse("zoom.}").is_err());
+        assert!(Template::parse("zoom.{$[}").is_err());
+    }
+
+    #[test]
+    fn test_template_edge_cases() {
+        let body = json!({
+            "count": 3,
+            "ratio": -0.5,
+            "active": false,
+            "tags": ["a", "b"],
+            "nested": { "id": "n-1" },
+            "nothing": null,
+            "account": "Acme Corp/EU+1",
+            "region": "eu-west.1_a"
+        });
+        let headers = json!({});
+        let query = json!({ "tenant": "t 1" });
+        let empty = json!({});
+        let event = WebhookEvent {
+            body: &body,
+            headers: &headers,
+            query: &query,
+            method: &empty,
+            received_at: Utc::now(),
+        };
+        let render = |source: &str| {
+            Template::parse(source)
+                .unwrap()
+                .render(&event, str::to_string)
+                .map_err(str::to_string)
+        };
+
+        // Missing values and nulls report the first placeholder that has none
+        assert_eq!(
+            render("{$.count}.{$.missing}.{$.nothing}"),
+            Err("$.missing".to_string())
+        );
+        assert_eq!(render("{$.nothing}"), Err("$.nothing".to_string()));
+        assert_eq!(
+            render("{$request.headers['x-tenant']}"),
+            Err("$request.headers['x-tenant']".to_string())
+        );
+        assert_eq!(render("{$request.query.tenant}"), Ok("t 1".to_string()));
+
+        // Numbers and bools are rendered as JSON; arrays and objects are missing
+        // unless the path selects inside them, where the first value is used
+        assert_eq!(
+            render("{$.count}/{$.ratio}/{$.active}"),
+            Ok("3/-0.5/false".to_string())
+        );
+        assert_eq!(render("{$.tags}"), Err("$.tags".to_string()));
+        assert_eq!(render("{$.nested}"), Err("$.nested".to_string()));
+        assert_eq!(
+            render("{$.tags[*]}-{$.tags[-1]}-{$.nested.id}"),
+            Ok("a-b-n-1".to_string())
+        );
+
+        // Doubled braces are literal
+        let escaped = Template::parse("{{id}}:{$.count}:}}{{").unwrap();
+        assert_eq!(
+            escaped.render(&event, str::to_string),
+            Ok("{id}:3:}{".to_string())
+        );
+        assert_eq!(escaped.literals().collect::<Vec<_>>(), vec!["{id}:", ":}{"]);
+        assert_eq!(render("{{{$.count}}}"), Ok("{3}".to_string()));
+        assert!(Template::parse("a}b").is_err());
+        assert!(Template::parse("{{$.count}").is_err());
+
+        // Topic templates replace characters not allowed in topic names with `_`,
+        // leaving letters, digits, `.`, `_` and `-`
+        let topic = Template::parse("zoom.{$.account}.{$.region}.{$request.query.tenant}").unwrap();
+        assert_eq!(
+            topic.render(&event, crate::filter::sanitize_topic),
+            Ok("zoom.Acme_Corp_EU_1.eu-west.1_a.t_1".to_string())
+        );
+        // One `_` per character, not per byte
+        let unicode = json!({ "account": "café ü" });
+        let event = WebhookEvent {
+            body: &unicode,
+            ..event
+        };
+        let account = Template::parse("{$.account}").unwrap();
+        assert_eq!(
+            account.render(&event, crate::filter::sanitize_topic),
+            Ok("caf___".to_string())
+        );
+    }
+}
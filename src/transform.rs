@@ -0,0 +1,382 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::{Map, Value};
+use serde_json_path::{JsonPath, PathElement};
+use std::collections::HashMap;
+
+use crate::crd::TransformConfig;
+
+/// A handler's `transform`, compiled when it is loaded. The stages run in
+/// order: `fields`, `drop`, `patch`, then `flatten`.
+#[derive(Debug)]
+pub struct Transform {
+    fields: Vec<JsonPath>,
+    drop: Vec<JsonPath>,
+    patch: Option<json_patch::Patch>,
+    flatten: Option<String>,
+    route_on_output: bool,
+}
+
+/// One step of a normalized path
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+enum Key {
+    Name(String),
+    Index(usize),
+}
+
+/// The parts of a document selected by `fields`
+#[derive(Debug)]
+enum Selection {
+    All,
+    Children(HashMap<Key, Selection>),
+}
+
+impl Transform {
+    pub fn compile(config: &TransformConfig) -> Result<Transform> {
+        let parse = |paths: &Option<Vec<String>>, name: &str| -> Result<Vec<JsonPath>> {
+            paths
+                .iter()
+                .flatten()
+                .map(|path| {
+                    JsonPath::parse(path)
+                        .map_err(|e| anyhow!("Invalid transform {} path {:?}: {}", name, path, e))
+                })
+                .collect()
+        };
+        let patch = config
+            .patch
+            .as_ref()
+            .map(|ops| serde_json::from_value(Value::Array(ops.clone())))
+            .transpose()
+            .context("Invalid transform patch")?;
+        if config.flatten.as_deref() == Some("") {
+            return Err(anyhow!("transform flatten separator must not be empty"));
+        }
+
+        Ok(Transform {
+            fields: parse(&config.fields, "fields")?,
+            drop: parse(&config.drop, "drop")?,
+            patch,
+            flatten: config.flatten.clone(),
+            route_on_output: config.route_on_output,
+        })
+    }
+
+    /// Whether routes match the transformed body rather than the original
+    pub fn route_on_output(&self) -> bool {
+        self.route_on_output
+    }
+
+    /// Reshapes a parsed body. Only a failing `patch` operation is an error.
+    pub fn apply(&self, body: &Value) -> Result<Value> {
+        let mut output = if self.fields.is_empty() {
+            body.clone()
+        } else {
+            let mut selection = Selection::Children(HashMap::new());
+            for path in &self.fields {
+                for location in path.query_located(body).locations() {
+                    selection.insert(&keys(location.iter()));
+                }
+            }
+            selection.project(body)
+        };
+
+        // Every drop path selects from the same document; removing from the
+        // end keeps the earlier array indices valid
+        let mut locations: Vec<Vec<Key>> = Vec::new();
+        for path in &self.drop {
+            locations.extend(
+                path.query_located(&output)
+                    .locations()
+                    .map(|location| keys(location.iter())),
+            );
+        }
+        locations.sort();
+        locations.dedup();
+        for location in locations.iter().rev() {
+            remove(&mut output, location);
+        }
+
+        if let Some(patch) = &self.patch {
+            json_patch::patch(&mut output, patch)
+                .map_err(|e| anyhow!("Transform patch failed: {}", e))?;
+        }
+
+        if let (Some(separator), Value::Object(object)) = (&self.flatten, &output) {
+            output = Value::Object(flatten(object, separator));
+        }
+        Ok(output)
+    }
+}
+
+impl Selection {
+    fn insert(&mut self, keys: &[Key]) {
+        let Selection::Children(children) = self else {
+            return; // Already selected whole
+        };
+        match keys.split_first() {
+            None => *self = Selection::All,
+            Some((key, rest)) => children
+                .entry(key.clone())
+                .or_insert_with(|| Selection::Children(HashMap::new()))
+                .insert(rest),
+        }
+    }
+
+    /// The selected parts of a value; arrays keep the order of their selected elements
+    fn project(&self, value: &Value) -> Value {
+        let Selection::Children(children) = self else {
+            return value.clone();
+        };
+        match value {
+            Value::Object(object) => Value::Object(
+                object
+                    .iter()
+                    .filter_map(|(name, value)| {
+                        let selection = children.get(&Key::Name(name.clone()))?;
+                        Some((name.clone(), selection.project(value)))
+                    })
+                    .collect(),
+            ),
+            Value::Array(items) => Value::Array(
+                items
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(index, value)| {
+                        Some(children.get(&Key::Index(index))?.project(value))
+                    })
+                    .collect(),
+            ),
+            value => value.clone(),
+        }
+    }
+}
+
+fn keys<'a, 'b: 'a>(elements: impl Iterator<Item = &'a PathElement<'b>>) -> Vec<Key> {
+    elements
+        .map(|element| match element {
+            PathElement::Name(name) => Key::Name(name.to_string()),
+            PathElement::Index(index) => Key::Index(*index),
+        })
+        .collect()
+}
+
+fn remove(value: &mut Value, keys: &[Key]) {
+    let Some((last, parents)) = keys.split_last() else {
+        return; // `$` itself is never dropped
+    };
+    let mut parent = value;
+    for key in parents {
+        let child = match (parent, key) {
+            (Value::Object(object), Key::Name(name)) => object.get_mut(name),
+            (Value::Array(items), Key::Index(index)) => items.get_mut(*index),
+            _ => None,
+        };
+        match child {
+            Some(child) => parent = child,
+            None => return,
+        }
+    }
+    match (parent, last) {
+        (Value::Object(object), Key::Name(name)) => {
+            object.remove(name);
+        }
+        (Value::Array(items), Key::Index(index)) if *index < items.len() => {
+            items.remove(*index);
+        }
+        _ => {}
+    }
+}
+
+/// Nested objects become keys joined by the separator; arrays are kept as
+/// values. When flattened keys collide, the value nested least deeply wins
+/// (a literal `"a.b"` key over `a` → `b`), then the first in key order.
+fn flatten(object: &Map<String, Value>, separator: &str) -> Map<String, Value> {
+    let mut entries = Vec::new();
+    flatten_into(&mut entries, None, 0, object, separator);
+    entries.sort_by_key(|(depth, _, _)| *depth);
+
+    let mut flat = Map::new();
+    for (_, key, value) in entries {
+        flat.entry(key).or_insert_with(|| value.clone());
+    }
+    flat
+}
+
+fn flatten_into<'a>(
+    entries: &mut Vec<(usize, String, &'a Value)>,
+    prefix: Option<&str>,
+    depth: usize,
+    object: &'a Map<String, Value>,
+    separator: &str,
+) {
+    for (name, value) in object {
+        let key = match prefix {
+            Some(prefix) => format!("{}{}{}", prefix, separator, name),
+            None => name.clone(),
+        };
+        match value {
+            Value::Object(nested) if !nested.is_empty() => {
+                flatten_into(entries, Some(&key), depth + 1, nested, separator)
+            }
+            value => entries.push((depth, key, value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn transform(config: Value) -> Transform {
+        Transform::compile(&serde_json::from_value(config).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_transform() {
+        let body = json!({
+            "event": "meeting.ended",
+            "payload": {
+                "account_id": "abc",
+                "object": {
+                    "id": 42,
+                    "participants": [{ "name": "a", "email": "a@x" }, { "name": "b", "email": "b@x" }],
+                    "recording": { "blob": "..." }
+                }
+            }
+        });
+
+        let projected = transform(
+            json!({ "fields": ["$.event", "$.payload.object.participants[*].name", "$.payload.object.id"] }),
+        );
+        assert_eq!(
+            projected.apply(&body).unwrap(),
+            json!({
+                "event": "meeting.ended",
+                "payload": { "object": { "id": 42, "participants": [{ "name": "a" }, { "name": "b" }] } }
+            })
+        );
+
+        let reshaped = transform(json!({
+            "drop": ["$.payload.object.recording", "$..email", "$.missing"],
+            "patch": [
+                { "op": "move", "from": "/payload/account_id", "path": "/account" },
+                { "op": "add", "path": "/tenant", "value": "acme" }
+            ],
+            "flatten": ".",
+            "routeOnOutput": true
+        }));
+        assert!(reshaped.route_on_output());
+        assert_eq!(
+            reshaped.apply(&body).unwrap(),
+            json!({
+                "event": "meeting.ended",
+                "payload.object.id": 42,
+                "payload.object.participants": [{ "name": "a" }, { "name": "b" }],
+                "account": "abc",
+                "tenant": "acme"
+            })
+        );
+
+        // Dropping several array elements keeps the right ones
+        let drop_items = transform(json!({ "drop": ["$.items[?@ > 1]"] }));
+        assert_eq!(
+            drop_items.apply(&json!({ "items": [1, 2, 3, 1] })).unwrap(),
+            json!({ "items": [1, 1] })
+        );
+
+        // Patch operations follow RFC 6902, so removing a missing path fails
+        let strict = transform(json!({ "patch": [{ "op": "remove", "path": "/missing" }] }));
+        assert_eq!(
+            strict.apply(&body).unwrap_err().to_string(),
+            "Transform patch failed: operation '/0' failed at path '/missing': path is invalid"
+        );
+
+        // Indexed drops all refer to the original elements, even when
+        // several paths select the same one
+        let drop_indexes =
+            transform(json!({ "drop": ["$.items[0]", "$.items[2]", "$.items[-1]", "$.items[4]"] }));
+        assert_eq!(
+            drop_indexes
+                .apply(&json!({ "items": ["a", "b", "c", "d", "e"] }))
+                .unwrap(),
+            json!({ "items": ["b", "d"] })
+        );
+
+        // Fields are selected first, then drops remove from the selection
+        let selected = transform(json!({
+            "fields": ["$.payload.object"],
+            "drop": ["$.payload.object.recording", "$.payload.object.participants[0]"]
+        }));
+        assert_eq!(
+            selected.apply(&body).unwrap(),
+            json!({ "payload": { "object": { "id": 42, "participants": [{ "name": "b", "email": "b@x" }] } } })
+        );
+
+        // Any separator joins flattened keys; empty objects and arrays are kept as values
+        let flattened = transform(json!({ "flatten": "__" }));
+        assert_eq!(
+            flattened
+                .apply(&json!({ "a": { "b": { "c": 1 }, "d": {} }, "e": [{ "f": 2 }] }))
+                .unwrap(),
+            json!({ "a__b__c": 1, "a__d": {}, "e": [{ "f": 2 }] })
+        );
+        assert!(!flattened.route_on_output());
+
+        let invalid =
+            |config: Value| Transform::compile(&serde_json::from_value(config).unwrap()).is_err();
+        assert!(invalid(json!({ "fields": ["$["] })));
+        assert!(invalid(
+            json!({ "patch": [{ "op": "rename", "path": "/a" }] })
+        ));
+        assert!(invalid(json!({ "flatten": "" })));
+    }
+
+    #[test]
+    fn test_transform_edge_cases() {
+        let body = json!({ "event": "meeting.ended", "payload": { "id": 42, "secret": "s" } });
+
+        // A failing `test` operation rejects the body
+        let guarded = transform(json!({ "patch": [
+            { "op": "add", "path": "/tenant", "value": "acme" },
+            { "op": "test", "path": "/event", "value": "meeting.started" }
+        ] }));
+        assert_eq!(
+            guarded.apply(&body).unwrap_err().to_string(),
+            "Transform patch failed: operation '/1' failed at path '/event': value did not match"
+        );
+
+        // Drops apply after fields, so a dropped path is removed even when selected
+        let overlapping = transform(json!({
+            "fields": ["$.event", "$.payload.secret", "$.payload.id"],
+            "drop": ["$.payload.secret", "$.event"]
+        }));
+        assert_eq!(
+            overlapping.apply(&body).unwrap(),
+            json!({ "payload": { "id": 42 } })
+        );
+        let dropped_parent =
+            transform(json!({ "fields": ["$.payload.id"], "drop": ["$.payload"] }));
+        assert_eq!(dropped_parent.apply(&body).unwrap(), json!({}));
+
+        // A transform may leave nothing of the body; the empty object is published
+        for config in [
+            json!({ "fields": ["$.missing"] }),
+            json!({ "drop": ["$.*"] }),
+            json!({ "patch": [{ "op": "remove", "path": "/event" }, { "op": "remove", "path": "/payload" }] }),
+            json!({ "drop": ["$.*"], "flatten": "." }),
+        ] {
+            assert_eq!(transform(config).apply(&body).unwrap(), json!({}));
+        }
+
+        // Colliding flattened keys keep the value nested least deeply, then
+        // the first in key order
+        let flattened = transform(json!({ "flatten": "." }));
+        assert_eq!(
+            flattened
+                .apply(&json!({ "a": { "b": 1 }, "a.b": 2, "c": { "d.e": 3, "d": { "e": 4 } }, "f.g": { "h": 5 }, "f": { "g.h": 6 } }))
+                .unwrap(),
+            json!({ "a.b": 2, "c.d.e": 3, "f.g.h": 6 })
+        );
+    }
+}